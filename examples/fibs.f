@@ -0,0 +1,32 @@
+extern getchar -> i;
+extern main;
+
+pow2 i->i: 1 swap << ;
+
+powersof2 :
+    1 @ dup 30 < :
+        dup pow2 iprintln
+        inc
+    ;
+    @ dup 1 > :
+        dup pow2 iprintln
+        dec
+    ; drop
+;
+
+fib i->i :
+    dup 1 <= ? :
+        dup 1 - fib swap 2 - fib +
+    ;
+;
+
+fibs:
+    0 @ dup 20 <= :
+        dup iprint
+        32 putchar
+        dup fib iprintln
+        inc
+    ; drop
+;
+
+main : fibs ;
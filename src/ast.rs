@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 // Types:
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConcreteType {
@@ -49,11 +51,111 @@ concrete_type_properties! {
     ConcreteType::Bool => false, true, 1;
 }
 
+/// A set of concrete types which a generic can be constrained to, eg. `num` in `'T:num`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeClass {
+    // Any integer or float type
+    Num,
+    // Any integer type
+    Int,
+    // Any record type
+    Record,
+}
+
+impl TypeClass {
+    pub fn members(&self) -> &'static [ConcreteType] {
+        use ConcreteType::*;
+        match self {
+            TypeClass::Num => &[I32, U32, I64, U64, I8, U8, F32, F64],
+            TypeClass::Int => &[I32, U32, I64, U64, I8, U8],
+            // Records are not concrete types, see `Type::Record`
+            TypeClass::Record => &[],
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
     Concrete(ConcreteType),
-    Generic(String),
+    // A generic may be constrained to a type class. Each use of the generic may repeat its
+    // constraint, eg. `'T:num 'T:num -> 'T`.
+    Generic(String, Option<TypeClass>),
     Pointer(Box<Type>),
+    // A record type, named by its `record` definition
+    Record(String),
+    // The type of an integer literal without a suffix, which is inferred from how the literal is
+    // used. This only exists during typechecking, after which it is replaced by a concrete type.
+    UntypedInt(usize),
+}
+
+impl Type {
+    pub fn has_generic(&self) -> bool {
+        match self {
+            Type::Generic(..) => true,
+            Type::Pointer(inner) => inner.has_generic(),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for ConcreteType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // These match the type names accepted by the parser
+        let name = match self {
+            ConcreteType::I32 => "i",
+            ConcreteType::U32 => "ui",
+            ConcreteType::F32 => "f",
+            ConcreteType::F64 => "d",
+            ConcreteType::I64 => "q",
+            ConcreteType::U64 => "uq",
+            ConcreteType::I8 => "c",
+            ConcreteType::U8 => "uc",
+            ConcreteType::Bool => "b",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for TypeClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // These match the class names accepted by the parser
+        match self {
+            TypeClass::Num => write!(f, "num"),
+            TypeClass::Int => write!(f, "int"),
+            TypeClass::Record => write!(f, "record"),
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Concrete(concrete) => write!(f, "{}", concrete),
+            Type::Generic(name, None) => write!(f, "'{}", name),
+            Type::Generic(name, Some(class)) => write!(f, "'{}:{}", name, class),
+            Type::Pointer(inner) => write!(f, "*{}", inner),
+            Type::Record(name) => write!(f, "{}", name),
+            Type::UntypedInt(_) => write!(f, "{{integer}}"),
+        }
+    }
+}
+
+/// A value computed at compile time, see `Const`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    // Integers are held sign or zero extended to 64 bits, depending on whether their type is signed
+    Int(i64, ConcreteType),
+    Float(f64, ConcreteType),
+    Bool(bool),
+}
+
+impl ConstValue {
+    pub fn typ(&self) -> ConcreteType {
+        match self {
+            ConstValue::Int(_, typ) | ConstValue::Float(_, typ) => typ.clone(),
+            ConstValue::Bool(_) => ConcreteType::Bool,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -62,65 +164,241 @@ pub struct FunctionType {
     pub outputs: Vec<Type>,
 }
 
+impl FunctionType {
+    pub fn is_generic(&self) -> bool {
+        self.inputs
+            .iter()
+            .chain(&self.outputs)
+            .any(Type::has_generic)
+    }
+}
+
+/// The types of everything defined in a program, collected by the typechecker
+#[derive(Debug, Default, Clone)]
+pub struct ProgramTypes {
+    pub functions: HashMap<String, FunctionType>,
+    // The fields of each record, in the order they are laid out in memory
+    pub records: HashMap<String, Vec<RecordField>>,
+}
+
+// Source locations:
+
+/// Identifies the source file a Span points into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(pub usize);
+
+/// Identifies one use of a macro, see `SourceMap::expansion`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ExpansionId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: FileId,
+    // Byte offset of the start of the span from the start of the file
+    pub offset: usize,
+    // Length of the span in bytes
+    pub len: usize,
+    // 1-based line and column of the start of the span. Column is counted in chars, not bytes.
+    pub line: u32,
+    pub column: usize,
+    // Set on words which were spliced into a body by expanding a macro. The span itself points
+    // into the macro's body, and the expansion records where the macro was used.
+    pub expansion: Option<ExpansionId>,
+}
+
+impl Span {
+    /// Creates a span from the start of self to the end of other
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            len: other.offset + other.len - self.offset,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 // AST:
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IfStatement {
     pub true_branch: CodeBlock,
     pub false_branch: CodeBlock,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WhileStatement {
     pub condition: CodeBlock,
     pub body: CodeBlock,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+/// An integer literal, eg. `12`, `0xFF`, `10q` or `'a'`
+#[derive(Debug, Clone)]
+pub struct IntLiteral {
+    // This is wide enough to hold every value of every integer type. The typechecker checks that
+    // it is in the range of the literal's type.
+    pub value: i128,
+    // The type given by the literal's suffix, eg. `q` in `10q`. Without one, the type is inferred
+    // from how the literal is used, and is `i` if nothing decides it.
+    pub suffix: Option<ConcreteType>,
+    // This is None until typechecking, which sets it to the literal's concrete type
+    pub typ: Option<Type>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
 pub struct FunctionCall {
     pub name: String,
+    // Types given explicitly for the function's generics, eg. `i` in `alloca<i>`. They are given
+    // in the order each generic first appears in the function's signature.
+    pub type_args: Vec<Type>,
+    pub span: Span,
     // This is set to None until typechecking. The typechecker annotates each function with
     // possible generic arguments with the reified types those generics were converted to. This is
     // used in codegen so that it knows what types are actually produced by the function.
     pub reified_type: Option<FunctionType>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Word {
-    I32Literal(i32),
-    F32Literal(f32),
-    BoolLiteral(bool),
+    IntLiteral(IntLiteral),
+    // A float literal, which is an `f` unless it has a type suffix, eg. `1.5d`
+    FloatLiteral(f64, ConcreteType, Span),
+    BoolLiteral(bool, Span),
+    // The bytes of a `"..."` literal after escapes are applied, without a nul terminator
+    StringLiteral(Vec<u8>, Span),
+    // A conversion of the value on top of the stack to a type, eg. `(i)` or `(*uc)`
+    Cast(Type, Span),
     FunctionCall(FunctionCall),
     IfStatement(IfStatement),
     WhileStatement(WhileStatement),
 }
 
-#[derive(Debug)]
-pub struct CodeBlock(pub Vec<Word>);
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    pub words: Vec<Word>,
+    pub span: Span,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionHeader {
     pub name: String,
     pub typ: FunctionType,
+    // Span of the function's name
+    pub name_span: Span,
+    // Span of the whole header (name and type signature)
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct FunctionDecl {
     pub head: FunctionHeader,
+    pub is_pub: bool,
     pub is_intrinsic: bool,
     pub is_extern: bool,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionImpl {
     pub head: FunctionHeader,
+    // Public functions can be used by other modules which import this one. A function is public
+    // if either its declaration or implementation is marked `pub`.
+    pub is_pub: bool,
+    // `inline name : ... ;`. Calls to an inline function are replaced by its body during codegen.
+    // Unlike a macro, it is still typechecked against its signature.
+    pub is_inline: bool,
     pub body: CodeBlock,
+    pub span: Span,
+}
+
+/// `let name <words> ;` or `let name : <words> ;`. The words are evaluated at compile time and
+/// must leave exactly one value, which is pushed wherever the constant's name is used.
+#[derive(Debug)]
+pub struct Const {
+    pub name: String,
+    pub is_pub: bool,
+    pub body: CodeBlock,
+    // This is None until typechecking, which evaluates the body
+    pub value: Option<ConstValue>,
+    pub name_span: Span,
+    pub span: Span,
+}
+
+/// `macro name : <words> ;`. Each use of the macro's name is replaced by its words before
+/// typechecking, so it has no type signature of its own.
+#[derive(Debug)]
+pub struct Macro {
+    pub name: String,
+    pub is_pub: bool,
+    pub body: CodeBlock,
+    pub name_span: Span,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordField {
+    pub name: String,
+    pub typ: Type,
+    pub span: Span,
+}
+
+/// `record name : field type , ... ;`. Each field gets a getter `.name.field`, which leaves the
+/// record on the stack under the field's value, and a setter `>name.field`, which replaces the
+/// field's value. Like functions, records are named by their module, and can only be used by
+/// other modules if they are `pub`.
+#[derive(Debug)]
+pub struct Record {
+    pub name: String,
+    pub fields: Vec<RecordField>,
+    // Whether the getters and setters can be used by other modules which import this one
+    pub is_pub: bool,
+    pub name_span: Span,
+    pub span: Span,
+}
+
+impl Record {
+    pub fn getter_name(&self, field: &RecordField) -> String {
+        self.field_function_name('.', field)
+    }
+
+    pub fn setter_name(&self, field: &RecordField) -> String {
+        self.field_function_name('>', field)
+    }
+
+    // Once the record has been renamed by the resolver, its getters and setters are qualified by
+    // the same module, eg. `shapes::.point.x` for the record `shapes::point`
+    fn field_function_name(&self, prefix: char, field: &RecordField) -> String {
+        match self.name.rsplit_once("::") {
+            Some((module, name)) => format!("{}::{}{}.{}", module, prefix, name, field.name),
+            None => format!("{}{}.{}", prefix, self.name, field.name),
+        }
+    }
+}
+
+/// `import "file.f";` or `use file;`. The imported file's items are added to the module by the
+/// loader, before those of the importing file.
+#[derive(Debug)]
+pub struct Import {
+    // Path of the imported file, relative to the importing file or a directory in the search path
+    pub path: String,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub enum TopLevelItem {
     Decl(FunctionDecl),
     Impl(FunctionImpl),
+    Const(Const),
+    Macro(Macro),
+    Record(Record),
+    Import(Import),
 }
 
 pub mod visitor {
@@ -135,12 +413,20 @@ pub mod visitor {
 
         fn visit_decl(&mut self, function: &mut FunctionDecl) -> Self::ItemResult;
         fn visit_impl(&mut self, function: &mut FunctionImpl) -> Self::ItemResult;
+        fn visit_const(&mut self, constant: &mut Const) -> Self::ItemResult;
+        fn visit_macro(&mut self, macro_: &mut Macro) -> Self::ItemResult;
+        fn visit_record(&mut self, record: &mut Record) -> Self::ItemResult;
+        fn visit_import(&mut self, import: &mut Import) -> Self::ItemResult;
         fn finalize(self) -> Self::FinalOutput;
         fn walk(mut self, module: &mut [TopLevelItem]) -> Self::FinalOutput {
             for top_level_item in module {
                 match top_level_item {
                     TopLevelItem::Decl(f_decl) => self.visit_decl(f_decl),
                     TopLevelItem::Impl(f_impl) => self.visit_impl(f_impl),
+                    TopLevelItem::Const(constant) => self.visit_const(constant),
+                    TopLevelItem::Macro(macro_) => self.visit_macro(macro_),
+                    TopLevelItem::Record(record) => self.visit_record(record),
+                    TopLevelItem::Import(import) => self.visit_import(import),
                 };
             }
             self.finalize()
@@ -161,6 +447,10 @@ pub mod visitor {
                 match top_level_item {
                     TopLevelItem::Decl(f_decl) => self.visit_decl(f_decl)?,
                     TopLevelItem::Impl(f_impl) => self.visit_impl(f_impl)?,
+                    TopLevelItem::Const(constant) => self.visit_const(constant)?,
+                    TopLevelItem::Macro(macro_) => self.visit_macro(macro_)?,
+                    TopLevelItem::Record(record) => self.visit_record(record)?,
+                    TopLevelItem::Import(import) => self.visit_import(import)?,
                 };
             }
             Ok(self.finalize())
@@ -174,9 +464,14 @@ pub mod visitor {
         type ItemResult;
         type FinalOutput;
 
-        fn visit_i32_literal(&mut self, n: i32) -> Self::ItemResult;
-        fn visit_f32_literal(&mut self, n: f32) -> Self::ItemResult;
-        fn visit_bool_literal(&mut self, n: bool) -> Self::ItemResult;
+        // Integer literals are mut so the typechecker can annotate them with their inferred type
+        fn visit_int_literal(&mut self, literal: &mut IntLiteral) -> Self::ItemResult;
+        fn visit_float_literal(&mut self, n: f64, typ: ConcreteType, span: Span)
+            -> Self::ItemResult;
+        fn visit_bool_literal(&mut self, n: bool, span: Span) -> Self::ItemResult;
+        fn visit_string_literal(&mut self, s: &[u8], span: Span) -> Self::ItemResult;
+        // Casts are mut so the resolver can rename the records in them
+        fn visit_cast(&mut self, to: &mut Type, span: Span) -> Self::ItemResult;
 
         // need to pass a mut ref to word here so that the typechecker can annotate the function
         // with it's reified type signature.
@@ -188,11 +483,15 @@ pub mod visitor {
         fn visit_while_statement(&mut self, statement: &mut WhileStatement) -> Self::ItemResult;
         fn finalize(self) -> Self::FinalOutput;
         fn walk(mut self, block: &mut CodeBlock) -> Self::FinalOutput {
-            for word in &mut block.0 {
+            for word in &mut block.words {
                 match word {
-                    Word::I32Literal(n) => self.visit_i32_literal(*n),
-                    Word::F32Literal(n) => self.visit_f32_literal(*n),
-                    Word::BoolLiteral(n) => self.visit_bool_literal(*n),
+                    Word::IntLiteral(literal) => self.visit_int_literal(literal),
+                    Word::FloatLiteral(n, typ, span) => {
+                        self.visit_float_literal(*n, typ.clone(), *span)
+                    }
+                    Word::BoolLiteral(n, span) => self.visit_bool_literal(*n, *span),
+                    Word::StringLiteral(s, span) => self.visit_string_literal(s, *span),
+                    Word::Cast(to, span) => self.visit_cast(to, *span),
                     Word::FunctionCall(function) => self.visit_function(function),
                     Word::IfStatement(if_statement) => self.visit_if_statement(if_statement),
                     Word::WhileStatement(while_statement) => {
@@ -210,11 +509,15 @@ pub mod visitor {
     {
         type ErrorType;
         fn walk_result(mut self, block: &mut CodeBlock) -> Result<Self::FinalOutput, Self::ErrorType> {
-            for word in &mut block.0 {
+            for word in &mut block.words {
                 match word {
-                    Word::I32Literal(n) => self.visit_i32_literal(*n)?,
-                    Word::F32Literal(n) => self.visit_f32_literal(*n)?,
-                    Word::BoolLiteral(n) => self.visit_bool_literal(*n)?,
+                    Word::IntLiteral(literal) => self.visit_int_literal(literal)?,
+                    Word::FloatLiteral(n, typ, span) => {
+                        self.visit_float_literal(*n, typ.clone(), *span)?
+                    }
+                    Word::BoolLiteral(n, span) => self.visit_bool_literal(*n, *span)?,
+                    Word::StringLiteral(s, span) => self.visit_string_literal(s, *span)?,
+                    Word::Cast(to, span) => self.visit_cast(to, *span)?,
                     Word::FunctionCall(function) => self.visit_function(function)?,
                     Word::IfStatement(if_statement) => self.visit_if_statement(if_statement)?,
                     Word::WhileStatement(while_statement) => {
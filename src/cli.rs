@@ -0,0 +1,274 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: myforth [build] [OPTIONS] <FILES>...
+       myforth run [OPTIONS] <FILES>... [-- <ARGS>...]
+       myforth repl [OPTIONS] [FILES]...
+
+Commands:
+  build    Compile the given files (default)
+  run      Compile the given files to an executable and run it
+  repl     Start an interactive session, with the words from the given files defined
+
+Options:
+  -o <PATH>                   Write output to PATH
+  -I <DIR>                    Add DIR to the directories searched for imports
+  --emit=ll|bc|obj|asm|exe    Kind of output to generate [default: exe]
+  -O0, -O1, -O2, -O3          Optimization level [default: -O0]
+  --no-std                    Do not include the standard library (std.f)
+  --dump-ir-before-opt        Print the LLVM IR to stderr before optimizing it
+  --dump-ir-after-opt         Print the LLVM IR to stderr after optimizing it
+  -h, --help                  Print this message
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Build,
+    Run,
+    Repl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitKind {
+    LlvmIr,
+    Bitcode,
+    Object,
+    Assembly,
+    Executable,
+}
+
+impl EmitKind {
+    fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "ll" => Some(EmitKind::LlvmIr),
+            "bc" => Some(EmitKind::Bitcode),
+            "obj" => Some(EmitKind::Object),
+            "asm" => Some(EmitKind::Assembly),
+            "exe" => Some(EmitKind::Executable),
+            _ => None,
+        }
+    }
+
+    /// File extension used when no explicit output path is given
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EmitKind::LlvmIr => "ll",
+            EmitKind::Bitcode => "bc",
+            EmitKind::Object => "o",
+            EmitKind::Assembly => "s",
+            EmitKind::Executable => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+}
+
+impl OptLevel {
+    fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            "-O3" => Some(OptLevel::O3),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    pub inputs: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub emit: EmitKind,
+    pub opt_level: OptLevel,
+    pub include_std: bool,
+    // Searched in order for imports which are not found relative to the importing file
+    pub include_dirs: Vec<PathBuf>,
+    pub dump_ir_before_opt: bool,
+    pub dump_ir_after_opt: bool,
+    // Arguments passed through to the program by `run`
+    pub run_args: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            command: Command::Build,
+            inputs: Vec::new(),
+            output: None,
+            emit: EmitKind::Executable,
+            opt_level: OptLevel::O0,
+            include_std: true,
+            include_dirs: Vec::new(),
+            dump_ir_before_opt: false,
+            dump_ir_after_opt: false,
+            run_args: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseArgsResult {
+    Options(Options),
+    Help,
+}
+
+/// Parse command line arguments (not including the program name)
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<ParseArgsResult, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+
+    match args.peek().map(String::as_str) {
+        Some("build") => {
+            args.next();
+        }
+        Some("run") => {
+            options.command = Command::Run;
+            args.next();
+        }
+        Some("repl") => {
+            options.command = Command::Repl;
+            args.next();
+        }
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(ParseArgsResult::Help),
+            "-o" => {
+                let path = args.next().ok_or("expected a path after `-o`")?;
+                options.output = Some(PathBuf::from(path));
+            }
+            "-I" => {
+                let dir = args.next().ok_or("expected a directory after `-I`")?;
+                options.include_dirs.push(PathBuf::from(dir));
+            }
+            "--no-std" => options.include_std = false,
+            "--dump-ir-before-opt" => options.dump_ir_before_opt = true,
+            "--dump-ir-after-opt" => options.dump_ir_after_opt = true,
+            "--" => {
+                if options.command != Command::Run {
+                    return Err("program arguments after `--` are only accepted by `run`".into());
+                }
+                options.run_args.extend(args.by_ref());
+            }
+            _ => {
+                if let Some(kind) = arg.strip_prefix("--emit=") {
+                    options.emit = EmitKind::from_arg(kind)
+                        .ok_or_else(|| format!("unknown emit kind `{}`", kind))?;
+                } else if let Some(level) = OptLevel::from_arg(&arg) {
+                    options.opt_level = level;
+                } else if arg.starts_with('-') {
+                    return Err(format!("unknown option `{}`", arg));
+                } else {
+                    options.inputs.push(PathBuf::from(arg));
+                }
+            }
+        }
+    }
+
+    if options.inputs.is_empty() && options.command != Command::Repl {
+        return Err("no input files".into());
+    }
+    if options.command == Command::Run && options.emit != EmitKind::Executable {
+        return Err("`run` can only be used with `--emit=exe`".into());
+    }
+
+    Ok(ParseArgsResult::Options(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ParseArgsResult, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    fn parse_options(args: &[&str]) -> Options {
+        match parse(args) {
+            Ok(ParseArgsResult::Options(options)) => options,
+            other => panic!("expected options, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse_options(&["a.f"]);
+        assert_eq!(options.command, Command::Build);
+        assert_eq!(options.inputs, vec![PathBuf::from("a.f")]);
+        assert_eq!(options.emit, EmitKind::Executable);
+        assert_eq!(options.opt_level, OptLevel::O0);
+        assert!(options.include_std);
+    }
+
+    #[test]
+    fn test_build() {
+        let options = parse_options(&[
+            "build",
+            "a.f",
+            "b.f",
+            "-o",
+            "out.ll",
+            "--emit=ll",
+            "-O3",
+            "--dump-ir-after-opt",
+            "-I",
+            "lib",
+        ]);
+        assert_eq!(
+            options.inputs,
+            vec![PathBuf::from("a.f"), PathBuf::from("b.f")]
+        );
+        assert_eq!(options.output, Some(PathBuf::from("out.ll")));
+        assert_eq!(options.emit, EmitKind::LlvmIr);
+        assert_eq!(options.opt_level, OptLevel::O3);
+        assert!(!options.dump_ir_before_opt);
+        assert!(options.dump_ir_after_opt);
+        assert_eq!(options.include_dirs, vec![PathBuf::from("lib")]);
+    }
+
+    #[test]
+    fn test_run() {
+        let options = parse_options(&["run", "--no-std", "a.f", "--", "-x", "y"]);
+        assert_eq!(options.command, Command::Run);
+        assert!(!options.include_std);
+        assert_eq!(options.run_args, vec!["-x".to_string(), "y".to_string()]);
+    }
+
+    #[test]
+    fn test_repl() {
+        let options = parse_options(&["repl"]);
+        assert_eq!(options.command, Command::Repl);
+        assert!(options.inputs.is_empty());
+        assert_eq!(
+            parse_options(&["repl", "a.f"]).inputs,
+            vec![PathBuf::from("a.f")]
+        );
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(parse(&["--help"]), Ok(ParseArgsResult::Help));
+        assert_eq!(parse(&["run", "-h"]), Ok(ParseArgsResult::Help));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["a.f", "-o"]).is_err());
+        assert!(parse(&["a.f", "-I"]).is_err());
+        assert!(parse(&["a.f", "--emit=wasm"]).is_err());
+        assert!(parse(&["a.f", "-O9"]).is_err());
+        assert!(parse(&["run", "a.f", "--emit=ll"]).is_err());
+        assert!(parse(&["a.f", "--", "x"]).is_err());
+    }
+}
@@ -1,12 +1,17 @@
+use std::os::raw::c_char;
+
 use crate::ast::visitor::CodeBlockVisitor;
-use crate::ast::{ConcreteType, FunctionCall, IfStatement, Type, WhileStatement};
+use crate::ast::{
+    CodeBlock, ConcreteType, FunctionCall, IfStatement, IntLiteral, Span, Type, WhileStatement,
+};
 
 use llvm::core::*;
 use llvm::prelude::*;
+use llvm::*;
 use llvm_sys as llvm;
 
-use super::intrinsics::try_append_intrinsic;
-use super::{CompilationStack, CompilationStackValue, Context, ToCStr};
+use super::intrinsics::{build_cast, try_append_intrinsic};
+use super::{reify_block, CompilationStack, CompilationStackValue, Context, ToCStr};
 
 pub(super) struct CodeBlockCodeGen<'a, 'b> {
     context: &'a mut Context<'b>,
@@ -38,6 +43,17 @@ impl<'a, 'b> CodeBlockCodeGen<'a, 'b> {
             final_bb: current_final_bb,
         }
     }
+
+    /// A copy of the body of `name` if calls to it should be inlined. The function is marked as
+    /// being inlined until the caller pops it from `Context::inlining`.
+    fn inline_body(&mut self, name: &str) -> Option<CodeBlock> {
+        if self.context.inlining.iter().any(|inlined| inlined == name) {
+            return None;
+        }
+        let body = self.context.inline_functions.get(name)?.clone();
+        self.context.inlining.push(name.to_string());
+        Some(body)
+    }
 }
 
 // finalize returns (stack, final BasicBlock)
@@ -45,59 +61,114 @@ impl CodeBlockVisitor for CodeBlockCodeGen<'_, '_> {
     type ItemResult = ();
     type FinalOutput = (CompilationStack, LLVMBasicBlockRef);
 
-    fn visit_i32_literal(&mut self, n: i32) {
+    fn visit_int_literal(&mut self, literal: &mut IntLiteral) {
         unsafe {
-            let typ = Type::Concrete(ConcreteType::I32);
+            let typ = literal
+                .typ
+                .clone()
+                .expect("integer literals are annotated by the typechecker");
+            let is_signed = matches!(&typ, Type::Concrete(concrete) if concrete.is_signed());
+            self.stack.push(CompilationStackValue {
+                // The typechecker has checked the value fits in the type, so truncating the low
+                // bits gives the same value in the type's width, whether it is signed or not
+                llvm_value: LLVMConstInt(
+                    self.context.get_llvm_type(&typ),
+                    literal.value as u64,
+                    is_signed as LLVMBool,
+                ),
+                typ,
+            });
+        }
+    }
+
+    fn visit_bool_literal(&mut self, n: bool, _: Span) {
+        unsafe {
+            let typ = Type::Concrete(ConcreteType::Bool);
             self.stack.push(CompilationStackValue {
                 llvm_value: LLVMConstInt(
                     self.context.get_llvm_type(&typ),
-                    // TODO negatives will be broken here:
-                    n as u64,
+                    if n { 1 } else { 0 },
                     false as LLVMBool,
                 ),
                 typ,
-            })
+            });
         }
     }
 
-    fn visit_f32_literal(&mut self, n: f32) {
+    fn visit_float_literal(&mut self, n: f64, typ: ConcreteType, _: Span) {
         unsafe {
-            let typ = Type::Concrete(ConcreteType::F32);
+            let typ = Type::Concrete(typ);
             self.stack.push(CompilationStackValue {
-                llvm_value: LLVMConstReal(self.context.get_llvm_type(&typ), n as f64),
+                llvm_value: LLVMConstReal(self.context.get_llvm_type(&typ), n),
                 typ,
             });
         }
     }
 
-    fn visit_bool_literal(&mut self, n: bool) {
+    // Each literal is stored in its own private global, which is writable so that programs can
+    // use it as a buffer. A nul terminator is added so it can be passed to C functions.
+    fn visit_string_literal(&mut self, s: &[u8], _: Span) {
         unsafe {
-            let typ = Type::Concrete(ConcreteType::Bool);
+            let initializer = LLVMConstStringInContext(
+                self.context.llvm_context,
+                s.as_ptr() as *const c_char,
+                s.len() as u32,
+                false as LLVMBool,
+            );
+            let global = LLVMAddGlobal(
+                self.context.module,
+                LLVMTypeOf(initializer),
+                "string\0".c_str(),
+            );
+            LLVMSetInitializer(global, initializer);
+            LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+
+            let typ = Type::Pointer(Box::new(Type::Concrete(ConcreteType::I8)));
             self.stack.push(CompilationStackValue {
-                llvm_value: LLVMConstInt(
-                    self.context.get_llvm_type(&typ),
-                    if n { 1 } else { 0 },
-                    false as LLVMBool,
-                ),
+                llvm_value: LLVMConstBitCast(global, self.context.get_llvm_type(&typ)),
                 typ,
             });
         }
     }
 
+    fn visit_cast(&mut self, to: &mut Type, _: Span) {
+        unsafe { build_cast(self.context, &mut self.stack, to.clone()) }
+    }
+
     fn visit_function(&mut self, function: &mut FunctionCall) {
         unsafe {
-            if !try_append_intrinsic(self.context, &function.name, &mut self.stack) {
+            if let Some(value) = self.context.constants.get(&function.name).cloned() {
+                let value = self.context.get_const_value(&value);
+                self.stack.push(value);
+            } else if let Some(mut body) = self.inline_body(&function.name) {
+                reify_block(&mut body, &self.context.call_generics(function));
+                // The body starts from the current stack and BasicBlock, so it is generated as if
+                // its words were written in place of the call
+                let (output_stack, final_bb) = CodeBlockCodeGen::new(
+                    self.context,
+                    self.containing_function,
+                    std::mem::take(&mut self.stack),
+                    self.final_bb,
+                )
+                .walk(&mut body);
+                self.context.inlining.pop();
+                self.stack = output_stack;
+                self.final_bb = final_bb;
+            } else if !try_append_intrinsic(self.context, function, &mut self.stack) {
                 let call_type = &self.context.functions[&function.name];
                 let mut args = Vec::new();
-                // Pop the required number of arguments off the compilation stack
+                // Pop the required number of arguments off the compilation stack. The top of the
+                // stack is the last argument.
                 for _ in 0..call_type.inputs.len() {
                     args.push(self.stack.pop().unwrap().llvm_value);
                 }
+                args.reverse();
 
                 // generate function call
+                let called = self.context.get_called_function(function);
                 let result = LLVMBuildCall(
                     self.context.builder,
-                    self.context.generated_functions[&function.name].function_value,
+                    called.function_value,
                     args.as_mut_ptr(),
                     args.len() as u32,
                     "\0".c_str(),
@@ -183,7 +254,7 @@ impl CodeBlockVisitor for CodeBlockCodeGen<'_, '_> {
             let mut output_stack = Vec::new();
             for (true_stackval, false_stackval) in true_output_stack
                 .into_iter()
-                .zip(false_output_stack.into_iter())
+                .zip(false_output_stack)
             {
                 let mut true_stackval = true_stackval;
                 let mut false_stackval = false_stackval;
@@ -284,7 +355,7 @@ impl CodeBlockVisitor for CodeBlockCodeGen<'_, '_> {
             // Complete the PHI nodes created above, since we now know the stack output of the body.
             for (phi, body_stackval) in condition_phis.iter_mut().zip(body_output_stack.iter_mut())
             {
-                LLVMAddIncoming((*phi).llvm_value, &mut body_stackval.llvm_value, &mut body_final_bb, 1);
+                LLVMAddIncoming(phi.llvm_value, &mut body_stackval.llvm_value, &mut body_final_bb, 1);
             }
 
             LLVMPositionBuilderAtEnd(self.context.builder, end_bb);
@@ -0,0 +1,230 @@
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::ptr;
+use std::sync::Once;
+
+use llvm::bit_writer::LLVMWriteBitcodeToFile;
+use llvm::core::*;
+use llvm::execution_engine::*;
+use llvm::prelude::*;
+use llvm::target::{
+    LLVMDisposeTargetData, LLVMSetModuleDataLayout, LLVM_InitializeNativeAsmPrinter,
+    LLVM_InitializeNativeTarget,
+};
+use llvm::target_machine::*;
+use llvm_sys as llvm;
+
+use super::module::STACK_ENTRY;
+use super::passes::add_passes;
+use super::ToCStr;
+use crate::cli::OptLevel;
+
+extern "C" {
+    // Used to flush output written by JIT compiled code through libc
+    fn fflush(stream: *mut c_void) -> i32;
+}
+
+/// Kinds of file which are produced by LLVM's backend for the target machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineCodeKind {
+    Object,
+    Assembly,
+}
+
+/// The native target must be initialized before generating machine code for it, whether to a
+/// file or through the JIT
+fn initialize_native_target() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        LLVMLinkInMCJIT();
+        LLVM_InitializeNativeTarget();
+        LLVM_InitializeNativeAsmPrinter();
+    });
+}
+
+fn path_to_c_string(path: &Path) -> Result<CString, String> {
+    path.to_str()
+        .and_then(|path| CString::new(path).ok())
+        .ok_or_else(|| format!("invalid output path {}", path.display()))
+}
+
+/// Takes ownership of a message allocated by LLVM
+unsafe fn take_message(message: *mut c_char) -> String {
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeMessage(message);
+    text
+}
+
+/// An LLVM module produced by `ModuleCodeGen`, which owns its LLVM context
+pub struct CompiledModule {
+    llvm_context: LLVMContextRef,
+    // Null once ownership of the module has been passed to an execution engine
+    module: LLVMModuleRef,
+}
+
+impl CompiledModule {
+    pub(super) fn new(llvm_context: LLVMContextRef, module: LLVMModuleRef) -> Self {
+        Self {
+            llvm_context,
+            module,
+        }
+    }
+
+    /// The textual LLVM IR of the module
+    pub fn to_ir(&self) -> String {
+        unsafe { take_message(LLVMPrintModuleToString(self.module)) }
+    }
+
+    /// Runs the LLVM optimization pipeline for `opt_level` over the module
+    pub fn optimize(&mut self, opt_level: OptLevel) {
+        unsafe {
+            let pass_manager = LLVMCreatePassManager();
+            add_passes(pass_manager, opt_level);
+            LLVMRunPassManager(pass_manager, self.module);
+            LLVMDisposePassManager(pass_manager);
+        }
+    }
+
+    pub fn write_ir(&self, path: &Path) -> Result<(), String> {
+        let c_path = path_to_c_string(path)?;
+        unsafe {
+            let mut error = ptr::null_mut();
+            if LLVMPrintModuleToFile(self.module, c_path.as_ptr(), &mut error) != 0 {
+                return Err(format!(
+                    "could not write {}: {}",
+                    path.display(),
+                    take_message(error)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_bitcode(&self, path: &Path) -> Result<(), String> {
+        let c_path = path_to_c_string(path)?;
+        unsafe {
+            if LLVMWriteBitcodeToFile(self.module, c_path.as_ptr()) != 0 {
+                return Err(format!("could not write {}", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates machine code for the module's target triple, and writes it to `path`
+    pub fn write_machine_code(
+        &self,
+        path: &Path,
+        kind: MachineCodeKind,
+        opt_level: OptLevel,
+    ) -> Result<(), String> {
+        initialize_native_target();
+        let c_path = path_to_c_string(path)?;
+        unsafe {
+            let target_machine = self.create_target_machine(opt_level)?;
+            let data_layout = LLVMCreateTargetDataLayout(target_machine);
+            LLVMSetModuleDataLayout(self.module, data_layout);
+            LLVMDisposeTargetData(data_layout);
+
+            let file_type = match kind {
+                MachineCodeKind::Object => LLVMCodeGenFileType::LLVMObjectFile,
+                MachineCodeKind::Assembly => LLVMCodeGenFileType::LLVMAssemblyFile,
+            };
+            let mut error = ptr::null_mut();
+            let failed = LLVMTargetMachineEmitToFile(
+                target_machine,
+                self.module,
+                c_path.as_ptr() as *mut c_char,
+                file_type,
+                &mut error,
+            );
+            LLVMDisposeTargetMachine(target_machine);
+            if failed != 0 {
+                return Err(format!(
+                    "could not write {}: {}",
+                    path.display(),
+                    take_message(error)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn create_target_machine(
+        &self,
+        opt_level: OptLevel,
+    ) -> Result<LLVMTargetMachineRef, String> {
+        let triple = LLVMGetTarget(self.module);
+        let mut target = ptr::null_mut();
+        let mut error = ptr::null_mut();
+        if LLVMGetTargetFromTriple(triple, &mut target, &mut error) != 0 {
+            return Err(format!("unsupported target: {}", take_message(error)));
+        }
+
+        let codegen_level = match opt_level {
+            OptLevel::O0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            OptLevel::O1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            OptLevel::O2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            OptLevel::O3 => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        };
+        // Objects are position independent so they can be linked into PIE executables, which
+        // most system linkers produce by default
+        Ok(LLVMCreateTargetMachine(
+            target,
+            triple,
+            "generic\0".c_str(),
+            "\0".c_str(),
+            codegen_level,
+            LLVMRelocMode::LLVMRelocPIC,
+            LLVMCodeModel::LLVMCodeModelDefault,
+        ))
+    }
+
+    /// JIT compiles the module and calls its stack entry function (see
+    /// `ModuleCodeGen::with_stack_entry`) with `inputs`. Returns the `output_count` values it left
+    /// on the stack.
+    pub fn run_stack_entry(
+        mut self,
+        inputs: &[u64],
+        output_count: usize,
+    ) -> Result<Vec<u64>, String> {
+        initialize_native_target();
+        unsafe {
+            let mut engine = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            // The execution engine takes ownership of the module
+            let module = std::mem::replace(&mut self.module, ptr::null_mut());
+            if LLVMCreateMCJITCompilerForModule(&mut engine, module, ptr::null_mut(), 0, &mut error)
+                != 0
+            {
+                return Err(format!("could not create JIT: {}", take_message(error)));
+            }
+
+            let mut name = STACK_ENTRY.to_string();
+            let address = LLVMGetFunctionAddress(engine, name.c_str());
+            if address == 0 {
+                LLVMDisposeExecutionEngine(engine);
+                return Err("JIT compiled module has no stack entry".into());
+            }
+            let stack_entry: extern "C" fn(*const u64, *mut u64) = std::mem::transmute(address);
+
+            let mut outputs = vec![0u64; output_count];
+            stack_entry(inputs.as_ptr(), outputs.as_mut_ptr());
+            fflush(ptr::null_mut());
+
+            LLVMDisposeExecutionEngine(engine);
+            Ok(outputs)
+        }
+    }
+}
+
+impl Drop for CompiledModule {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.module.is_null() {
+                LLVMDisposeModule(self.module);
+            }
+            LLVMContextDispose(self.llvm_context);
+        }
+    }
+}
@@ -3,10 +3,13 @@ use std::os::raw::c_char;
 
 use llvm::core::*;
 use llvm::prelude::*;
+use llvm::LLVMIntPredicate::*;
+use llvm::LLVMRealPredicate::*;
 use llvm::*;
 use llvm_sys as llvm;
 
 use crate::ast::ConcreteType;
+use crate::ast::FunctionCall;
 use crate::ast::Type;
 
 use super::CompilationStack;
@@ -15,23 +18,35 @@ use super::{Context, ToCStr};
 
 pub(super) unsafe fn try_append_intrinsic(
     context: &mut Context,
-    name: &str,
+    function: &FunctionCall,
     stack: &mut Vec<CompilationStackValue>,
 ) -> bool {
-    match name {
-        "+" => binop_intrinsic(LLVMBuildAdd, stack, context),
-        "-" => binop_intrinsic(LLVMBuildSub, stack, context),
-        "*" => binop_intrinsic(LLVMBuildMul, stack, context),
-        "/" => binop_intrinsic(LLVMBuildSDiv, stack, context),
-        "%" => binop_intrinsic(LLVMBuildSRem, stack, context),
-        "<<" => binop_intrinsic(LLVMBuildShl, stack, context),
-        ">>" => binop_intrinsic(LLVMBuildAShr, stack, context),
-        "=" => icmp_intrinsic(LLVMIntPredicate::LLVMIntEQ, stack, context),
-        "!=" => icmp_intrinsic(LLVMIntPredicate::LLVMIntNE, stack, context),
-        ">" => icmp_intrinsic(LLVMIntPredicate::LLVMIntSGT, stack, context),
-        "<" => icmp_intrinsic(LLVMIntPredicate::LLVMIntSLT, stack, context),
-        ">=" => icmp_intrinsic(LLVMIntPredicate::LLVMIntSGE, stack, context),
-        "<=" => icmp_intrinsic(LLVMIntPredicate::LLVMIntSLE, stack, context),
+    match function.name.as_str() {
+        "+" => binop_intrinsic(LLVMBuildAdd, LLVMBuildAdd, Some(LLVMBuildFAdd), stack, context),
+        "-" => binop_intrinsic(LLVMBuildSub, LLVMBuildSub, Some(LLVMBuildFSub), stack, context),
+        "*" => binop_intrinsic(LLVMBuildMul, LLVMBuildMul, Some(LLVMBuildFMul), stack, context),
+        "/" => binop_intrinsic(LLVMBuildSDiv, LLVMBuildUDiv, Some(LLVMBuildFDiv), stack, context),
+        "%" => binop_intrinsic(LLVMBuildSRem, LLVMBuildURem, Some(LLVMBuildFRem), stack, context),
+        "<<" => binop_intrinsic(LLVMBuildShl, LLVMBuildShl, None, stack, context),
+        ">>" => binop_intrinsic(LLVMBuildAShr, LLVMBuildLShr, None, stack, context),
+        "=" => cmp_intrinsic(LLVMIntEQ, LLVMIntEQ, LLVMRealOEQ, stack, context),
+        "!=" => cmp_intrinsic(LLVMIntNE, LLVMIntNE, LLVMRealUNE, stack, context),
+        ">" => cmp_intrinsic(LLVMIntSGT, LLVMIntUGT, LLVMRealOGT, stack, context),
+        "<" => cmp_intrinsic(LLVMIntSLT, LLVMIntULT, LLVMRealOLT, stack, context),
+        ">=" => cmp_intrinsic(LLVMIntSGE, LLVMIntUGE, LLVMRealOGE, stack, context),
+        "<=" => cmp_intrinsic(LLVMIntSLE, LLVMIntULE, LLVMRealOLE, stack, context),
+        "ptr=" => cmp_intrinsic(LLVMIntEQ, LLVMIntEQ, LLVMRealOEQ, stack, context),
+        "ptr!=" => cmp_intrinsic(LLVMIntNE, LLVMIntNE, LLVMRealUNE, stack, context),
+        "ptr>" => cmp_intrinsic(LLVMIntUGT, LLVMIntUGT, LLVMRealOGT, stack, context),
+        "ptr<" => cmp_intrinsic(LLVMIntULT, LLVMIntULT, LLVMRealOLT, stack, context),
+        "ptr>=" => cmp_intrinsic(LLVMIntUGE, LLVMIntUGE, LLVMRealOGE, stack, context),
+        "ptr<=" => cmp_intrinsic(LLVMIntULE, LLVMIntULE, LLVMRealOLE, stack, context),
+        "deref" => deref_intrinsic(stack, context),
+        "store" => store_intrinsic(stack, context),
+        "ref" => ref_intrinsic(stack, context),
+        "alloca" => alloca_intrinsic(function, stack, context),
+        "ptr+" => ptr_add_intrinsic(stack, context),
+        "construct" => construct_intrinsic(function, stack, context),
         "swap" => {
             let a = stack.pop().unwrap();
             let b = stack.pop().unwrap();
@@ -66,31 +81,30 @@ pub(super) unsafe fn try_append_intrinsic(
             stack.pop().unwrap();
             true
         }
-        "(i)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::I32)),
-        "(ui)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::U32)),
-        "(q)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::I64)),
-        "(uq)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::U64)),
-        "(c)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::I8)),
-        "(uc)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::U8)),
-        "(f)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::F32)),
-        "(d)" => cast_intrinsic(context, stack, Type::Concrete(ConcreteType::F64)),
-        // TODO implementh `nth` function for dereferenceing into an array. Need to keep reified
-        // generic types around from the typechecking stage so we know what types are being indexed
-        // (to generate correct getelementptr).
-        _ => false,
+        name => field_intrinsic(name, stack, context),
     }
 }
 
 type LLVMBuildBinopFn =
     unsafe extern "C" fn(LLVMBuilderRef, LLVMValueRef, LLVMValueRef, *const c_char) -> LLVMValueRef;
 
+/// Builds `signed_op` or `unsigned_op` for integer operands, depending on their signedness, or
+/// `float_op` for float operands. The typechecker only allows float operands for intrinsics which
+/// have a float_op.
 unsafe fn binop_intrinsic(
-    f: LLVMBuildBinopFn,
+    signed_op: LLVMBuildBinopFn,
+    unsigned_op: LLVMBuildBinopFn,
+    float_op: Option<LLVMBuildBinopFn>,
     stack: &mut CompilationStack,
     context: &mut Context,
 ) -> bool {
     let rhs = stack.pop().unwrap();
     let lhs = stack.pop().unwrap();
+    let f = match (operand_kind(&rhs.typ), float_op) {
+        (OperandKind::Float, Some(float_op)) => float_op,
+        (OperandKind::Unsigned, _) => unsigned_op,
+        _ => signed_op,
+    };
     let new = f(
         context.builder,
         lhs.llvm_value,
@@ -105,28 +119,211 @@ unsafe fn binop_intrinsic(
     true
 }
 
-unsafe fn icmp_intrinsic(
-    predicate: LLVMIntPredicate,
+enum OperandKind {
+    Signed,
+    Unsigned,
+    Float,
+}
+
+// Everything which is not a float or a signed integer, eg. a bool or a pointer, is unsigned
+fn operand_kind(typ: &Type) -> OperandKind {
+    match typ {
+        Type::Concrete(concrete) if !concrete.is_integral() => OperandKind::Float,
+        Type::Concrete(concrete) if concrete.is_signed() => OperandKind::Signed,
+        _ => OperandKind::Unsigned,
+    }
+}
+
+/// Compares two integers with `signed_predicate` or `unsigned_predicate`, depending on their
+/// signedness, or two floats with `float_predicate`. Comparisons with NaN are false, except `!=`.
+unsafe fn cmp_intrinsic(
+    signed_predicate: LLVMIntPredicate,
+    unsigned_predicate: LLVMIntPredicate,
+    float_predicate: LLVMRealPredicate,
     stack: &mut CompilationStack,
     context: &mut Context,
 ) -> bool {
     let rhs = stack.pop().unwrap();
     let lhs = stack.pop().unwrap();
-    let new = LLVMBuildICmp(
+    let kind = operand_kind(&rhs.typ);
+    let (lhs, rhs) = (lhs.llvm_value, rhs.llvm_value);
+    let new = match kind {
+        OperandKind::Float => {
+            LLVMBuildFCmp(context.builder, float_predicate, lhs, rhs, "\0".c_str())
+        }
+        OperandKind::Signed => {
+            LLVMBuildICmp(context.builder, signed_predicate, lhs, rhs, "\0".c_str())
+        }
+        OperandKind::Unsigned => {
+            LLVMBuildICmp(context.builder, unsigned_predicate, lhs, rhs, "\0".c_str())
+        }
+    };
+    stack.push(CompilationStackValue {
+        llvm_value: new,
+        typ: Type::Concrete(ConcreteType::Bool),
+    });
+    true
+}
+
+fn pointee(typ: &Type) -> &Type {
+    match typ {
+        Type::Pointer(inner) => inner,
+        _ => unreachable!("the typechecker only allows pointers here"),
+    }
+}
+
+unsafe fn deref_intrinsic(stack: &mut CompilationStack, context: &mut Context) -> bool {
+    let pointer = stack.pop().unwrap();
+    let value = LLVMBuildLoad(context.builder, pointer.llvm_value, "\0".c_str());
+    stack.push(CompilationStackValue {
+        llvm_value: value,
+        typ: pointee(&pointer.typ).clone(),
+    });
+    true
+}
+
+unsafe fn store_intrinsic(stack: &mut CompilationStack, context: &mut Context) -> bool {
+    let value = stack.pop().unwrap();
+    let pointer = stack.pop().unwrap();
+    LLVMBuildStore(context.builder, value.llvm_value, pointer.llvm_value);
+    true
+}
+
+/// Copies the value on top of the stack into a new slot, and replaces it with the slot's address
+unsafe fn ref_intrinsic(stack: &mut CompilationStack, context: &mut Context) -> bool {
+    let value = stack.pop().unwrap();
+    let slot = build_entry_alloca(context, &value.typ);
+    LLVMBuildStore(context.builder, value.llvm_value, slot);
+    stack.push(CompilationStackValue {
+        llvm_value: slot,
+        typ: Type::Pointer(Box::new(value.typ)),
+    });
+    true
+}
+
+unsafe fn alloca_intrinsic(
+    function: &FunctionCall,
+    stack: &mut CompilationStack,
+    context: &mut Context,
+) -> bool {
+    // The allocated type is given by the call's type argument, eg. `alloca<i>` returns a `*i`
+    let typ = function.reified_type.as_ref().unwrap().outputs[0].clone();
+    let slot = build_entry_alloca(context, pointee(&typ));
+    stack.push(CompilationStackValue {
+        llvm_value: slot,
+        typ,
+    });
+    true
+}
+
+/// Allocates a slot for a `typ` at the start of the current function. Slots allocated in a loop
+/// are reused by each iteration, rather than growing the stack until the function returns.
+unsafe fn build_entry_alloca(context: &mut Context, typ: &Type) -> LLVMValueRef {
+    let function = LLVMGetBasicBlockParent(LLVMGetInsertBlock(context.builder));
+    let entry = LLVMGetEntryBasicBlock(function);
+    let builder = LLVMCreateBuilderInContext(context.llvm_context);
+    let first = LLVMGetFirstInstruction(entry);
+    if first.is_null() {
+        LLVMPositionBuilderAtEnd(builder, entry);
+    } else {
+        LLVMPositionBuilderBefore(builder, first);
+    }
+    let slot = LLVMBuildAlloca(builder, context.get_llvm_type(typ), "slot\0".c_str());
+    LLVMDisposeBuilder(builder);
+    slot
+}
+
+/// Offsets a pointer by a number of elements of the type it points to
+unsafe fn ptr_add_intrinsic(stack: &mut CompilationStack, context: &mut Context) -> bool {
+    let mut offset = stack.pop().unwrap().llvm_value;
+    let pointer = stack.pop().unwrap();
+    let offset_pointer = LLVMBuildGEP(
         context.builder,
-        predicate,
-        lhs.llvm_value,
-        rhs.llvm_value,
+        pointer.llvm_value,
+        &mut offset,
+        1,
         "\0".c_str(),
     );
     stack.push(CompilationStackValue {
-        llvm_value: new,
-        typ: Type::Concrete(ConcreteType::Bool),
+        llvm_value: offset_pointer,
+        typ: pointer.typ,
     });
     true
 }
 
-unsafe fn cast_intrinsic(context: &mut Context, stack: &mut CompilationStack, to: Type) -> bool {
+/// Creates a record with every field zeroed. The record's type is given by the call's type
+/// argument, eg. `construct<my-struct>`.
+unsafe fn construct_intrinsic(
+    function: &FunctionCall,
+    stack: &mut CompilationStack,
+    context: &mut Context,
+) -> bool {
+    let typ = function.reified_type.as_ref().unwrap().outputs[0].clone();
+    stack.push(CompilationStackValue {
+        llvm_value: LLVMConstNull(context.get_llvm_type(&typ)),
+        typ,
+    });
+    true
+}
+
+/// Builds the getter or setter of a record's field if `name` is one, eg. `.my-struct.foo` or
+/// `>my-struct.foo`, which may be qualified by the record's module, eg. `shapes::.point.x`.
+/// Record names cannot contain `.`, so the record's name ends at the second `.`.
+unsafe fn field_intrinsic(name: &str, stack: &mut CompilationStack, context: &mut Context) -> bool {
+    let (module, name) = match name.rsplit_once("::") {
+        Some((module, name)) => (Some(module), name),
+        None => (None, name),
+    };
+    let (is_getter, accessed) = match (name.strip_prefix('.'), name.strip_prefix('>')) {
+        (Some(accessed), _) => (true, accessed),
+        (_, Some(accessed)) => (false, accessed),
+        _ => return false,
+    };
+    let records = context.records;
+    let field = accessed.split_once('.').and_then(|(record, field)| {
+        let record = match module {
+            Some(module) => format!("{}::{}", module, record),
+            None => record.to_string(),
+        };
+        records
+            .get(&record)?
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.name == field)
+    });
+    let (index, field) = match field {
+        Some(field) => field,
+        None => return false,
+    };
+
+    if is_getter {
+        // The record stays on the stack under the field's value
+        let record = stack.last().unwrap().llvm_value;
+        let value = LLVMBuildExtractValue(context.builder, record, index as u32, "\0".c_str());
+        stack.push(CompilationStackValue {
+            llvm_value: value,
+            typ: field.typ.clone(),
+        });
+    } else {
+        let value = stack.pop().unwrap();
+        let record = stack.pop().unwrap();
+        let updated = LLVMBuildInsertValue(
+            context.builder,
+            record.llvm_value,
+            value.llvm_value,
+            index as u32,
+            "\0".c_str(),
+        );
+        stack.push(CompilationStackValue {
+            llvm_value: updated,
+            typ: record.typ,
+        });
+    }
+    true
+}
+
+/// Converts the value on top of the stack to `to`
+pub(super) unsafe fn build_cast(context: &mut Context, stack: &mut CompilationStack, to: Type) {
     let from = stack.pop().unwrap();
     let opcode = get_cast_opcode(&from.typ, &to);
     if let Some(opcode) = opcode {
@@ -147,15 +344,16 @@ unsafe fn cast_intrinsic(context: &mut Context, stack: &mut CompilationStack, to
             typ: to,
         });
     }
-    true
 }
 
 fn get_cast_opcode(from: &Type, to: &Type) -> Option<LLVMOpcode> {
     match (from, to) {
         (Type::Concrete(from), Type::Concrete(to)) => get_cast_opcode_concrete(from, to.clone()),
-        (Type::Concrete(_), Type::Pointer(_)) => todo!(),
-        (Type::Pointer(_), Type::Concrete(_)) => todo!(),
-        (Type::Pointer(_), Type::Pointer(_)) => todo!(),
+        (Type::Concrete(_), Type::Pointer(_)) => Some(LLVMOpcode::LLVMIntToPtr),
+        (Type::Pointer(_), Type::Concrete(_)) => Some(LLVMOpcode::LLVMPtrToInt),
+        (Type::Pointer(_), Type::Pointer(_)) => Some(LLVMOpcode::LLVMBitCast),
+        // A record can only be cast to its own type
+        (Type::Record(_), Type::Record(_)) => None,
         (_, _) => panic!("Should not be any generics in codegen stage"),
     }
 }
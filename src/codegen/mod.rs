@@ -1,17 +1,47 @@
 mod code_block;
+mod compiled_module;
 mod intrinsics;
 pub mod module;
+mod passes;
 
 use std::{collections::HashMap, os::raw::c_char};
 
-use crate::ast::{ConcreteType, Type};
-use crate::ast::{FunctionHeader, FunctionType};
+use crate::ast::{CodeBlock, ConcreteType, ConstValue, RecordField, Span, Type, Word};
+use crate::ast::{FunctionCall, FunctionHeader, FunctionImpl, FunctionType};
+use crate::diagnostic::Diagnostic;
 
 use llvm::core::*;
 use llvm::prelude::*;
 use llvm::*;
 use llvm_sys as llvm;
 
+pub use compiled_module::{CompiledModule, MachineCodeKind};
+
+/// The function called by the C runtime when a program starts. Its result is the program's exit
+/// status, so if it is declared without one it returns 0, as `main` does in C.
+pub(super) const ENTRY_POINT: &str = "main";
+
+/// LLVM rejected the code generated for a function. Typechecking should reject any program that
+/// would cause this, so it indicates a compiler bug.
+#[derive(Debug)]
+pub struct CodeGenError {
+    pub function: String,
+    pub span: Span,
+}
+
+impl CodeGenError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(format!(
+            "generated invalid LLVM IR for function `{}`",
+            self.function
+        ))
+        .with_span(self.span)
+        .with_label("in this function")
+        .with_note("this is a bug in the compiler")
+        .with_help("use `--emit=ll` to inspect the generated IR")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct CompilationStackValue {
     llvm_value: LLVMValueRef,
@@ -37,9 +67,86 @@ pub(super) struct Context<'a> {
     // Used to get the input/output type of functions so that their
     // CompilationStack can be properly generated
     functions: &'a HashMap<String, FunctionType>,
+    // The fields of each record, which give the layout of its struct type
+    records: &'a HashMap<String, Vec<RecordField>>,
+    // The struct type of each record which has been used so far
+    record_types: HashMap<String, LLVMTypeRef>,
+    // Values of the constants in the module, which are emitted wherever they are used
+    constants: HashMap<String, ConstValue>,
+    // Bodies of the inline functions in the module, which are generated into each caller
+    inline_functions: HashMap<String, CodeBlock>,
+    // Inline functions whose bodies are currently being generated, innermost last. A recursive
+    // call to one of these is generated as a normal call, since inlining it would never finish.
+    inlining: Vec<String>,
+    // Implementations of the generic functions in the module. These are not generated directly,
+    // instead a copy is generated for each set of types a call binds their generics to.
+    generic_functions: HashMap<String, FunctionImpl>,
+    // Copies of generic functions which have been declared, but whose bodies are not generated yet
+    specializations: Vec<FunctionImpl>,
 }
 
 impl<'a> Context<'a> {
+    unsafe fn get_const_value(&mut self, value: &ConstValue) -> CompilationStackValue {
+        let typ = Type::Concrete(value.typ());
+        let llvm_type = self.get_llvm_type(&typ);
+        let llvm_value = match *value {
+            ConstValue::Int(n, ref concrete) => {
+                LLVMConstInt(llvm_type, n as u64, concrete.is_signed() as LLVMBool)
+            }
+            ConstValue::Float(n, _) => LLVMConstReal(llvm_type, n),
+            ConstValue::Bool(b) => LLVMConstInt(llvm_type, b as u64, false as LLVMBool),
+        };
+        CompilationStackValue { llvm_value, typ }
+    }
+
+    /// The generics of the function called by `call`, bound to the types they have at the call
+    fn call_generics(&self, call: &FunctionCall) -> HashMap<String, Type> {
+        let typ = &self.functions[&call.name];
+        let reified = call.reified_type.as_ref().unwrap();
+        let mut generics = HashMap::new();
+        for (generic, reified) in typ
+            .inputs
+            .iter()
+            .chain(&typ.outputs)
+            .zip(reified.inputs.iter().chain(&reified.outputs))
+        {
+            bind_generics(generic, reified, &mut generics);
+        }
+        generics
+    }
+
+    /// The function which `call` calls. For a generic function, this is the copy of it for the
+    /// types at the call, which is declared the first time it is called with them. Its body is
+    /// generated later, from `specializations`.
+    unsafe fn get_called_function(&mut self, call: &FunctionCall) -> GeneratedFunction {
+        let generic = match self.generic_functions.get(&call.name) {
+            Some(generic) => generic,
+            None => return self.generated_functions[&call.name],
+        };
+        let reified = call.reified_type.clone().unwrap();
+        let types = |types: &[Type]| {
+            let types = types.iter().map(Type::to_string).collect::<Vec<_>>();
+            types.join(" ")
+        };
+        let name = format!(
+            "{}<{} -> {}>",
+            call.name,
+            types(&reified.inputs),
+            types(&reified.outputs)
+        );
+        if let Some(&function) = self.generated_functions.get(&name) {
+            return function;
+        }
+
+        let mut specialization = generic.clone();
+        reify_block(&mut specialization.body, &self.call_generics(call));
+        specialization.head.name = name;
+        specialization.head.typ = reified;
+        let function = self.create_function_decl(&specialization.head, false, false);
+        self.specializations.push(specialization);
+        function
+    }
+
     pub(super) unsafe fn get_llvm_type(&mut self, typ: &Type) -> LLVMTypeRef {
         match typ {
             Type::Concrete(concrete_type) => match concrete_type {
@@ -50,11 +157,37 @@ impl<'a> Context<'a> {
                 ConcreteType::F64 => LLVMDoubleTypeInContext(self.llvm_context),
                 ConcreteType::Bool => LLVMInt1TypeInContext(self.llvm_context),
             },
-            Type::Generic(_) => todo!("Should get the reified type here!"),
+            Type::Generic(..) => unreachable!("generic functions are generated for each use"),
+            Type::UntypedInt(_) => unreachable!("literal types are resolved by the typechecker"),
             Type::Pointer(inner) => LLVMPointerType(self.get_llvm_type(inner), 0),
+            Type::Record(name) => self.get_record_type(name),
         }
     }
 
+    /// Creates the named struct type of a record the first time it is used. The struct is named
+    /// before its fields are added, so that a record can hold a pointer to itself.
+    unsafe fn get_record_type(&mut self, name: &str) -> LLVMTypeRef {
+        if let Some(&record_type) = self.record_types.get(name) {
+            return record_type;
+        }
+        let mut struct_name = name.to_string();
+        let record_type = LLVMStructCreateNamed(self.llvm_context, struct_name.c_str());
+        self.record_types.insert(name.to_string(), record_type);
+
+        let records = self.records;
+        let mut field_types = records[name]
+            .iter()
+            .map(|field| self.get_llvm_type(&field.typ))
+            .collect::<Vec<_>>();
+        LLVMStructSetBody(
+            record_type,
+            field_types.as_mut_ptr(),
+            field_types.len() as u32,
+            false as LLVMBool,
+        );
+        record_type
+    }
+
     pub(super) unsafe fn get_function_type(
         &mut self,
         typ: &FunctionType,
@@ -76,6 +209,7 @@ impl<'a> Context<'a> {
 
     pub(super) unsafe fn create_return_type(&mut self, head: &FunctionHeader) -> LLVMTypeRef {
         match &head.typ.outputs.len() {
+            0 if head.name == ENTRY_POINT => LLVMInt32TypeInContext(self.llvm_context),
             0 => LLVMVoidTypeInContext(self.llvm_context),
             1 => self.get_llvm_type(&head.typ.outputs[0]),
             _ => {
@@ -107,6 +241,7 @@ impl<'a> Context<'a> {
     pub(super) unsafe fn create_function_decl(
         &mut self,
         head: &FunctionHeader,
+        is_pub: bool,
         is_extern: bool,
     ) -> GeneratedFunction {
         let return_type = self.create_return_type(head);
@@ -114,8 +249,16 @@ impl<'a> Context<'a> {
 
         let mut function_name = head.name.clone();
         let function_value = LLVMAddFunction(self.module, function_name.c_str(), function_type);
+        // Only extern functions are visible to the linker. Public functions keep a local symbol,
+        // so they can still be found by name in the output (eg. `io::print` in assembly), while
+        // private functions are left out of the symbol table.
         if !is_extern {
-            LLVMSetLinkage(function_value, LLVMLinkage::LLVMPrivateLinkage);
+            let linkage = if is_pub {
+                LLVMLinkage::LLVMInternalLinkage
+            } else {
+                LLVMLinkage::LLVMPrivateLinkage
+            };
+            LLVMSetLinkage(function_value, linkage);
         }
 
         self.generated_functions.insert(
@@ -130,6 +273,60 @@ impl<'a> Context<'a> {
     }
 }
 
+// Binds each generic in `generic` to the type in the same place in `reified`
+fn bind_generics(generic: &Type, reified: &Type, generics: &mut HashMap<String, Type>) {
+    match (generic, reified) {
+        (Type::Generic(name, _), _) => {
+            generics.insert(name.clone(), reified.clone());
+        }
+        (Type::Pointer(generic), Type::Pointer(reified)) => {
+            bind_generics(generic, reified, generics)
+        }
+        _ => {}
+    }
+}
+
+fn reify(typ: &Type, generics: &HashMap<String, Type>) -> Type {
+    match typ {
+        Type::Generic(name, _) => generics.get(name).cloned().unwrap_or_else(|| typ.clone()),
+        Type::Pointer(inner) => Type::Pointer(Box::new(reify(inner, generics))),
+        _ => typ.clone(),
+    }
+}
+
+/// Replaces the generics in the types annotated on a block with the types they are bound to, so
+/// that the body of a generic function can be generated for a call
+fn reify_block(block: &mut CodeBlock, generics: &HashMap<String, Type>) {
+    let reify_all = |types: &mut Vec<Type>| {
+        for typ in types {
+            *typ = reify(typ, generics);
+        }
+    };
+    for word in &mut block.words {
+        match word {
+            Word::Cast(to, _) => *to = reify(to, generics),
+            Word::FunctionCall(call) => {
+                reify_all(&mut call.type_args);
+                let reified = call.reified_type.as_mut().unwrap();
+                reify_all(&mut reified.inputs);
+                reify_all(&mut reified.outputs);
+            }
+            Word::IfStatement(statement) => {
+                reify_block(&mut statement.true_branch, generics);
+                reify_block(&mut statement.false_branch, generics);
+            }
+            Word::WhileStatement(statement) => {
+                reify_block(&mut statement.condition, generics);
+                reify_block(&mut statement.body, generics);
+            }
+            Word::IntLiteral(_)
+            | Word::FloatLiteral(..)
+            | Word::BoolLiteral(..)
+            | Word::StringLiteral(..) => {}
+        }
+    }
+}
+
 trait ToCStr {
     fn c_str(self) -> *const c_char;
 }
@@ -1,10 +1,9 @@
 use std::collections::HashMap;
-use std::ffi::CStr;
 use std::ptr;
 
 use crate::ast::visitor::CodeBlockVisitor;
-use crate::ast::{visitor::ModuleVisitor, FunctionType};
-use crate::ast::{FunctionDecl, FunctionImpl};
+use crate::ast::{visitor::ModuleVisitor, ProgramTypes};
+use crate::ast::{Const, FunctionDecl, FunctionImpl, Import, Macro, Record, TopLevelItem};
 use crate::codegen::CompilationStackValue;
 
 use llvm::analysis::LLVMVerifyFunction;
@@ -14,14 +13,23 @@ use llvm::target_machine::LLVMGetDefaultTargetTriple;
 use llvm::*;
 use llvm_sys as llvm;
 
-use super::{code_block::CodeBlockCodeGen, Context, ToCStr};
+use super::{
+    code_block::CodeBlockCodeGen, CodeGenError, CompiledModule, Context, ToCStr, ENTRY_POINT,
+};
+
+/// Name of the function generated by `ModuleCodeGen::with_stack_entry`. Words cannot contain `[`,
+/// so this never clashes with a function in the module.
+pub const STACK_ENTRY: &str = "[stack_entry]";
 
 pub struct ModuleCodeGen<'a> {
     context: Context<'a>,
+    errors: Vec<CodeGenError>,
+    // Function to generate a STACK_ENTRY wrapper for, see `with_stack_entry`
+    stack_entry: Option<String>,
 }
 
 impl<'a> ModuleCodeGen<'a> {
-    pub fn new(functions: &'a HashMap<String, FunctionType>) -> Self {
+    pub fn new(types: &'a ProgramTypes) -> Self {
         unsafe {
             let context = LLVMContextCreate();
             let module = LLVMModuleCreateWithNameInContext("main_module\0".c_str(), context);
@@ -33,22 +41,103 @@ impl<'a> ModuleCodeGen<'a> {
                     module,
                     builder: LLVMCreateBuilderInContext(context),
                     generated_functions: HashMap::new(),
-                    functions,
+                    functions: &types.functions,
+                    records: &types.records,
+                    record_types: HashMap::new(),
+                    constants: HashMap::new(),
+                    inline_functions: HashMap::new(),
+                    inlining: Vec::new(),
+                    generic_functions: HashMap::new(),
+                    specializations: Vec::new(),
                 },
+                errors: Vec::new(),
+                stack_entry: None,
             }
         }
     }
+
+    /// Additionally generate an externally visible `void STACK_ENTRY(i64* inputs, i64* outputs)`,
+    /// which calls `function` with arguments loaded from `inputs`, and stores its results to
+    /// `outputs`. Each value takes up one 64 bit slot, so that the stack can be passed to and from
+    /// a JIT compiled function without knowing its types at compile time.
+    pub fn with_stack_entry(mut self, function: &str) -> Self {
+        self.stack_entry = Some(function.to_string());
+        self
+    }
+
+    unsafe fn build_stack_entry(&mut self, function: &str) {
+        let context = &mut self.context;
+        let called = context.generated_functions[function];
+        let typ = context.functions[function].clone();
+
+        let slots_type = LLVMPointerType(LLVMInt64TypeInContext(context.llvm_context), 0);
+        let mut param_types = [slots_type, slots_type];
+        let entry_type = LLVMFunctionType(
+            LLVMVoidTypeInContext(context.llvm_context),
+            param_types.as_mut_ptr(),
+            2,
+            false as LLVMBool,
+        );
+        let mut entry_name = STACK_ENTRY.to_string();
+        let entry = LLVMAddFunction(context.module, entry_name.c_str(), entry_type);
+        let entry_bb =
+            LLVMAppendBasicBlockInContext(context.llvm_context, entry, "entry\0".c_str());
+        LLVMPositionBuilderAtEnd(context.builder, entry_bb);
+
+        // Returns a pointer to the `index`th slot in `slots`, cast to a pointer to `typ`
+        let slot_ptr = |context: &mut Context, slots: LLVMValueRef, index: usize, typ| {
+            let mut index = LLVMConstInt(
+                LLVMInt64TypeInContext(context.llvm_context),
+                index as u64,
+                false as LLVMBool,
+            );
+            let slot = LLVMBuildGEP(context.builder, slots, &mut index, 1, "slot\0".c_str());
+            let element_type = context.get_llvm_type(typ);
+            LLVMBuildBitCast(
+                context.builder,
+                slot,
+                LLVMPointerType(element_type, 0),
+                "slot_ptr\0".c_str(),
+            )
+        };
+
+        let inputs = LLVMGetParam(entry, 0);
+        let mut args = Vec::new();
+        for (i, input_type) in typ.inputs.iter().enumerate() {
+            let ptr = slot_ptr(context, inputs, i, input_type);
+            args.push(LLVMBuildLoad(context.builder, ptr, "input\0".c_str()));
+        }
+        let result = LLVMBuildCall(
+            context.builder,
+            called.function_value,
+            args.as_mut_ptr(),
+            args.len() as u32,
+            "\0".c_str(),
+        );
+
+        let outputs = LLVMGetParam(entry, 1);
+        for (i, output_type) in typ.outputs.iter().enumerate() {
+            let value = match typ.outputs.len() {
+                1 => result,
+                _ => LLVMBuildExtractValue(context.builder, result, i as u32, "\0".c_str()),
+            };
+            let ptr = slot_ptr(context, outputs, i, output_type);
+            LLVMBuildStore(context.builder, value, ptr);
+        }
+        LLVMBuildRetVoid(context.builder);
+    }
 }
 
 impl<'a> ModuleVisitor for ModuleCodeGen<'a> {
     type ItemResult = ();
-    type FinalOutput = String;
+    type FinalOutput = Result<CompiledModule, Vec<CodeGenError>>;
 
+    // Generic functions are declared along with each copy of their implementation
     fn visit_decl(&mut self, function: &mut FunctionDecl) {
-        if !function.is_intrinsic {
+        if !function.is_intrinsic && !function.head.typ.is_generic() {
             unsafe {
                 self.context
-                    .create_function_decl(&function.head, function.is_extern);
+                    .create_function_decl(&function.head, function.is_pub, function.is_extern);
             }
         }
     }
@@ -64,7 +153,8 @@ impl<'a> ModuleVisitor for ModuleCodeGen<'a> {
             {
                 self.context.generated_functions[&function.head.name]
             } else {
-                self.context.create_function_decl(&function.head, false)
+                self.context
+                    .create_function_decl(&function.head, function.is_pub, false)
             };
 
             let entry_bb = LLVMAppendBasicBlockInContext(
@@ -99,6 +189,10 @@ impl<'a> ModuleVisitor for ModuleCodeGen<'a> {
             .walk(&mut function.body);
 
             match function.head.typ.outputs.len() {
+                0 if function.head.name == ENTRY_POINT => LLVMBuildRet(
+                    self.context.builder,
+                    LLVMConstInt(LLVMInt32TypeInContext(self.context.llvm_context), 0, 0),
+                ),
                 0 => LLVMBuildRetVoid(self.context.builder),
 
                 // If only a single return, we can just return the value directly
@@ -135,18 +229,85 @@ impl<'a> ModuleVisitor for ModuleCodeGen<'a> {
                 }
             };
 
-            LLVMVerifyFunction(
+            let is_broken = LLVMVerifyFunction(
                 self.context.generated_functions[&function.head.name].function_value,
-                analysis::LLVMVerifierFailureAction::LLVMPrintMessageAction,
+                analysis::LLVMVerifierFailureAction::LLVMReturnStatusAction,
             );
+            if is_broken != 0 {
+                self.errors.push(CodeGenError {
+                    function: function.head.name.clone(),
+                    span: function.head.name_span,
+                });
+            }
         }
     }
 
-    fn finalize(self) -> String {
+    fn visit_const(&mut self, constant: &mut Const) {
+        let value = constant
+            .value
+            .clone()
+            .expect("constants are evaluated during typechecking");
+        self.context.constants.insert(constant.name.clone(), value);
+    }
+
+    fn visit_macro(&mut self, _: &mut Macro) {}
+
+    // The struct type of a record is created when it is first used, and its getters and setters
+    // are generated as intrinsics
+    fn visit_record(&mut self, _: &mut Record) {}
+
+    fn visit_import(&mut self, _: &mut Import) {}
+
+    // Constants and the bodies of inline and generic functions are collected before anything
+    // else, since functions earlier in the module may use them
+    fn walk(mut self, module: &mut [TopLevelItem]) -> Self::FinalOutput {
+        for item in module.iter_mut() {
+            match item {
+                TopLevelItem::Const(constant) => self.visit_const(constant),
+                TopLevelItem::Impl(function) => {
+                    if function.is_inline {
+                        self.context
+                            .inline_functions
+                            .insert(function.head.name.clone(), function.body.clone());
+                    }
+                    if function.head.typ.is_generic() {
+                        self.context
+                            .generic_functions
+                            .insert(function.head.name.clone(), function.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        for item in module {
+            match item {
+                TopLevelItem::Decl(f_decl) => self.visit_decl(f_decl),
+                TopLevelItem::Impl(f_impl) if f_impl.head.typ.is_generic() => {}
+                TopLevelItem::Impl(f_impl) => self.visit_impl(f_impl),
+                TopLevelItem::Macro(macro_) => self.visit_macro(macro_),
+                TopLevelItem::Record(record) => self.visit_record(record),
+                TopLevelItem::Import(import) => self.visit_import(import),
+                TopLevelItem::Const(_) => {}
+            }
+        }
+        // Generating a copy of a generic function can declare copies of the functions it calls
+        while let Some(mut function) = self.context.specializations.pop() {
+            self.visit_impl(&mut function);
+        }
+        self.finalize()
+    }
+
+    fn finalize(mut self) -> Result<CompiledModule, Vec<CodeGenError>> {
         unsafe {
-            CStr::from_ptr(LLVMPrintModuleToString(self.context.module))
-                .to_string_lossy()
-                .into_owned()
+            if let Some(function) = self.stack_entry.take() {
+                self.build_stack_entry(&function);
+            }
+            LLVMDisposeBuilder(self.context.builder);
+            let module = CompiledModule::new(self.context.llvm_context, self.context.module);
+            if !self.errors.is_empty() {
+                return Err(self.errors);
+            }
+            Ok(module)
         }
     }
 }
@@ -0,0 +1,48 @@
+use llvm::prelude::*;
+use llvm::transforms::instcombine::LLVMAddInstructionCombiningPass;
+use llvm::transforms::ipo::*;
+use llvm::transforms::scalar::*;
+use llvm::transforms::util::LLVMAddPromoteMemoryToRegisterPass;
+use llvm_sys as llvm;
+
+use crate::cli::OptLevel;
+
+/// Adds the passes for `opt_level` to a module pass manager. Each level runs everything the
+/// previous one does, followed by some more expensive passes.
+pub(super) unsafe fn add_passes(pass_manager: LLVMPassManagerRef, opt_level: OptLevel) {
+    if opt_level == OptLevel::O0 {
+        return;
+    }
+
+    // Codegen keeps multiple return values in allocas, these are turned into SSA values first so
+    // the other passes can see through them
+    LLVMAddPromoteMemoryToRegisterPass(pass_manager);
+    LLVMAddInstructionCombiningPass(pass_manager);
+    LLVMAddReassociatePass(pass_manager);
+    LLVMAddCFGSimplificationPass(pass_manager);
+    if opt_level == OptLevel::O1 {
+        return;
+    }
+
+    // Most words are small, so inlining them exposes most of the remaining optimizations
+    LLVMAddFunctionInliningPass(pass_manager);
+    LLVMAddEarlyCSEPass(pass_manager);
+    LLVMAddGVNPass(pass_manager);
+    LLVMAddInstructionCombiningPass(pass_manager);
+    LLVMAddCFGSimplificationPass(pass_manager);
+    LLVMAddGlobalDCEPass(pass_manager);
+    if opt_level == OptLevel::O2 {
+        return;
+    }
+
+    LLVMAddIPSCCPPass(pass_manager);
+    LLVMAddTailCallEliminationPass(pass_manager);
+    LLVMAddLICMPass(pass_manager);
+    LLVMAddLoopUnrollPass(pass_manager);
+    LLVMAddGVNPass(pass_manager);
+    LLVMAddDeadStoreEliminationPass(pass_manager);
+    LLVMAddAggressiveDCEPass(pass_manager);
+    LLVMAddInstructionCombiningPass(pass_manager);
+    LLVMAddCFGSimplificationPass(pass_manager);
+    LLVMAddGlobalDCEPass(pass_manager);
+}
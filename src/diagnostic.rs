@@ -0,0 +1,326 @@
+use std::fmt::Write;
+
+use crate::ast::{ExpansionId, FileId, Span};
+
+pub struct SourceFile {
+    pub name: String,
+    // Name of the module the file's items are defined in, eg. `io` for `lib/io.f`
+    pub module: String,
+    pub source: String,
+}
+
+/// One use of a macro, whose words were copied from the macro's body
+pub struct Expansion {
+    // Global name of the macro, eg. `std::nip`
+    pub name: String,
+    // Span of the word which used the macro. This may itself be part of another expansion.
+    pub call_site: Span,
+}
+
+/// Holds the contents of every file being compiled, so that diagnostics can show the source
+/// that a Span points into.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    expansions: Vec<Expansion>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: String, module: String, source: String) -> FileId {
+        self.files.push(SourceFile {
+            name,
+            module,
+            source,
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceFile {
+        &self.files[file.0]
+    }
+
+    pub fn add_expansion(&mut self, name: String, call_site: Span) -> ExpansionId {
+        self.expansions.push(Expansion { name, call_site });
+        ExpansionId(self.expansions.len() - 1)
+    }
+
+    pub fn expansion(&self, expansion: ExpansionId) -> &Expansion {
+        &self.expansions[expansion.0]
+    }
+
+    /// Describes each macro use that `span` was copied through, innermost first
+    fn expansion_notes(&self, span: Span) -> Vec<String> {
+        let mut notes = Vec::new();
+        let mut current = span.expansion;
+        while let Some(id) = current {
+            let expansion = self.expansion(id);
+            let call_site = expansion.call_site;
+            notes.push(format!(
+                "in this expansion of macro `{}`, used at {}:{}",
+                expansion.name,
+                self.get(call_site.file).name,
+                call_site
+            ));
+            current = call_site.expansion;
+        }
+        notes
+    }
+}
+
+/// An error message which can be rendered with a snippet of the source it refers to, eg:
+/// ```text
+/// error: expected a `i` on the stack for `+`, but got a `f`
+///  --> main.f:3:9
+///   |
+/// 3 |     1.0 +
+///   |         ^ expected `i i`, stack has `f`
+///   |
+///   = help: ...
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    // Shown next to the caret underline
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = String::new();
+        writeln!(out, "error: {}", self.message).unwrap();
+
+        let span = match self.span {
+            Some(span) => span,
+            None => {
+                for note in &self.notes {
+                    writeln!(out, "  = note: {}", note).unwrap();
+                }
+                for help in &self.help {
+                    writeln!(out, "  = help: {}", help).unwrap();
+                }
+                return out;
+            }
+        };
+
+        let file = sources.get(span.file);
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        // Find the full line of source containing the start of the span
+        let line_start = file.source[..span.offset]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let line_end = file.source[span.offset..]
+            .find('\n')
+            .map(|i| span.offset + i)
+            .unwrap_or(file.source.len());
+        let line = file.source[line_start..line_end].trim_end_matches('\r');
+
+        // Only underline the part of the span on the first line. Empty spans still get a caret so
+        // the location is visible.
+        let underline_len = file.source[span.offset..(span.offset + span.len).min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        // Tabs are kept in the padding so the caret lines up with the source line
+        let padding = line
+            .chars()
+            .take(span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        writeln!(
+            out,
+            "{}--> {}:{}:{}",
+            gutter, file.name, span.line, span.column
+        )
+        .unwrap();
+        writeln!(out, "{} |", gutter).unwrap();
+        writeln!(out, "{} | {}", line_number, line).unwrap();
+        write!(out, "{} | {}{}", gutter, padding, "^".repeat(underline_len)).unwrap();
+        if let Some(label) = &self.label {
+            write!(out, " {}", label).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        let mut notes = self.notes.clone();
+        notes.extend(sources.expansion_notes(span));
+        if !notes.is_empty() || !self.help.is_empty() {
+            writeln!(out, "{} |", gutter).unwrap();
+        }
+        for note in &notes {
+            writeln!(out, "{} = note: {}", gutter, note).unwrap();
+        }
+        for help in &self.help {
+            writeln!(out, "{} = help: {}", gutter, help).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> SourceMap {
+        let mut sources = SourceMap::new();
+        sources.add("std.f".into(), "std".into(), "".into());
+        sources.add(
+            "main.f".into(),
+            "main".into(),
+            "a : ;\nb i -> :\n\t1.0 +\n;\n".into(),
+        );
+        sources
+    }
+
+    #[test]
+    fn test_render_snippet() {
+        let span = Span {
+            file: FileId(1),
+            offset: 16,
+            len: 3,
+            line: 3,
+            column: 2,
+            expansion: None,
+        };
+        let rendered = Diagnostic::error("bad thing")
+            .with_span(span)
+            .with_label("here")
+            .with_note("a note")
+            .with_help("some help")
+            .render(&sources());
+        assert_eq!(
+            rendered,
+            "error: bad thing\n \
+             --> main.f:3:2\n  \
+             |\n\
+             3 | \t1.0 +\n  \
+             | \t^^^ here\n  \
+             |\n  \
+             = note: a note\n  \
+             = help: some help\n"
+        );
+    }
+
+    #[test]
+    fn test_render_multiline_span() {
+        let span = Span {
+            file: FileId(1),
+            offset: 6,
+            len: 15,
+            line: 2,
+            column: 1,
+            expansion: None,
+        };
+        let rendered = Diagnostic::error("bad thing")
+            .with_span(span)
+            .render(&sources());
+        assert_eq!(
+            rendered,
+            "error: bad thing\n --> main.f:2:1\n  |\n2 | b i -> :\n  | ^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_empty_span() {
+        let span = Span {
+            file: FileId(1),
+            offset: 4,
+            len: 0,
+            line: 1,
+            column: 5,
+            expansion: None,
+        };
+        let rendered = Diagnostic::error("bad thing")
+            .with_span(span)
+            .render(&sources());
+        assert_eq!(
+            rendered,
+            "error: bad thing\n --> main.f:1:5\n  |\n1 | a : ;\n  |     ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_expansion() {
+        let mut sources = sources();
+        // `+` in `b` was copied into `a`
+        let call_site = Span {
+            file: FileId(1),
+            offset: 4,
+            len: 1,
+            line: 1,
+            column: 5,
+            expansion: None,
+        };
+        let expansion = sources.add_expansion("main::b".into(), call_site);
+        let span = Span {
+            file: FileId(1),
+            offset: 20,
+            len: 1,
+            line: 3,
+            column: 6,
+            expansion: Some(expansion),
+        };
+        let rendered = Diagnostic::error("bad thing")
+            .with_span(span)
+            .with_note("a note")
+            .render(&sources);
+        assert_eq!(
+            rendered,
+            "error: bad thing\n \
+             --> main.f:3:6\n  \
+             |\n\
+             3 | \t1.0 +\n  \
+             | \t    ^\n  \
+             |\n  \
+             = note: a note\n  \
+             = note: in this expansion of macro `main::b`, used at main.f:1:5\n"
+        );
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let rendered = Diagnostic::error("bad thing")
+            .with_note("a note")
+            .render(&sources());
+        assert_eq!(rendered, "error: bad thing\n  = note: a note\n");
+    }
+}
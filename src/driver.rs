@@ -0,0 +1,568 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::ast::{
+    visitor::{ModuleVisitor, ResultModuleVisitor},
+    TopLevelItem,
+};
+use crate::cli::{self, EmitKind, Options};
+use crate::codegen::{module::ModuleCodeGen, CodeGenError, CompiledModule, MachineCodeKind};
+use crate::diagnostic::{Diagnostic, SourceMap};
+use crate::expand::{ExpandError, MacroExpander};
+use crate::loader::{module_name, Loader, STD_FILE};
+use crate::resolve::{NameResolver, ResolveError};
+use crate::typecheck::{FunctionMapBuilder, ModuleTypeChecker, TypeCheckError};
+
+/// Run the full compilation pipeline described by `options`. Returns the status to exit with, which
+/// is the program's own exit code for `run`. On failure, returns the rendered error messages.
+pub fn compile(options: &Options) -> Result<i32, String> {
+    let mut sources = SourceMap::new();
+    compile_sources(options, &mut sources)
+        .map_err(|diagnostics| render_diagnostics(&diagnostics, &sources))
+}
+
+fn compile_sources(options: &Options, sources: &mut SourceMap) -> Result<i32, Vec<Diagnostic>> {
+    let mut loader = Loader::new(options.include_dirs.clone());
+    let mut module = load_sources(options, &mut loader, sources)?;
+
+    NameResolver::new(prelude(options))
+        .resolve(&mut module, sources)
+        .map_err(|errors| {
+            errors
+                .iter()
+                .map(ResolveError::to_diagnostic)
+                .collect::<Vec<_>>()
+        })?;
+    MacroExpander::new()
+        .expand(&mut module, sources)
+        .map_err(|errors| {
+            errors
+                .iter()
+                .map(ExpandError::to_diagnostic)
+                .collect::<Vec<_>>()
+        })?;
+    let types = FunctionMapBuilder::new()
+        .walk_result(&mut module)
+        .map_err(|e| vec![e.to_diagnostic()])?;
+    ModuleTypeChecker::new(&types)
+        .walk(&mut module)
+        .map_err(|errors| {
+            errors
+                .iter()
+                .map(TypeCheckError::to_diagnostic)
+                .collect::<Vec<_>>()
+        })?;
+    let mut compiled = ModuleCodeGen::new(&types)
+        .walk(&mut module)
+        .map_err(|errors| {
+            errors
+                .iter()
+                .map(CodeGenError::to_diagnostic)
+                .collect::<Vec<_>>()
+        })?;
+
+    if options.dump_ir_before_opt {
+        eprintln!("; IR before optimization\n{}", compiled.to_ir());
+    }
+    compiled.optimize(options.opt_level);
+    if options.dump_ir_after_opt {
+        eprintln!("; IR after optimization\n{}", compiled.to_ir());
+    }
+
+    match options.command {
+        cli::Command::Build => {
+            let output = options
+                .output
+                .clone()
+                .unwrap_or_else(|| default_output_path(&options.inputs[0], options.emit));
+            emit(&compiled, options, &output).map(|_| 0)
+        }
+        cli::Command::Repl => unreachable!("the REPL does not use the compilation pipeline"),
+        cli::Command::Run => {
+            let output = options
+                .output
+                .clone()
+                .unwrap_or_else(|| temp_path(options.emit.extension()));
+            let status = emit(&compiled, options, &output)
+                .and_then(|_| run_executable(&output, &options.run_args));
+            // The executable is only kept if it was given a path with `-o`
+            if options.output.is_none() {
+                let _ = fs::remove_file(&output);
+            }
+            status
+        }
+    }
+    .map_err(|message| vec![Diagnostic::error(message)])
+}
+
+pub fn render_diagnostics(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+    let mut rendered = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(sources))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if diagnostics.len() > 1 {
+        rendered.push_str(&format!(
+            "\nerror: could not compile due to {} previous errors\n",
+            diagnostics.len()
+        ));
+    }
+    rendered
+}
+
+/// The modules which are implicitly imported by every module
+pub fn prelude(options: &Options) -> Vec<String> {
+    if options.include_std {
+        vec![module_name(STD_FILE)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Loads the standard library (unless disabled), every input file, and everything they import
+pub fn load_sources(
+    options: &Options,
+    loader: &mut Loader,
+    sources: &mut SourceMap,
+) -> Result<Vec<TopLevelItem>, Vec<Diagnostic>> {
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+
+    let std = options
+        .include_std
+        .then(|| loader.load_builtin(STD_FILE, sources));
+    let inputs = options
+        .inputs
+        .iter()
+        .map(|path| loader.load_file(path, sources))
+        .collect::<Vec<_>>();
+    for result in std.into_iter().chain(inputs) {
+        match result {
+            Ok(loaded) => items.extend(loaded),
+            Err(diagnostics) => errors.extend(diagnostics),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(items)
+    } else {
+        Err(errors)
+    }
+}
+
+fn default_output_path(first_input: &Path, emit: EmitKind) -> PathBuf {
+    let stem = first_input
+        .file_stem()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("out"));
+    stem.with_extension(emit.extension())
+}
+
+/// A path in the temp directory which is not used by any other call
+fn temp_path(extension: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir()
+        .join(format!("myforth-{}-{}", std::process::id(), count))
+        .with_extension(extension)
+}
+
+fn emit(module: &CompiledModule, options: &Options, output: &Path) -> Result<(), String> {
+    match options.emit {
+        EmitKind::LlvmIr => module.write_ir(output),
+        EmitKind::Bitcode => module.write_bitcode(output),
+        EmitKind::Object => {
+            module.write_machine_code(output, MachineCodeKind::Object, options.opt_level)
+        }
+        EmitKind::Assembly => {
+            module.write_machine_code(output, MachineCodeKind::Assembly, options.opt_level)
+        }
+        EmitKind::Executable => {
+            let object_path = temp_path("o");
+            let result = module
+                .write_machine_code(&object_path, MachineCodeKind::Object, options.opt_level)
+                .and_then(|_| link(&object_path, output));
+            let _ = fs::remove_file(&object_path);
+            result
+        }
+    }
+}
+
+/// Links an object file with the C runtime, using the system's C compiler driver as the linker
+fn link(object: &Path, output: &Path) -> Result<(), String> {
+    let linker = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let linker_out = Command::new(&linker)
+        .arg(object)
+        .arg("-o")
+        .arg(output)
+        .output()
+        .map_err(|e| format!("failed to invoke linker `{}`: {}", linker, e))?;
+
+    if !linker_out.status.success() {
+        return Err(format!(
+            "linker `{}` returned nonzero exit status:\n{}",
+            linker,
+            String::from_utf8_lossy(&linker_out.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the program at `path`, returning its exit code
+fn run_executable(path: &Path, args: &[String]) -> Result<i32, String> {
+    // The program inherits stdio, so its output is visible as it runs
+    let status = Command::new(path)
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to invoke {}: {}", path.display(), e))?;
+    status
+        .code()
+        .ok_or_else(|| format!("{} was terminated by a signal", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::OptLevel;
+
+    fn compile_to(source: &str, emit: EmitKind, opt_level: OptLevel, output: &Path) {
+        let source_path = temp_path("f");
+        fs::write(&source_path, source).unwrap();
+        let options = Options {
+            inputs: vec![source_path.clone()],
+            output: Some(output.to_path_buf()),
+            emit,
+            opt_level,
+            ..Default::default()
+        };
+        let result = compile(&options);
+        let _ = fs::remove_file(&source_path);
+        if let Err(rendered) = result {
+            panic!("{}", rendered);
+        }
+    }
+
+    /// Compiles `source` along with the standard library to an executable, and returns what it
+    /// prints when run
+    fn run_program(source: &str, opt_level: OptLevel) -> String {
+        let executable = temp_path("");
+        compile_to(source, EmitKind::Executable, opt_level, &executable);
+        let output = Command::new(&executable).output().unwrap();
+        let _ = fs::remove_file(&executable);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_run_example() {
+        let output = run_program(include_str!("../examples/fibs.f"), OptLevel::O0);
+        assert!(output.starts_with("0 0\n1 1\n2 1\n"));
+        assert!(output.ends_with("20 6765\n"));
+    }
+
+    #[test]
+    fn test_run_exit_status() {
+        let run = |source| {
+            let source_path = temp_path("f");
+            fs::write(&source_path, source).unwrap();
+            let options = Options {
+                command: cli::Command::Run,
+                inputs: vec![source_path.clone()],
+                ..Default::default()
+            };
+            let result = compile(&options);
+            let _ = fs::remove_file(&source_path);
+            result
+        };
+        assert_eq!(
+            run("extern main; extern exit i -> ; main : 3 exit ;"),
+            Ok(3)
+        );
+        // A main without a result exits successfully
+        assert_eq!(run("extern main; main : 3 drop ;"), Ok(0));
+    }
+
+    #[test]
+    fn test_opt_levels() {
+        let source = include_str!("../examples/fibs.f");
+        let unoptimized = run_program(source, OptLevel::O0);
+        for opt_level in [OptLevel::O1, OptLevel::O2, OptLevel::O3] {
+            assert_eq!(run_program(source, opt_level), unoptimized);
+        }
+
+        // mem2reg removes the allocas used to return multiple values
+        let source = "extern main; pair -> i i : 1 2 ; main : pair + iprintln ;";
+        let ir_at = |opt_level| {
+            let ir_path = temp_path("ll");
+            compile_to(source, EmitKind::LlvmIr, opt_level, &ir_path);
+            let ir = fs::read_to_string(&ir_path).unwrap();
+            let _ = fs::remove_file(&ir_path);
+            ir
+        };
+        assert!(ir_at(OptLevel::O0).contains("alloca"));
+        assert!(!ir_at(OptLevel::O1).contains("alloca"));
+    }
+
+    #[test]
+    fn test_constants() {
+        let source = "extern main; main : area iprintln side 1 - iprintln ;
+            let side 6;
+            let area : side side *;";
+        assert_eq!(run_program(source, OptLevel::O0), "36\n5\n");
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = "extern main; main : 1 2 nip iprintln 3 4 tuck iprint iprint iprintln ;";
+        assert_eq!(run_program(source, OptLevel::O0), "2\n434\n");
+    }
+
+    #[test]
+    fn test_inline() {
+        let source = "extern main; inline twice i -> i : 2 * ; main : 4 twice inc iprintln ;";
+        assert_eq!(run_program(source, OptLevel::O0), "9\n");
+
+        // Inline functions are not called, even without optimization
+        let ir_path = temp_path("ll");
+        compile_to(source, EmitKind::LlvmIr, OptLevel::O0, &ir_path);
+        let ir = fs::read_to_string(&ir_path).unwrap();
+        let _ = fs::remove_file(&ir_path);
+        // `twice` is still defined, but the only function `main` calls is `iprintln`
+        assert!(ir.contains("::twice\"(i32"));
+        let main = ir.split("define i32 @main()").nth(1).unwrap();
+        let main = &main[..main.find("\n}").unwrap()];
+        assert!(main
+            .lines()
+            .filter(|line| line.contains("call"))
+            .all(|line| line.contains("call void @\"std::iprintln\"(")));
+
+        // A recursive call is inlined once, then called normally
+        let source = "extern main;
+            inline countdown i -> : dup 0 > ? dup iprintln 1 - countdown : drop ; ;
+            main : 3 countdown ;";
+        assert_eq!(run_program(source, OptLevel::O0), "3\n2\n1\n");
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let source = "extern main; main :
+            0xFF iprintln 0b1010 iprintln 0o17 iprintln 1_000 iprintln 'a' (i) iprintln
+            10q (i) 5 + iprintln 255uc (i) iprintln 3.75d (i) iprintln ;";
+        assert_eq!(
+            run_program(source, OptLevel::O0),
+            "255\n10\n15\n1000\n97\n15\n255\n3\n"
+        );
+    }
+
+    #[test]
+    fn test_negative_literals() {
+        // Casting to `i` sign extends signed types and zero extends unsigned ones
+        let source = "extern main; main :
+            -5 iprintln -2147483648 iprintln 2147483647 iprintln -128c (i) iprintln
+            255uc (i) iprintln '\\xff' (i) iprintln -7q (i) iprintln 4294967295ui (i) iprintln
+            -9223372036854775808q (i) iprintln 0xFFFF_FFFF_FFFF_FFFFuq (i) iprintln ;";
+        let expected = "-5\n-2147483648\n2147483647\n-128\n255\n-1\n-7\n-1\n0\n-1\n";
+        assert_eq!(run_program(source, OptLevel::O0), expected);
+        assert_eq!(run_program(source, OptLevel::O2), expected);
+    }
+
+    #[test]
+    fn test_literal_inference() {
+        // Literals take the type of the parameter they are passed to, with no cast needed
+        let source = "extern main; qprintln q -> : (i) iprintln ; cprintln c -> : (i) iprintln ;
+            main : 7 qprintln 'a' cprintln true ? 3 : 4 ; qprintln ;";
+        assert_eq!(run_program(source, OptLevel::O0), "7\n97\n3\n");
+    }
+
+    #[test]
+    fn test_unsigned_arithmetic() {
+        // Unsigned types divide, shift and compare as unsigned, signed types as signed
+        let source = "extern main; main :
+            -1 (ui) 2ui / (i) iprintln 4294967295ui 2 / (i) iprintln
+            250uc 7uc % (i) iprintln 128uc 4uc >> (i) iprintln
+            200uc 100uc > ? 1 : 0 ; iprintln -1 (ui) 1ui < ? 1 : 0 ; iprintln
+            -7 2 / iprintln -7 2 % iprintln -128c 4c >> (i) iprintln -1 1 < ? 1 : 0 ; iprintln ;";
+        assert_eq!(
+            run_program(source, OptLevel::O0),
+            "2147483647\n2147483647\n5\n8\n1\n0\n-3\n-1\n-8\n1\n"
+        );
+    }
+
+    #[test]
+    fn test_argument_order() {
+        let source = "extern main; sub i i -> i : - ;
+            digits i i i -> : rot iprint swap iprint iprintln ;
+            main : 10 3 sub iprintln 1 2 3 digits ;";
+        assert_eq!(run_program(source, OptLevel::O0), "7\n123\n");
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let source = "extern main; main :
+            1.0 2.5 + fprintln -3.75d dprintln 7.0 2.0 / fprintln 7.5 2.0 % fprintln 0.1 fprintln
+            1.0 2.0 < ? 1 : 0 ; iprint 2.0d 2.0d = ? 1 : 0 ; iprint 1.0 1.0 != ? 1 : 0 ; iprint
+            0.0 0.0 / dup = ? 1 : 0 ; iprint 0.0d 0.0d / dup != ? 1 : 0 ; iprintln
+            -9223372036854775807q 1q - qprintln ;";
+        assert_eq!(
+            run_program(source, OptLevel::O0),
+            "3.500000\n-3.750000\n3.500000\n1.500000\n0.100000\n11001\n-9223372036854775808\n"
+        );
+    }
+
+    #[test]
+    fn test_pointers() {
+        let source = "extern main;
+            swap-values *i *i -> : over deref over deref rot swap store store ;
+            main :
+                alloca<q> dup 10000000000 store deref qprintln
+                3 ref 4 ref dup2 swap-values deref iprint deref iprintln
+                alloca<*d> dup 2.5d ref store deref deref dprintln
+                alloca<i> dup 2 ptr+ dup2 ptr< ? 1 : 0 ; iprint ptr= ? 1 : 0 ; iprintln
+                \"abc\" 2 ptr+ deref (i) putchar nl
+                0 @ dup 3 < : dup ref deref iprint 1 + ; drop nl ;";
+        assert_eq!(
+            run_program(source, OptLevel::O0),
+            "10000000000\n43\n2.500000\n10\nc\n012\n"
+        );
+    }
+
+    #[test]
+    fn test_pointer_casts() {
+        // Reads the bytes and halves of a `q` through pointers to smaller types
+        let source = "extern main; main :
+            alloca<q> dup 0x0102030405060708q store
+            dup (*uc) deref (i) iprintln dup (*i) 1 ptr+ deref iprintln
+            dup (q) 4q + (*c) deref (i) iprintln dup (uq) (*q) deref qprintln drop ;";
+        assert_eq!(
+            run_program(source, OptLevel::O0),
+            "8\n16909060\n4\n72623859790382856\n"
+        );
+    }
+
+    #[test]
+    fn test_generic_casts() {
+        // `view` reads a `q` through a pointer to the type its first input points to
+        let source = "extern main;
+            view *'T *q -> *'T : swap drop (*'T) ;
+            to-int 'T:num -> i : (i) ;
+            main : alloca<q> dup 0x0102030405060708q store
+                alloca<uc> swap view deref (i) iprintln 2.5d to-int iprintln ;";
+        assert_eq!(run_program(source, OptLevel::O0), "8\n2\n");
+    }
+
+    #[test]
+    fn test_records() {
+        // `main` uses the records before they are defined. A `line` holds `point`s, and a pointer
+        // to another `line`.
+        let source = "extern main;
+            make-point i i -> point : swap construct<point> swap >point.x swap >point.y ;
+            print-point point -> : .point.x iprint 32 putchar .point.y iprintln drop ;
+            main :
+                3 4 make-point dup print-point
+                construct<line> swap >line.start 5 6 make-point >line.end .line.end print-point
+                ref construct<line> swap >line.next
+                .line.next deref .line.start print-point drop drop
+                construct<point> .point.x swap .point.y nip + iprintln ;
+            record point : x i , y i , ;
+            record line : start point , end point , next *line , ;";
+        for opt_level in [OptLevel::O0, OptLevel::O2] {
+            assert_eq!(run_program(source, opt_level), "3 4\n5 6\n3 4\n0\n");
+        }
+    }
+
+    #[test]
+    fn test_records_in_modules() {
+        // Each module has its own `point`, so they are qualified where both are imported
+        let dir = temp_path("");
+        fs::create_dir(&dir).unwrap();
+        let files = [
+            (
+                "shapes.f",
+                "pub record point : x i , ; pub origin -> point : construct<point> ;",
+            ),
+            (
+                "grid.f",
+                "pub record point : row i , col i , ;
+                pub cell i i -> point : swap construct<point> swap >point.row swap >point.col ;",
+            ),
+            (
+                "main.f",
+                "use shapes; use grid; extern main;
+                print-col grid::point -> : grid::.point.col iprintln drop ;
+                main :
+                    shapes::origin 7 shapes::>point.x shapes::.point.x iprintln drop
+                    1 2 grid::cell print-col ;",
+            ),
+        ];
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        let executable = dir.join("main");
+        let options = Options {
+            inputs: vec![dir.join("main.f")],
+            output: Some(executable.clone()),
+            ..Default::default()
+        };
+        let output = compile(&options).map(|_| Command::new(&executable).output().unwrap());
+        let _ = fs::remove_dir_all(&dir);
+        match output {
+            Ok(output) => assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\n2\n"),
+            Err(rendered) => panic!("{}", rendered),
+        }
+    }
+
+    #[test]
+    fn test_generic_arithmetic() {
+        let source = "extern main; main :
+            10q 3q - (i) iprintln 2.5 2.0 * (i) iprintln 7.5d 2.0d / (i) iprintln
+            250uc 10uc + (i) iprintln 1uq 40uq << 38uq >> (i) iprintln ;";
+        assert_eq!(run_program(source, OptLevel::O0), "7\n5\n3\n4\n4\n");
+    }
+
+    #[test]
+    fn test_generic_functions() {
+        let source = "extern main;
+            add 'T:num 'T:num -> 'T:num : + ;
+            id 'T -> 'T : dup drop ;
+            rem 'T:int 'T -> 'T : over over < ? drop : swap over - swap rem ; ;
+            inline twice 'T:num -> 'T : dup add ;
+            main : 1 2 add iprintln 1.5 2.25 add fprintln 7q id (i) iprintln
+                17 5 rem iprintln 5uc twice (i) iprintln ;";
+        assert_eq!(run_program(source, OptLevel::O0), "3\n3.750000\n7\n2\n10\n");
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let source = "extern puts *c -> i; extern main;
+            main : \"tab\\there\\x21 \\\"quoted\\\"\" puts drop \"\" puts drop ;";
+        assert_eq!(
+            run_program(source, OptLevel::O0),
+            "tab\there! \"quoted\"\n\n"
+        );
+    }
+
+    #[test]
+    fn test_emit_kinds() {
+        let source = "extern main; main : 42 iprintln ;";
+        for emit in [
+            EmitKind::LlvmIr,
+            EmitKind::Bitcode,
+            EmitKind::Object,
+            EmitKind::Assembly,
+        ] {
+            let output = temp_path(emit.extension());
+            compile_to(source, emit, OptLevel::O0, &output);
+            let contents = fs::read(&output).unwrap();
+            let _ = fs::remove_file(&output);
+            match emit {
+                EmitKind::LlvmIr => assert!(contents.starts_with(b"; ModuleID")),
+                EmitKind::Bitcode => assert!(contents.starts_with(b"BC")),
+                EmitKind::Object => assert!(contents.starts_with(b"\x7fELF")),
+                _ => assert!(String::from_utf8(contents).unwrap().contains("main:")),
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandError {
+    pub kind: ExpandErrorKind,
+    pub span: Span,
+}
+
+impl ExpandError {
+    pub fn new(kind: ExpandErrorKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpandErrorKind {
+    // A macro which uses itself. The chain starts and ends with the same macro, eg. `[a, b, a]`.
+    Recursive(Vec<String>),
+    // A macro with the same name as another macro, function or constant
+    Redefinition(String),
+}
+
+impl fmt::Display for ExpandErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpandErrorKind::Recursive(chain) => {
+                write!(f, "macro `{}` expands to itself", chain[0])
+            }
+            ExpandErrorKind::Redefinition(name) => {
+                write!(f, "macro `{}` is already defined", name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl std::error::Error for ExpandError {}
+
+impl ExpandError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string()).with_span(self.span);
+        match &self.kind {
+            ExpandErrorKind::Recursive(chain) => diagnostic
+                .with_label("used again here")
+                .with_note(format!("the expansion is {}", chain.join(" -> ")))
+                .with_help(
+                    "macros are copied into place, so they cannot use themselves. \
+                     Use a function instead.",
+                ),
+            ExpandErrorKind::Redefinition(_) => diagnostic.with_label("defined again here"),
+        }
+    }
+}
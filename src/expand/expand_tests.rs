@@ -0,0 +1,160 @@
+use super::*;
+use crate::diagnostic::SourceMap;
+use crate::parser::parse_module;
+use crate::resolve::NameResolver;
+
+/// Parses and resolves each `(module, source)` pair as a separate file, then expands the macros
+/// in all of them
+fn expand_files(
+    files: &[(&str, &str)],
+) -> (Result<Vec<TopLevelItem>, Vec<ExpandError>>, SourceMap) {
+    let mut sources = SourceMap::new();
+    let mut items = Vec::new();
+    for (module, source) in files {
+        let file = sources.add(
+            format!("{}.f", module),
+            module.to_string(),
+            source.to_string(),
+        );
+        items.extend(parse_module(&sources.get(file).source, file).unwrap());
+    }
+    NameResolver::new(vec!["std".to_string()])
+        .resolve(&mut items, &sources)
+        .unwrap();
+    let expanded = MacroExpander::new()
+        .expand(&mut items, &mut sources)
+        .map(|_| items);
+    (expanded, sources)
+}
+
+/// Every word in `block` after expansion, with the contents of if and while statements flattened
+fn word_names(block: &CodeBlock) -> Vec<String> {
+    let mut names = Vec::new();
+    for word in &block.words {
+        match word {
+            Word::IntLiteral(literal) => match &literal.suffix {
+                Some(suffix) => names.push(format!("{}{}", literal.value, suffix)),
+                None => names.push(literal.value.to_string()),
+            },
+            Word::FloatLiteral(n, typ, _) => names.push(format!("{}{}", n, typ)),
+            Word::BoolLiteral(n, _) => names.push(n.to_string()),
+            Word::StringLiteral(s, _) => names.push(format!("{:?}", s)),
+            Word::Cast(to, _) => names.push(format!("({})", to)),
+            Word::FunctionCall(call) => names.push(call.name.clone()),
+            Word::IfStatement(statement) => {
+                names.push("?".to_string());
+                names.extend(word_names(&statement.true_branch));
+                names.push(":".to_string());
+                names.extend(word_names(&statement.false_branch));
+            }
+            Word::WhileStatement(statement) => {
+                names.push("@".to_string());
+                names.extend(word_names(&statement.condition));
+                names.push(":".to_string());
+                names.extend(word_names(&statement.body));
+            }
+        }
+    }
+    names
+}
+
+/// The body of the last function in the files, after expansion
+fn last_body(items: &[TopLevelItem]) -> &CodeBlock {
+    match items.last() {
+        Some(TopLevelItem::Impl(function)) => &function.body,
+        other => panic!("expected a function implementation, got {:?}", other),
+    }
+}
+
+fn expand_err(files: &[(&str, &str)]) -> ExpandErrorKind {
+    expand_files(files).0.unwrap_err().remove(0).kind
+}
+
+const STD: (&str, &str) = (
+    "std",
+    "pub intrinsic swap 'T 'U -> 'U 'T; pub intrinsic drop 'T -> ; helper : ; \
+     pub macro nip : swap drop ; pub macro twice : helper helper ;",
+);
+
+#[test]
+fn test_expand() {
+    let (items, _) = expand_files(&[STD, ("main", "a : 1 2 nip true ? nip : twice ; ;")]);
+    assert_eq!(
+        word_names(last_body(&items.unwrap())),
+        vec![
+            "1",
+            "2",
+            "swap",
+            "drop",
+            "true",
+            "?",
+            "swap",
+            "drop",
+            ":",
+            "std::helper",
+            "std::helper"
+        ]
+    );
+
+    // Macros may use other macros, and functions in the current module shadow imported macros
+    let (items, _) = expand_files(&[
+        STD,
+        (
+            "main",
+            "macro inner : 1 nip ; macro outer : @ true : inner ; ; a : outer ; nip : ;",
+        ),
+    ]);
+    let items = items.unwrap();
+    let a = match &items[items.len() - 2] {
+        TopLevelItem::Impl(function) => &function.body,
+        other => panic!("expected a function implementation, got {:?}", other),
+    };
+    assert_eq!(word_names(a), vec!["@", "true", ":", "1", "main::nip"]);
+}
+
+#[test]
+fn test_expansion_spans() {
+    let (items, sources) = expand_files(&[STD, ("main", "macro m : nip ; a : m ;")]);
+    let items = items.unwrap();
+    let swap = match &last_body(&items).words[0] {
+        Word::FunctionCall(call) => call.span,
+        other => panic!("expected a function call, got {:?}", other),
+    };
+    // `swap` points into the body of `nip`, which was used by `m`, which was used by `a`
+    assert_eq!(sources.get(swap.file).name, "std.f");
+    let nip = sources.expansion(swap.expansion.unwrap());
+    assert_eq!(nip.name, "std::nip");
+    assert_eq!(sources.get(nip.call_site.file).name, "main.f");
+    let m = sources.expansion(nip.call_site.expansion.unwrap());
+    assert_eq!(m.name, "main::m");
+    assert_eq!(m.call_site.expansion, None);
+    assert_eq!((m.call_site.line, m.call_site.column), (1, 21));
+}
+
+#[test]
+fn test_expand_errors() {
+    assert_eq!(
+        expand_err(&[STD, ("main", "macro a : 1 a ;")]),
+        ExpandErrorKind::Recursive(vec!["main::a".to_string(), "main::a".to_string()])
+    );
+    assert_eq!(
+        expand_err(&[STD, ("main", "macro a : e ; macro e : true ? a : ; ;")]),
+        ExpandErrorKind::Recursive(vec![
+            "main::a".to_string(),
+            "main::e".to_string(),
+            "main::a".to_string()
+        ])
+    );
+    assert_eq!(
+        expand_err(&[STD, ("main", "macro a : ; a : ;")]),
+        ExpandErrorKind::Redefinition("main::a".to_string())
+    );
+    assert_eq!(
+        expand_err(&[STD, ("main", "macro a : ; macro a : 1 ;")]),
+        ExpandErrorKind::Redefinition("main::a".to_string())
+    );
+
+    // Each macro in a cycle is reported once
+    let (errors, _) = expand_files(&[STD, ("main", "macro a : e ; macro e : a ; g : a ;")]);
+    assert_eq!(errors.unwrap_err().len(), 2);
+}
@@ -0,0 +1,195 @@
+mod error;
+#[cfg(test)]
+mod expand_tests;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{CodeBlock, ExpansionId, TopLevelItem, Word};
+use crate::diagnostic::SourceMap;
+
+pub use error::{ExpandError, ExpandErrorKind};
+
+/// Replaces each use of a macro with a copy of the macro's body. This runs after name resolution,
+/// so words in the body already refer to what they would in the macro's own module, and before
+/// typechecking, so the copied words are checked as part of the body they were copied into.
+///
+/// Copied words keep the spans of the macro's body, so errors in them point at the macro. Each
+/// span is tagged with an expansion (see `SourceMap::add_expansion`), which records where the
+/// macro was used.
+#[derive(Debug, Clone, Default)]
+pub struct MacroExpander {
+    // The unexpanded body of every macro defined so far, by global name
+    macros: HashMap<String, CodeBlock>,
+    // Every function and constant defined so far, which macros may not share a name with
+    names: HashSet<String>,
+}
+
+impl MacroExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the macros of `items`, then expands every use of a macro in them. Macros added by
+    /// previous calls can be used by later ones.
+    pub fn expand(
+        &mut self,
+        items: &mut [TopLevelItem],
+        sources: &mut SourceMap,
+    ) -> Result<(), Vec<ExpandError>> {
+        let mut errors = Vec::new();
+        for item in items.iter() {
+            let name = match item {
+                TopLevelItem::Decl(function) => &function.head.name,
+                TopLevelItem::Impl(function) => &function.head.name,
+                TopLevelItem::Const(constant) => &constant.name,
+                TopLevelItem::Record(record) => {
+                    for field in &record.fields {
+                        self.names.insert(record.getter_name(field));
+                        self.names.insert(record.setter_name(field));
+                    }
+                    continue;
+                }
+                TopLevelItem::Macro(_) | TopLevelItem::Import(_) => continue,
+            };
+            self.names.insert(name.clone());
+        }
+        for item in items.iter() {
+            if let TopLevelItem::Macro(macro_) = item {
+                if self.names.contains(&macro_.name) || self.macros.contains_key(&macro_.name) {
+                    errors.push(ExpandError::new(
+                        ExpandErrorKind::Redefinition(macro_.name.clone()),
+                        macro_.name_span,
+                    ));
+                } else {
+                    self.macros.insert(macro_.name.clone(), macro_.body.clone());
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // Macros are checked for recursion first, so expanding the other items always finishes
+        for item in items.iter_mut() {
+            if let TopLevelItem::Macro(macro_) = item {
+                let mut expansion = Expansion::new(&self.macros, sources);
+                expansion.active.push(macro_.name.clone());
+                expansion.expand(&mut macro_.body);
+                errors.extend(expansion.errors);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for item in items {
+            match item {
+                TopLevelItem::Impl(function) => self.expand_block(&mut function.body, sources)?,
+                TopLevelItem::Const(constant) => self.expand_block(&mut constant.body, sources)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands every use of a macro in `block`
+    pub fn expand_block(
+        &self,
+        block: &mut CodeBlock,
+        sources: &mut SourceMap,
+    ) -> Result<(), Vec<ExpandError>> {
+        let mut expansion = Expansion::new(&self.macros, sources);
+        expansion.expand(block);
+        if expansion.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(expansion.errors)
+        }
+    }
+}
+
+/// Expands the macros in a block, recursing into the bodies it copies
+struct Expansion<'a> {
+    macros: &'a HashMap<String, CodeBlock>,
+    sources: &'a mut SourceMap,
+    // The macros currently being expanded, outermost first
+    active: Vec<String>,
+    errors: Vec<ExpandError>,
+}
+
+impl<'a> Expansion<'a> {
+    fn new(macros: &'a HashMap<String, CodeBlock>, sources: &'a mut SourceMap) -> Self {
+        Self {
+            macros,
+            sources,
+            active: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn expand(&mut self, block: &mut CodeBlock) {
+        for word in std::mem::take(&mut block.words) {
+            match word {
+                Word::FunctionCall(call) if self.macros.contains_key(&call.name) => {
+                    if let Some(start) = self.active.iter().position(|name| *name == call.name) {
+                        // A cycle which does not include the outermost macro is reported when
+                        // that macro's own body is expanded
+                        if start == 0 {
+                            let mut chain = self.active.clone();
+                            chain.push(call.name);
+                            self.errors.push(ExpandError::new(
+                                ExpandErrorKind::Recursive(chain),
+                                call.span,
+                            ));
+                        }
+                        continue;
+                    }
+
+                    let expansion = self.sources.add_expansion(call.name.clone(), call.span);
+                    let mut body = self.macros[&call.name].clone();
+                    set_expansion(&mut body, expansion);
+                    self.active.push(call.name);
+                    self.expand(&mut body);
+                    self.active.pop();
+                    block.words.extend(body.words);
+                }
+                Word::IfStatement(mut statement) => {
+                    self.expand(&mut statement.true_branch);
+                    self.expand(&mut statement.false_branch);
+                    block.words.push(Word::IfStatement(statement));
+                }
+                Word::WhileStatement(mut statement) => {
+                    self.expand(&mut statement.condition);
+                    self.expand(&mut statement.body);
+                    block.words.push(Word::WhileStatement(statement));
+                }
+                word => block.words.push(word),
+            }
+        }
+    }
+}
+
+/// Marks every span in a copy of a macro's body as coming from `expansion`
+fn set_expansion(block: &mut CodeBlock, expansion: ExpansionId) {
+    block.span.expansion = Some(expansion);
+    for word in &mut block.words {
+        match word {
+            Word::IntLiteral(literal) => literal.span.expansion = Some(expansion),
+            Word::FloatLiteral(_, _, span)
+            | Word::BoolLiteral(_, span)
+            | Word::StringLiteral(_, span)
+            | Word::Cast(_, span) => span.expansion = Some(expansion),
+            Word::FunctionCall(call) => call.span.expansion = Some(expansion),
+            Word::IfStatement(statement) => {
+                statement.span.expansion = Some(expansion);
+                set_expansion(&mut statement.true_branch, expansion);
+                set_expansion(&mut statement.false_branch, expansion);
+            }
+            Word::WhileStatement(statement) => {
+                statement.span.expansion = Some(expansion);
+                set_expansion(&mut statement.condition, expansion);
+                set_expansion(&mut statement.body, expansion);
+            }
+        }
+    }
+}
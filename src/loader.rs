@@ -0,0 +1,344 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{Span, TopLevelItem};
+use crate::diagnostic::{Diagnostic, SourceMap};
+use crate::parser::parse_module;
+
+/// Files which are built into the compiler, so they can be imported from anywhere. These are
+/// found after everything in the search path.
+const BUILTIN_FILES: &[(&str, &str)] = &[("std.f", include_str!("../std.f"))];
+
+pub const STD_FILE: &str = "std.f";
+
+/// Where a file was loaded from. Each source is only loaded once.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Source {
+    // Canonicalized, so that the same file imported through different paths is deduplicated
+    File(PathBuf),
+    Builtin {
+        name: &'static str,
+        text: &'static str,
+    },
+}
+
+/// The name of the module defined by the file at `path`, which is its file name without the
+/// extension. This is also the name used to qualify words from the module, eg. `io::print`.
+pub fn module_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Loads source files along with everything they import. Imported items are placed before the
+/// items of the importing file, so that functions are always generated before their callers.
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    loaded: HashSet<Source>,
+    // The chain of imports currently being loaded along with their names, used to detect cycles
+    loading: Vec<(Source, String)>,
+    // Maps each module name to the file defining it, since two files with the same name would
+    // otherwise share a namespace
+    modules: HashMap<String, String>,
+}
+
+impl Loader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            loaded: HashSet::new(),
+            loading: Vec::new(),
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Loads a file given on the command line
+    pub fn load_file(
+        &mut self,
+        path: &Path,
+        sources: &mut SourceMap,
+    ) -> Result<Vec<TopLevelItem>, Vec<Diagnostic>> {
+        let source = fs::canonicalize(path).map(Source::File).map_err(|e| {
+            vec![Diagnostic::error(format!(
+                "could not read {}: {}",
+                path.display(),
+                e
+            ))]
+        })?;
+        self.load(source, path.display().to_string(), None, sources)
+    }
+
+    /// Loads one of the files which are built into the compiler, eg. STD_FILE
+    pub fn load_builtin(
+        &mut self,
+        name: &str,
+        sources: &mut SourceMap,
+    ) -> Result<Vec<TopLevelItem>, Vec<Diagnostic>> {
+        let source = find_builtin(name).expect("no such builtin file");
+        self.load(source, name.to_string(), None, sources)
+    }
+
+    /// Loads everything imported by `items`, which were parsed from a file in `dir` (or from
+    /// somewhere with no directory, like the REPL, if `dir` is None). Returns the imported items
+    /// followed by `items`.
+    pub fn resolve_imports(
+        &mut self,
+        items: Vec<TopLevelItem>,
+        dir: Option<&Path>,
+        sources: &mut SourceMap,
+    ) -> Result<Vec<TopLevelItem>, Vec<Diagnostic>> {
+        let mut imported = Vec::new();
+        let mut errors = Vec::new();
+        for item in &items {
+            if let TopLevelItem::Import(import) = item {
+                let result = match self.find(&import.path, dir) {
+                    Some((source, name)) => self.load(source, name, Some(import.span), sources),
+                    None => Err(vec![self.not_found_error(&import.path, dir, import.span)]),
+                };
+                match result {
+                    Ok(items) => imported.extend(items),
+                    Err(diagnostics) => errors.extend(diagnostics),
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        imported.extend(items);
+        Ok(imported)
+    }
+
+    /// Loads `source`, which is shown in diagnostics as `name`
+    fn load(
+        &mut self,
+        source: Source,
+        name: String,
+        import_span: Option<Span>,
+        sources: &mut SourceMap,
+    ) -> Result<Vec<TopLevelItem>, Vec<Diagnostic>> {
+        if let Some(index) = self
+            .loading
+            .iter()
+            .position(|(loading, _)| *loading == source)
+        {
+            let cycle = self.loading[index..]
+                .iter()
+                .map(|(_, name)| name)
+                .chain(std::iter::once(&name))
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            let mut diagnostic = Diagnostic::error(format!("import cycle: {}", cycle));
+            if let Some(span) = import_span {
+                diagnostic = diagnostic.with_span(span).with_label("imported again here");
+            }
+            return Err(vec![diagnostic.with_help(
+                "move the items used by both files into a separate file",
+            )]);
+        }
+        if self.loaded.contains(&source) {
+            return Ok(Vec::new());
+        }
+
+        let text = match &source {
+            Source::File(path) => fs::read_to_string(path).map_err(|e| {
+                let diagnostic =
+                    Diagnostic::error(format!("could not read {}: {}", path.display(), e));
+                vec![match import_span {
+                    Some(span) => diagnostic.with_span(span),
+                    None => diagnostic,
+                }]
+            })?,
+            Source::Builtin { text, .. } => text.to_string(),
+        };
+        let module = module_name(&name);
+        if let Some(other) = self.modules.get(&module) {
+            let mut diagnostic = Diagnostic::error(format!(
+                "module `{}` is defined by both `{}` and `{}`",
+                module, other, name
+            ));
+            if let Some(span) = import_span {
+                diagnostic = diagnostic.with_span(span).with_label("imported here");
+            }
+            return Err(vec![diagnostic.with_help(
+                "modules are named after their file, so one must be renamed",
+            )]);
+        }
+        let file = sources.add(name.clone(), module.clone(), text);
+        let items = parse_module(&sources.get(file).source, file).map_err(|e| vec![e])?;
+
+        // Imports are resolved relative to the path the file was found at, rather than its
+        // canonical path, so that names in diagnostics stay relative too
+        let dir = match &source {
+            Source::File(_) => Path::new(&name).parent().map(Path::to_path_buf),
+            Source::Builtin { .. } => None,
+        };
+        self.modules.insert(module.clone(), name.clone());
+        self.loading.push((source.clone(), name));
+        let result = self.resolve_imports(items, dir.as_deref(), sources);
+        self.loading.pop();
+        if result.is_ok() {
+            self.loaded.insert(source);
+        } else {
+            self.modules.remove(&module);
+        }
+        result
+    }
+
+    /// Looks for `path` relative to `dir`, then in each directory of the search path, then in the
+    /// builtin files. Returns the source along with the path it was found at.
+    fn find(&self, path: &str, dir: Option<&Path>) -> Option<(Source, String)> {
+        let found = dir
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file());
+        match found {
+            Some(found) => fs::canonicalize(&found)
+                .ok()
+                .map(|canonical| (Source::File(canonical), found.display().to_string())),
+            None => find_builtin(path).map(|source| (source, path.to_string())),
+        }
+    }
+
+    fn not_found_error(&self, path: &str, dir: Option<&Path>, span: Span) -> Diagnostic {
+        let searched = dir
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| format!("`{}`", dir.display()))
+            .collect::<Vec<_>>();
+        let mut diagnostic = Diagnostic::error(format!("could not find `{}` to import", path))
+            .with_span(span)
+            .with_label("imported here");
+        if !searched.is_empty() {
+            diagnostic = diagnostic.with_note(format!("searched in {}", searched.join(", ")));
+        }
+        diagnostic.with_help("add a directory to search for imports with `-I`")
+    }
+}
+
+fn find_builtin(name: &str) -> Option<Source> {
+    BUILTIN_FILES
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(name, text)| Source::Builtin { name, text })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::FunctionDecl;
+
+    /// Creates a fresh directory containing the given files
+    fn create_files(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "myforth-loader-{}-{}",
+            std::process::id(),
+            files[0].0.replace('/', "-")
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, text) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn decl_names(items: &[TopLevelItem]) -> Vec<&str> {
+        items
+            .iter()
+            .filter_map(|item| match item {
+                TopLevelItem::Decl(FunctionDecl { head, .. }) => Some(head.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_import_order_and_dedup() {
+        let dir = create_files(&[
+            ("order/main.f", "import \"a.f\"; use b; main;"),
+            ("order/a.f", "use b; a;"),
+            ("order/lib/b.f", "lib-b;"),
+        ]);
+        let mut loader = Loader::new(vec![dir.join("order/lib")]);
+        let mut sources = SourceMap::new();
+        let items = loader
+            .load_file(&dir.join("order/main.f"), &mut sources)
+            .unwrap();
+        assert_eq!(decl_names(&items), vec!["lib-b", "a", "main"]);
+
+        // Already loaded files are not loaded again
+        let items = loader
+            .load_file(&dir.join("order/lib/../a.f"), &mut sources)
+            .unwrap();
+        assert!(items.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_builtin_import() {
+        let dir = create_files(&[("builtin/main.f", "import \"std.f\"; main;")]);
+        let mut loader = Loader::new(Vec::new());
+        let mut sources = SourceMap::new();
+        let std_items = loader.load_builtin(STD_FILE, &mut sources).unwrap();
+        assert!(decl_names(&std_items).contains(&"dup"));
+
+        let items = loader
+            .load_file(&dir.join("builtin/main.f"), &mut sources)
+            .unwrap();
+        assert_eq!(decl_names(&items), vec!["main"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = create_files(&[
+            ("errors/a.f", "use b;"),
+            ("errors/b.f", "use c;"),
+            ("errors/c.f", "use b;"),
+            ("errors/missing.f", "use nothing;"),
+            ("errors/twice.f", "use x; use lib/x;"),
+            ("errors/x.f", ""),
+            ("errors/lib/x.f", ""),
+        ]);
+        let mut loader = Loader::new(Vec::new());
+        let mut sources = SourceMap::new();
+        let errors = loader
+            .load_file(&dir.join("errors/a.f"), &mut sources)
+            .unwrap_err();
+        let b = dir.join("errors/b.f");
+        let c = dir.join("errors/c.f");
+        assert_eq!(
+            errors[0].message,
+            format!(
+                "import cycle: `{}` -> `{}` -> `{}`",
+                b.display(),
+                c.display(),
+                b.display()
+            )
+        );
+
+        let errors = loader
+            .load_file(&dir.join("errors/missing.f"), &mut sources)
+            .unwrap_err();
+        assert_eq!(errors[0].message, "could not find `nothing.f` to import");
+
+        let errors = loader
+            .load_file(&dir.join("errors/twice.f"), &mut sources)
+            .unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            format!(
+                "module `x` is defined by both `{}` and `{}`",
+                dir.join("errors/x.f").display(),
+                dir.join("errors/lib/x.f").display()
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,93 +1,46 @@
-use std::{fs, process::Command};
+// Errors and diagnostics are only constructed on the failure path, so their size does not matter
+#![allow(clippy::result_large_err)]
 
-use ast::visitor::ModuleVisitor;
-use codegen::module::ModuleCodeGen;
-use typecheck::{FunctionMapBuilder, ModuleTypeChecker};
+use std::{env, process};
 
-use crate::parser::module;
+use cli::{Command, ParseArgsResult};
 
 mod ast;
+mod cli;
 mod codegen;
+mod diagnostic;
+mod driver;
+mod expand;
+mod loader;
 mod parser;
+mod repl;
+mod resolve;
 mod typecheck;
 
 fn main() {
-    let test = concat!(
-        include_str!("../std.f"),
-        "
-    extern getchar -> i;
-    extern main;
-
-    pow2 i->i: 1 swap << ;
-
-    powersof2 :
-        1 @ dup 30 < :
-            dup pow2 iprintln
-            inc
-        ;
-        @ dup 1 > :
-            dup pow2 iprintln
-            dec
-        ; drop
-    ;
-
-    fib i->i :
-        dup 1 <= ? :
-            dup 1 - fib swap 2 - fib +
-        ;
-    ;
-    
-    fibs:
-        0 @ dup 20 <= :
-            dup iprint
-            32 putchar
-            dup fib iprintln
-            inc
-        ; drop
-    ;
-
-    main : fibs ;
-
-    "
-    );
-    let mut module = module(test).unwrap().1;
-
-    let functions = FunctionMapBuilder::new().walk(&mut module);
-
-    ModuleTypeChecker::new(&functions).walk(&mut module);
-    let module_ir = ModuleCodeGen::new(&functions).walk(&mut module);
-    run_ir(&module_ir);
-}
-
-fn run_ir(module: &str) {
-    let ll_filename = "out.ll";
-    fs::write(ll_filename, module).unwrap();
-
-    let clang_out = Command::new("clang")
-        .arg("-O3")
-        .arg(ll_filename)
-        .arg("-o")
-        .arg("out")
-        .output()
-        .expect("Failed to invoke clang");
-    println!(
-        "CLANG STDOUT: {}",
-        std::str::from_utf8(&clang_out.stdout).unwrap()
-    );
-    println!(
-        "CLANG STDERR: {}",
-        std::str::from_utf8(&clang_out.stderr).unwrap()
-    );
-    if !clang_out.status.success() {
-        println!("Clang returned nonzero exit status");
-        return;
+    let options = match cli::parse_args(env::args().skip(1)) {
+        Ok(ParseArgsResult::Options(options)) => options,
+        Ok(ParseArgsResult::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match options.command {
+        Command::Repl => repl::run(&options).map(|_| 0),
+        Command::Build | Command::Run => driver::compile(&options),
+    };
+    match result {
+        Ok(0) => {}
+        // `run` exits with the status of the program it ran
+        Ok(status) => process::exit(status),
+        Err(rendered_errors) => {
+            eprint!("{}", rendered_errors);
+            process::exit(1);
+        }
     }
-
-    let output = Command::new("./out")
-        .output()
-        .expect("Failed to invoke ./out");
-    println!(
-        "EXECUTABLE STDOUT:\n{}",
-        std::str::from_utf8(&output.stdout).unwrap()
-    );
 }
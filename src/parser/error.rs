@@ -0,0 +1,193 @@
+use nom::error::{ContextError, ErrorKind, FromExternalError};
+
+use super::{span_between, word_text, Input};
+use crate::diagnostic::Diagnostic;
+
+/// A parse failure, recording what the parser expected at the furthest point it reached
+#[derive(Debug, Clone)]
+pub struct ParseError<'a> {
+    pub input: Input<'a>,
+    // Descriptions of everything that could have appeared at `input`, eg. "`;`". Empty if the
+    // failing parser did not describe what it was looking for.
+    pub expected: Vec<String>,
+    // The constructs being parsed when the error occured, innermost first. Each one is paired with
+    // the input where it starts.
+    pub context: Vec<(Input<'a>, &'static str)>,
+    // Name of the top level item the error occured in
+    pub item: Option<String>,
+    // Extra advice on fixing the error, eg. why a name is not allowed
+    pub help: Option<String>,
+    // The text found at `input`, if it is only part of a word, eg. a generic in `alloca<'T>`
+    pub found: Option<String>,
+}
+
+impl<'a> ParseError<'a> {
+    fn new(input: Input<'a>, expected: Vec<String>) -> Self {
+        Self {
+            input,
+            expected,
+            context: Vec::new(),
+            item: None,
+            help: None,
+            found: None,
+        }
+    }
+
+    /// An error for a definition named `name` at `input`, which is not allowed for `reason`
+    pub fn reserved(input: Input<'a>, name: &str, reason: &str) -> Self {
+        Self {
+            help: Some(format!(
+                "`{}` {}, so it cannot be the name of a definition",
+                name, reason
+            )),
+            ..Self::new(input, vec!["name".to_string()])
+        }
+    }
+
+    /// An error for an integer literal at `input` which does not fit in 128 bits
+    pub fn literal_too_large(input: Input<'a>) -> Self {
+        Self::new(
+            input,
+            vec!["integer literal that fits in 128 bits".to_string()],
+        )
+    }
+
+    /// An error for the generic `generic` at `input`, given as a type argument to `function`
+    pub fn generic_type_arg(input: Input<'a>, generic: &str, function: &str) -> Self {
+        Self {
+            help: Some(format!("type arguments to `{}` cannot be generic", function)),
+            found: Some(generic.to_string()),
+            ..Self::new(input, vec!["type argument".to_string()])
+        }
+    }
+
+    /// Replaces what this error expected with `description`, located at `input`
+    pub fn expecting(self, input: Input<'a>, description: &str) -> Self {
+        Self {
+            input,
+            expected: vec![description.to_string()],
+            ..self
+        }
+    }
+
+    pub fn in_item(self, name: Option<String>) -> Self {
+        Self {
+            item: self.item.or(name),
+            ..self
+        }
+    }
+
+    fn expected_list(&self) -> Option<String> {
+        match self.expected.split_last() {
+            None => None,
+            Some((last, [])) => Some(last.clone()),
+            Some((last, rest)) => Some(format!("{} or {}", rest.join(", "), last)),
+        }
+    }
+
+    /// Describes the text at the error location, eg. "`foo`" or "end of file"
+    fn found(&self) -> (String, usize) {
+        if self.input.fragment().is_empty() {
+            return ("end of file".to_string(), 0);
+        }
+        let token = match (&self.found, word_text(self.input)) {
+            (Some(found), _) => found.clone(),
+            (None, Ok((_, word))) => word,
+            (None, Err(_)) => self.input.fragment().chars().next().unwrap().to_string(),
+        };
+        (format!("`{}`", token), token.len())
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let (found, found_len) = self.found();
+        let at_eof = found_len == 0;
+        let expected = self.expected_list();
+        // A construct which failed on its very first character was never really being parsed
+        let context = self
+            .context
+            .iter()
+            .find(|(start, _)| start.location_offset() < self.input.location_offset());
+
+        let message = match (context, &expected) {
+            (Some((_, name)), Some(expected)) if at_eof => {
+                format!("unterminated {}, expected {}", name, expected)
+            }
+            (Some((_, name)), None) if at_eof => format!("unterminated {}", name),
+            (Some((_, name)), Some(expected)) => {
+                format!("expected {} in {}, found {}", expected, name, found)
+            }
+            (Some((_, name)), None) => format!("unexpected {} in {}", found, name),
+            (None, Some(expected)) => format!("expected {}, found {}", expected, found),
+            (None, None) => format!("unexpected {}", found),
+        };
+
+        let mut span = span_between(&self.input, &self.input);
+        span.len = found_len;
+        let mut diagnostic = Diagnostic::error(message).with_span(span);
+        if let Some(expected) = expected {
+            diagnostic = diagnostic.with_label(format!("expected {}", expected));
+        }
+        if let Some((start, name)) = context {
+            diagnostic = diagnostic.with_note(format!(
+                "the {} starts at {}",
+                name,
+                span_between(start, start)
+            ));
+        }
+        if let Some(item) = &self.item {
+            diagnostic = diagnostic.with_note(format!("in top level item `{}`", item));
+        }
+        if let Some(help) = &self.help {
+            diagnostic = diagnostic.with_help(help.clone());
+        }
+        diagnostic
+    }
+}
+
+impl<'a> nom::error::ParseError<Input<'a>> for ParseError<'a> {
+    fn from_error_kind(input: Input<'a>, _: ErrorKind) -> Self {
+        Self::new(input, Vec::new())
+    }
+
+    fn append(_: Input<'a>, _: ErrorKind, other: Self) -> Self {
+        // The innermost error is the most useful one to report
+        other
+    }
+
+    fn from_char(input: Input<'a>, c: char) -> Self {
+        Self::new(input, vec![format!("`{}`", c)])
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        // Report whichever alternative got furthest. If they failed at the same point, either
+        // one's expectations could have continued the parse.
+        let offset = self.input.location_offset();
+        let other_offset = other.input.location_offset();
+        if other_offset > offset {
+            other
+        } else if other_offset < offset {
+            self
+        } else {
+            for expected in other.expected {
+                if !self.expected.contains(&expected) {
+                    self.expected.push(expected);
+                }
+            }
+            self.item = self.item.or(other.item);
+            self
+        }
+    }
+}
+
+impl<'a> ContextError<Input<'a>> for ParseError<'a> {
+    fn add_context(input: Input<'a>, context: &'static str, mut other: Self) -> Self {
+        other.context.push((input, context));
+        other
+    }
+}
+
+impl<'a, E> FromExternalError<Input<'a>, E> for ParseError<'a> {
+    fn from_external_error(input: Input<'a>, _: ErrorKind, _: E) -> Self {
+        Self::new(input, Vec::new())
+    }
+}
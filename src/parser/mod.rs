@@ -0,0 +1,1483 @@
+mod error;
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while_m_n},
+    character::complete::{alphanumeric1, char, multispace1, none_of, satisfy},
+    combinator::{all_consuming, cut, eof, map, map_opt, map_res, not, opt, recognize, value},
+    error::context,
+    multi::{fold_many0, many0, many1, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+use nom_locate::{position, LocatedSpan};
+
+pub use error::ParseError;
+
+/// Parser input, which tracks the location in the source file
+pub type Input<'a> = LocatedSpan<&'a str, FileId>;
+
+type PResult<'a, T> = IResult<Input<'a>, T, ParseError<'a>>;
+
+/// Creates a span covering everything between `start` and `end`
+fn span_between(start: &Input, end: &Input) -> Span {
+    Span {
+        file: start.extra,
+        offset: start.location_offset(),
+        len: end.location_offset() - start.location_offset(),
+        line: start.location_line(),
+        column: start.get_utf8_column(),
+        expansion: None,
+    }
+}
+
+/// Runs `parser`, additionally returning the span of the input it consumed
+fn spanned<'a, T>(
+    mut parser: impl FnMut(Input<'a>) -> PResult<'a, T>,
+) -> impl FnMut(Input<'a>) -> PResult<'a, (T, Span)> {
+    move |input: Input<'a>| {
+        let (input, start) = position(input)?;
+        let (input, output) = parser(input)?;
+        let span = span_between(&start, &input);
+        Ok((input, (output, span)))
+    }
+}
+
+/// Runs `parser`, reporting `description` as what was expected if it fails without committing
+fn expecting<'a, T>(
+    description: &'static str,
+    mut parser: impl FnMut(Input<'a>) -> PResult<'a, T>,
+) -> impl FnMut(Input<'a>) -> PResult<'a, T> {
+    move |input: Input<'a>| match parser(input) {
+        Err(nom::Err::Error(e)) => Err(nom::Err::Error(e.expecting(input, description))),
+        result => result,
+    }
+}
+
+fn whitespace(input: Input) -> PResult<Input> {
+    expecting("whitespace", recognize(many1(alt((multispace1, comment)))))(input)
+}
+
+fn maybe_whitespace(input: Input) -> PResult<Input> {
+    recognize(opt(whitespace))(input)
+}
+
+fn comment(input: Input) -> PResult<Input> {
+    recognize(context(
+        "comment",
+        preceded(
+            char('['),
+            cut(terminated(take_while(|c| c != ']'), char(']'))),
+        ),
+    ))(input)
+}
+
+fn word_text(input: Input) -> PResult<String> {
+    map(recognize(many1(none_of(" []\t\r\n:;?@"))), |text: Input| {
+        String::from(*text.fragment())
+    })(input)
+}
+
+/// A word which may be qualified by the module it is defined in, eg. `io::print`
+fn qualified_word_text(input: Input) -> PResult<String> {
+    map(
+        recognize(pair(word_text, many0(pair(tag("::"), word_text)))),
+        |text: Input| String::from(*text.fragment()),
+    )(input)
+}
+
+/// A cast to a type, eg. `(i)` or `(*uc)`
+fn word_cast(input: Input) -> PResult<Word> {
+    map(
+        spanned(terminated(delimited(char('('), typ, char(')')), word_end)),
+        |(to, span)| Word::Cast(to, span),
+    )(input)
+}
+
+/// The name of a function called with type arguments, which ends at the `<` they start with
+fn type_args_name(input: Input) -> PResult<String> {
+    let name_part = || many1(none_of(" []\t\r\n:;?@<"));
+    map(
+        recognize(pair(name_part(), many0(pair(tag("::"), name_part())))),
+        |text: Input| String::from(*text.fragment()),
+    )(input)
+}
+
+fn word_function_call(input: Input) -> PResult<Word> {
+    let with_type_args = |input| {
+        let (input, name) = type_args_name(input)?;
+        let (input, type_args) = terminated(|input| type_args(&name, input), word_end)(input)?;
+        Ok((input, (name, type_args)))
+    };
+    let without_type_args = map(qualified_word_text, |name| (name, Vec::new()));
+    map(
+        spanned(alt((with_type_args, without_type_args))),
+        |((name, type_args), span)| {
+            Word::FunctionCall(FunctionCall {
+                name,
+                type_args,
+                span,
+                reified_type: None,
+            })
+        },
+    )(input)
+}
+
+/// Succeeds without consuming input if the next character cannot continue a word, so that a
+/// literal is not parsed from the start of a word such as `2dup`
+fn word_end(input: Input) -> PResult<()> {
+    not(none_of(" []\t\r\n:;?@"))(input)
+}
+
+/// One or more digits in `radix`, which may be separated by `_` after the first digit
+fn digits<'a>(radix: u32) -> impl FnMut(Input<'a>) -> PResult<'a, Input<'a>> {
+    recognize(pair(
+        satisfy(move |c| c.is_digit(radix)),
+        take_while(move |c: char| c.is_digit(radix) || c == '_'),
+    ))
+}
+
+/// The type given by the suffix of an integer literal, eg. `uc` in `255uc`
+fn int_suffix(input: Input) -> PResult<ConcreteType> {
+    alt((
+        value(ConcreteType::U64, tag("uq")),
+        value(ConcreteType::U32, tag("ui")),
+        value(ConcreteType::U8, tag("uc")),
+        value(ConcreteType::I64, char('q')),
+        value(ConcreteType::I32, char('i')),
+        value(ConcreteType::I8, char('c')),
+    ))(input)
+}
+
+fn float_suffix(input: Input) -> PResult<ConcreteType> {
+    alt((
+        value(ConcreteType::F32, char('f')),
+        value(ConcreteType::F64, char('d')),
+    ))(input)
+}
+
+/// An integer such as `-12`, `1_000`, `0xFF`, `0b1010` or `0o17`, with an optional type suffix.
+/// Hex digits are read first, so `0xFFc` is `0xFFC` rather than `0xFF` as a `c`.
+fn word_int_literal(input: Input) -> PResult<Word> {
+    let radix_digits = alt((
+        preceded(tag("0x"), map(digits(16), |d| (16, d))),
+        preceded(tag("0b"), map(digits(2), |d| (2, d))),
+        preceded(tag("0o"), map(digits(8), |d| (8, d))),
+        map(digits(10), |d| (10, d)),
+    ));
+    let (rest, ((sign, (radix, digits), suffix), span)) = spanned(terminated(
+        tuple((opt(char('-')), radix_digits, opt(int_suffix))),
+        word_end,
+    ))(input)?;
+    // Whether the value fits its type is checked once the type is known, so this only fails for
+    // literals too large for any type to hold
+    let magnitude = i128::from_str_radix(&digits.fragment().replace('_', ""), radix)
+        .map_err(|_| nom::Err::Failure(ParseError::literal_too_large(input)))?;
+    let value = match sign {
+        Some(_) => -magnitude,
+        None => magnitude,
+    };
+    Ok((
+        rest,
+        Word::IntLiteral(IntLiteral {
+            value,
+            suffix,
+            typ: None,
+            span,
+        }),
+    ))
+}
+
+/// A decimal such as `1.5`, with an optional type suffix
+fn word_float_literal(input: Input) -> PResult<Word> {
+    map_res(
+        spanned(terminated(
+            pair(
+                recognize(tuple((opt(char('-')), digits(10), char('.'), digits(10)))),
+                opt(float_suffix),
+            ),
+            word_end,
+        )),
+        |((text, suffix), span)| {
+            let text = text.fragment().replace('_', "");
+            text.parse()
+                .map(|n| Word::FloatLiteral(n, suffix.unwrap_or(ConcreteType::F32), span))
+        },
+    )(input)
+}
+
+/// `'a'`, which has type `c`. Supports the same escapes as strings.
+fn word_char_literal(input: Input) -> PResult<Word> {
+    let ascii = expecting(
+        "ASCII character",
+        map_opt(none_of("'\\\n"), |c| c.is_ascii().then_some(c as u8)),
+    );
+    let escape = expecting("escape sequence", escape_sequence);
+    map(
+        spanned(context(
+            "character literal",
+            preceded(
+                char('\''),
+                cut(terminated(alt((escape, ascii)), char('\''))),
+            ),
+        )),
+        |(c, span)| {
+            Word::IntLiteral(IntLiteral {
+                // Like in C, characters above 0x7F are negative
+                value: (c as i8).into(),
+                suffix: Some(ConcreteType::I8),
+                typ: None,
+                span,
+            })
+        },
+    )(input)
+}
+
+fn true_literal(input: Input) -> PResult<Word> {
+    map_opt(spanned(word_text), |(text, span)| {
+        (text == "true").then_some(Word::BoolLiteral(true, span))
+    })(input)
+}
+
+fn false_literal(input: Input) -> PResult<Word> {
+    map_opt(spanned(word_text), |(text, span)| {
+        (text == "false").then_some(Word::BoolLiteral(false, span))
+    })(input)
+}
+
+/// A backslash escape in a string literal, eg. `\n` or `\x7f`
+fn escape_sequence(input: Input) -> PResult<u8> {
+    preceded(
+        char('\\'),
+        cut(expecting(
+            "escape sequence",
+            alt((
+                value(b'\n', char('n')),
+                value(b'\t', char('t')),
+                value(b'"', char('"')),
+                value(b'\'', char('\'')),
+                value(b'\\', char('\\')),
+                preceded(
+                    char('x'),
+                    map_res(
+                        take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
+                        |hex: Input| u8::from_str_radix(hex.fragment(), 16),
+                    ),
+                ),
+            )),
+        )),
+    )(input)
+}
+
+/// `"..."`, which may not span multiple lines
+fn string_literal(input: Input) -> PResult<Vec<u8>> {
+    let string_char = alt((
+        map(escape_sequence, |byte| vec![byte]),
+        map(none_of("\"\\\n"), |c| c.to_string().into_bytes()),
+    ));
+    preceded(
+        char('"'),
+        context(
+            "string literal",
+            cut(terminated(
+                fold_many0(string_char, Vec::new, |mut bytes, c| {
+                    bytes.extend(c);
+                    bytes
+                }),
+                char('"'),
+            )),
+        ),
+    )(input)
+}
+
+fn word_string_literal(input: Input) -> PResult<Word> {
+    map(spanned(string_literal), |(s, span)| {
+        Word::StringLiteral(s, span)
+    })(input)
+}
+
+fn word_if_statement(input: Input) -> PResult<Word> {
+    map(if_statement, Word::IfStatement)(input)
+}
+
+fn word_while_statement(input: Input) -> PResult<Word> {
+    map(while_statement, Word::WhileStatement)(input)
+}
+
+fn word(input: Input) -> PResult<Word> {
+    alt((
+        word_if_statement,
+        word_while_statement,
+        word_string_literal,
+        word_char_literal,
+        word_float_literal,
+        word_int_literal,
+        true_literal,
+        false_literal,
+        word_cast,
+        word_function_call,
+    ))(input)
+}
+
+fn words(input: Input) -> PResult<Vec<Word>> {
+    separated_list0(whitespace, word)(input)
+}
+
+fn code_block(input: Input) -> PResult<CodeBlock> {
+    map(spanned(words), |(words, span)| CodeBlock { words, span })(input)
+}
+
+fn if_statement(input: Input) -> PResult<IfStatement> {
+    map(
+        spanned(context(
+            "`?` if-statement",
+            preceded(
+                char('?'),
+                cut(tuple((
+                    whitespace,
+                    terminated(code_block, maybe_whitespace),
+                    terminated(char(':'), whitespace),
+                    terminated(code_block, maybe_whitespace),
+                    char(';'),
+                ))),
+            ),
+        )),
+        |((_, true_branch, _, false_branch, _), span)| IfStatement {
+            true_branch,
+            false_branch,
+            span,
+        },
+    )(input)
+}
+
+fn while_statement(input: Input) -> PResult<WhileStatement> {
+    map(
+        spanned(context(
+            "`@` while loop",
+            preceded(
+                char('@'),
+                cut(tuple((
+                    whitespace,
+                    terminated(code_block, maybe_whitespace),
+                    terminated(char(':'), whitespace),
+                    terminated(code_block, maybe_whitespace),
+                    char(';'),
+                ))),
+            ),
+        )),
+        |((_, condition, _, body, _), span)| WhileStatement {
+            condition,
+            body,
+            span,
+        },
+    )(input)
+}
+
+macro_rules! concrete_type_parser {
+    ($input:expr, $($name:literal => $type:expr),*) => {
+        let (input, typ) = terminated(alt(( $(tag($name)),* )), type_name_end)($input)?;
+        match *typ.fragment() {
+            $(
+                $name => Ok((input, Type::Concrete($type))),
+            )*
+            _ => unreachable!(),
+        }
+    };
+}
+
+const TYPE_NAMES: [&str; 9] = ["i", "ui", "f", "d", "q", "uq", "c", "uc", "b"];
+
+fn concrete_type(input: Input) -> PResult<Type> {
+    concrete_type_parser! {
+        input,
+        "i" => ConcreteType::I32,
+        "ui" => ConcreteType::U32,
+        "f" => ConcreteType::F32,
+        "d" => ConcreteType::F64,
+        "q" => ConcreteType::I64,
+        "uq" => ConcreteType::U64,
+        "c" => ConcreteType::I8,
+        "uc" => ConcreteType::U8,
+        "b" => ConcreteType::Bool
+    }
+}
+
+const TYPE_CLASS_NAMES: [&str; 3] = ["num", "int", "record"];
+
+fn type_class(input: Input) -> PResult<TypeClass> {
+    alt((
+        value(TypeClass::Num, terminated(tag("num"), word_end)),
+        value(TypeClass::Int, terminated(tag("int"), word_end)),
+        value(TypeClass::Record, terminated(tag("record"), word_end)),
+    ))(input)
+}
+
+/// A character which can continue the name of a type. A `-` is allowed, eg. in `my-struct`, unless
+/// it starts the `->` of a function signature.
+fn type_name_char(input: Input) -> PResult<char> {
+    alt((
+        satisfy(|c| c.is_alphanumeric() || c == '_'),
+        terminated(char('-'), not(char('>'))),
+    ))(input)
+}
+
+/// Succeeds without consuming input if the next character cannot continue a type name, so that
+/// the `i` of a record named `item` is not parsed as a concrete type
+fn type_name_end(input: Input) -> PResult<()> {
+    not(type_name_char)(input)
+}
+
+fn record_name(input: Input) -> PResult<String> {
+    map(
+        recognize(pair(satisfy(char::is_alphabetic), many0(type_name_char))),
+        |name: Input| String::from(*name.fragment()),
+    )(input)
+}
+
+/// The name of a record type, eg. `my-struct`, optionally qualified by its module, eg.
+/// `shapes::point`. Concrete types are tried first, so a record type never has the name of a
+/// concrete type.
+fn record_type(input: Input) -> PResult<Type> {
+    map(
+        recognize(pair(record_name, many0(pair(tag("::"), record_name)))),
+        |name: Input| Type::Record(String::from(*name.fragment())),
+    )(input)
+}
+
+/// A generic such as `'T`, optionally constrained to a type class, eg. `'T:num` or `'T: int`
+fn generic_type(input: Input) -> PResult<Type> {
+    map(
+        pair(
+            preceded(char('\''), alphanumeric1),
+            opt(preceded(pair(char(':'), maybe_whitespace), type_class)),
+        ),
+        |(name, class): (Input, _)| Type::Generic(String::from(*name.fragment()), class),
+    )(input)
+}
+
+/// A type given as a type argument to `function`. Generics cannot be used, since a type argument
+/// is given in a function's body rather than its signature.
+fn type_arg<'a>(function: &str, input: Input<'a>) -> PResult<'a, Type> {
+    if let Ok((_, generic)) = recognize(generic_type)(input) {
+        return Err(nom::Err::Failure(ParseError::generic_type_arg(
+            input,
+            generic.fragment(),
+            function,
+        )));
+    }
+    alt((
+        map(
+            preceded(char('*'), |input| type_arg(function, input)),
+            |typ| Type::Pointer(Box::new(typ)),
+        ),
+        concrete_type,
+        record_type,
+    ))(input)
+}
+
+/// The types given for the generics of a call to `function`, eg. `<i>` in `alloca<i>` or `<*i,q>`
+fn type_args<'a>(function: &str, input: Input<'a>) -> PResult<'a, Vec<Type>> {
+    delimited(
+        char('<'),
+        separated_list1(char(','), |input| type_arg(function, input)),
+        char('>'),
+    )(input)
+}
+
+fn pointer_type(input: Input) -> PResult<Type> {
+    map(preceded(char('*'), typ), |typ| Type::Pointer(Box::new(typ)))(input)
+}
+
+fn typ(input: Input) -> PResult<Type> {
+    alt((pointer_type, concrete_type, generic_type, record_type))(input)
+}
+
+fn type_list(input: Input) -> PResult<Vec<Type>> {
+    separated_list0(whitespace, typ)(input)
+}
+
+fn defined_function_type(input: Input) -> PResult<FunctionType> {
+    map(
+        separated_pair(
+            terminated(type_list, maybe_whitespace),
+            tag("->"),
+            preceded(maybe_whitespace, type_list),
+        ),
+        |(inputs, outputs)| FunctionType { inputs, outputs },
+    )(input)
+}
+
+/// Items are private to their module unless marked `pub`
+fn visibility(input: Input) -> PResult<bool> {
+    map(opt(terminated(tag("pub"), whitespace)), |pub_opt| {
+        pub_opt.is_some()
+    })(input)
+}
+
+/// Succeeds without consuming input unless `keyword` is used as a modifier, ie. it is followed by
+/// more of an item rather than the `:` or `;` of a function named `keyword`. Since a signature may
+/// name record types, `extern f i -> i` would otherwise also parse as the header of a function
+/// named `extern`.
+fn not_modifier<'a>(keyword: &'static str) -> impl FnMut(Input<'a>) -> PResult<'a, ()> {
+    not(tuple((tag(keyword), whitespace, none_of(":;"))))
+}
+
+fn inline_modifier(input: Input) -> PResult<bool> {
+    map(opt(terminated(tag("inline"), whitespace)), |inline_opt| {
+        inline_opt.is_some()
+    })(input)
+}
+
+/// The name of a function, constant, macro or record. Type names, type class names and literal
+/// keywords are rejected, since a definition with one of those names could be confused with the
+/// type or literal.
+fn definition_name(input: Input) -> PResult<(String, Span)> {
+    reserved_names(spanned(word_text))(input)
+}
+
+/// Runs `parser`, failing if the name it parses is reserved (see `definition_name`)
+fn reserved_names<'a>(
+    mut parser: impl FnMut(Input<'a>) -> PResult<'a, (String, Span)>,
+) -> impl FnMut(Input<'a>) -> PResult<'a, (String, Span)> {
+    move |input: Input<'a>| {
+        let (rest, (name, span)) = parser(input)?;
+        let reason = if name == "true" || name == "false" {
+            "is a bool literal"
+        } else if TYPE_NAMES.contains(&name.as_str()) {
+            "is the name of a type"
+        } else if TYPE_CLASS_NAMES.contains(&name.as_str()) {
+            "is the name of a type class"
+        } else {
+            return Ok((rest, (name, span)));
+        };
+        Err(nom::Err::Failure(ParseError::reserved(input, &name, reason)))
+    }
+}
+
+// TODO the lack of whitespace in this `fn a;` makes it not parse
+fn function_header(input: Input) -> PResult<FunctionHeader> {
+    map(
+        tuple((
+            definition_name,
+            opt(preceded(maybe_whitespace, spanned(defined_function_type))),
+        )),
+        |((name, name_span), typ)| match typ {
+            Some((typ, type_span)) => FunctionHeader {
+                name,
+                typ,
+                name_span,
+                span: name_span.to(type_span),
+            },
+            None => FunctionHeader {
+                name,
+                typ: Default::default(),
+                name_span,
+                span: name_span,
+            },
+        },
+    )(input)
+}
+
+fn function_decl(input: Input) -> PResult<FunctionDecl> {
+    map(
+        spanned(tuple((
+            terminated(visibility, not_modifier("inline")),
+            opt(terminated(tag("extern"), whitespace)),
+            opt(terminated(tag("intrinsic"), whitespace)),
+            terminated(function_header, maybe_whitespace),
+            char(';'),
+        ))),
+        |((is_pub, extern_opt, intrinsic_opt, head, _), span)| FunctionDecl {
+            head,
+            is_pub,
+            is_extern: extern_opt.is_some(),
+            is_intrinsic: intrinsic_opt.is_some(),
+            span,
+        },
+    )(input)
+}
+
+fn function_impl(input: Input) -> PResult<FunctionImpl> {
+    map(
+        spanned(tuple((
+            terminated(
+                visibility,
+                pair(not_modifier("extern"), not_modifier("intrinsic")),
+            ),
+            inline_modifier,
+            terminated(function_header, maybe_whitespace),
+            preceded(
+                char(':'),
+                context(
+                    "function body",
+                    cut(delimited(
+                        maybe_whitespace,
+                        code_block,
+                        preceded(maybe_whitespace, char(';')),
+                    )),
+                ),
+            ),
+        ))),
+        |((is_pub, is_inline, head, body), span)| FunctionImpl {
+            head,
+            is_pub,
+            is_inline,
+            body,
+            span,
+        },
+    )(input)
+}
+
+fn constant(input: Input) -> PResult<Const> {
+    map(
+        spanned(tuple((
+            visibility,
+            preceded(pair(tag("let"), whitespace), definition_name),
+            // The `:` before the value is optional, eg. `let a 5;` or `let b : a 1 +;`
+            context(
+                "constant",
+                cut(delimited(
+                    pair(maybe_whitespace, opt(pair(char(':'), maybe_whitespace))),
+                    code_block,
+                    preceded(maybe_whitespace, char(';')),
+                )),
+            ),
+        ))),
+        |((is_pub, (name, name_span), body), span)| Const {
+            name,
+            is_pub,
+            body,
+            value: None,
+            name_span,
+            span,
+        },
+    )(input)
+}
+
+fn macro_def(input: Input) -> PResult<Macro> {
+    map(
+        spanned(tuple((
+            visibility,
+            preceded(pair(tag("macro"), whitespace), definition_name),
+            context(
+                "macro",
+                cut(delimited(
+                    tuple((maybe_whitespace, char(':'), maybe_whitespace)),
+                    code_block,
+                    preceded(maybe_whitespace, char(';')),
+                )),
+            ),
+        ))),
+        |((is_pub, (name, name_span), body), span)| Macro {
+            name,
+            is_pub,
+            body,
+            name_span,
+            span,
+        },
+    )(input)
+}
+
+/// The name of a record's field, which ends at the `,` after its type if there is no whitespace
+fn field_name(input: Input) -> PResult<String> {
+    map(recognize(many1(none_of(" []\t\r\n:;?@,"))), |text: Input| {
+        String::from(*text.fragment())
+    })(input)
+}
+
+/// A field of a record and its type, eg. `foo i`
+fn record_field(input: Input) -> PResult<RecordField> {
+    map(
+        spanned(separated_pair(field_name, whitespace, typ)),
+        |((name, typ), span)| RecordField { name, typ, span },
+    )(input)
+}
+
+fn record_def(input: Input) -> PResult<Record> {
+    let field_separator = || tuple((maybe_whitespace, char(','), maybe_whitespace));
+    map(
+        spanned(tuple((
+            visibility,
+            preceded(pair(tag("record"), whitespace), reserved_names(spanned(record_name))),
+            context(
+                "record",
+                cut(delimited(
+                    tuple((maybe_whitespace, char(':'), maybe_whitespace)),
+                    many0(terminated(record_field, cut(field_separator()))),
+                    char(';'),
+                )),
+            ),
+        ))),
+        |((is_pub, (name, name_span), fields), span)| Record {
+            name,
+            fields,
+            is_pub,
+            name_span,
+            span,
+        },
+    )(input)
+}
+
+fn import_path(input: Input) -> PResult<String> {
+    map(
+        preceded(
+            char('"'),
+            cut(terminated(take_while(|c| c != '"' && c != '\n'), char('"'))),
+        ),
+        |path: Input| String::from(*path.fragment()),
+    )(input)
+}
+
+/// `use name;` is shorthand for `import "name.f";`
+fn use_name(input: Input) -> PResult<String> {
+    map(
+        preceded(pair(tag("use"), whitespace), word_text),
+        |name| format!("{}.f", name),
+    )(input)
+}
+
+fn import(input: Input) -> PResult<Import> {
+    map(
+        spanned(context(
+            "import",
+            terminated(
+                alt((
+                    preceded(pair(tag("import"), maybe_whitespace), import_path),
+                    use_name,
+                )),
+                preceded(maybe_whitespace, char(';')),
+            ),
+        )),
+        |(path, span)| Import { path, span },
+    )(input)
+}
+
+fn import_tli(input: Input) -> PResult<TopLevelItem> {
+    map(import, TopLevelItem::Import)(input)
+}
+
+fn const_tli(input: Input) -> PResult<TopLevelItem> {
+    map(constant, TopLevelItem::Const)(input)
+}
+
+fn macro_tli(input: Input) -> PResult<TopLevelItem> {
+    map(macro_def, TopLevelItem::Macro)(input)
+}
+
+fn record_tli(input: Input) -> PResult<TopLevelItem> {
+    map(record_def, TopLevelItem::Record)(input)
+}
+
+fn function_decl_tli(input: Input) -> PResult<TopLevelItem> {
+    map(function_decl, TopLevelItem::Decl)(input)
+}
+
+fn function_impl_tli(input: Input) -> PResult<TopLevelItem> {
+    map(function_impl, TopLevelItem::Impl)(input)
+}
+
+/// The name of the item starting at `input`, used to say where a parse error occured
+fn item_name(input: Input) -> Option<String> {
+    let modifier = terminated(
+        alt((
+            tag("pub"),
+            tag("let"),
+            tag("macro"),
+            tag("record"),
+            tag("inline"),
+            tag("extern"),
+            tag("intrinsic"),
+        )),
+        whitespace,
+    );
+    preceded(many0(modifier), word_text)(input)
+        .ok()
+        .map(|(_, name)| name)
+}
+
+pub fn top_level_item(input: Input) -> PResult<TopLevelItem> {
+    alt((
+        import_tli,
+        const_tli,
+        macro_tli,
+        record_tli,
+        function_impl_tli,
+        function_decl_tli,
+    ))(input)
+        .map_err(|e| e.map(|e| e.in_item(item_name(input))))
+}
+
+pub fn module(input: Input) -> PResult<Vec<TopLevelItem>> {
+    // Anything other than trailing whitespace must be a top level item, so once there is
+    // remaining input a failure to parse an item is reported rather than backtracked
+    all_consuming(preceded(
+        maybe_whitespace,
+        many0(terminated(
+            preceded(not(eof), cut(top_level_item)),
+            maybe_whitespace,
+        )),
+    ))(input)
+}
+
+/// Parses a whole source file, converting any parse failure into a diagnostic
+pub fn parse_module(source: &str, file: FileId) -> Result<Vec<TopLevelItem>, Diagnostic> {
+    match module(Input::new_extra(source, file)) {
+        Ok((_, items)) => Ok(items),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e.to_diagnostic()),
+        Err(nom::Err::Incomplete(_)) => unreachable!("only complete parsers are used"),
+    }
+}
+
+/// A line of input to the REPL
+pub enum ReplLine {
+    Items(Vec<TopLevelItem>),
+    Expression(CodeBlock),
+}
+
+/// Parses a line of REPL input, which is either some top level items to define, or a code block
+/// to evaluate
+pub fn parse_repl_line(source: &str, file: FileId) -> Result<ReplLine, Diagnostic> {
+    let input = Input::new_extra(source, file);
+    let expression_error =
+        match all_consuming(delimited(maybe_whitespace, code_block, maybe_whitespace))(input) {
+            Ok((_, block)) => return Ok(ReplLine::Expression(block)),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => e,
+            Err(nom::Err::Incomplete(_)) => unreachable!("only complete parsers are used"),
+        };
+    match module(input) {
+        Ok((_, items)) => Ok(ReplLine::Items(items)),
+        // Report whichever interpretation of the line got furthest, preferring the expression
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e))
+            if e.input.location_offset() > expression_error.input.location_offset() =>
+        {
+            Err(e.to_diagnostic())
+        }
+        Err(_) => Err(expression_error.to_diagnostic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(s: &str) -> Input<'_> {
+        Input::new_extra(s, FileId::default())
+    }
+
+    type TestResult = Result<(), String>;
+    trait ParserTester {
+        fn test(self) -> TestResult;
+    }
+
+    impl<T> ParserTester for PResult<'_, T> {
+        fn test(self) -> TestResult {
+            match self {
+                Ok((remaining, _)) => {
+                    if !remaining.is_empty() {
+                        Err("Parser did not consume entire str".to_string())
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_impl() -> TestResult {
+        top_level_item(input("foo: ;")).test()?;
+        top_level_item(input("foo:;")).test()?;
+        top_level_item(input("foo :;")).test()?;
+        top_level_item(input("foo i -> i : ;")).test()?;
+        top_level_item(input("foo i -> i: ;")).test()?;
+        top_level_item(input("foo i -> :;")).test()?;
+        top_level_item(input("foo -> i :;")).test()?;
+        top_level_item(input("foo i f b -> i :;")).test()?;
+        top_level_item(input(
+            "foo 'Typ 'Typ2 *'Typ i f b -> 'Typ 'Typ2 *'Typ i f b:;",
+        ))
+        .test()
+    }
+
+    #[test]
+    fn test_fn_type() -> TestResult {
+        defined_function_type(input("i -> i")).test()
+    }
+
+    #[test]
+    fn test_type_class() {
+        let (_, typ) = generic_type(input("'T:num")).unwrap();
+        assert_eq!(typ, Type::Generic("T".into(), Some(TypeClass::Num)));
+        let (_, typ) = generic_type(input("'T: int")).unwrap();
+        assert_eq!(typ, Type::Generic("T".into(), Some(TypeClass::Int)));
+        let (_, typ) = generic_type(input("'T")).unwrap();
+        assert_eq!(typ, Type::Generic("T".into(), None));
+        assert!(top_level_item(input("foo 'T:number -> 'T;")).test().is_err());
+    }
+
+    #[test]
+    fn test_decl() -> TestResult {
+        top_level_item(input("foo ;")).test()?;
+        top_level_item(input("foo;")).test()?;
+        top_level_item(input("extern foo;")).test()?;
+        top_level_item(input("foo -> ;")).test()?;
+        top_level_item(input("intrinsic foo i -> i ;")).test()?;
+        top_level_item(input("extern foo i -> ;")).test()?;
+        top_level_item(input("foo -> i ;")).test()?;
+        top_level_item(input("foo i f b -> i ;")).test()?;
+        top_level_item(input(
+            "foo 'Typ 'Typ2 *'Typ i f b -> 'Typ 'Typ2 *'Typ i f b;",
+        ))
+        .test()?;
+        top_level_item(input("foo;")).test()?;
+        top_level_item(input("foo ->;")).test()?;
+        top_level_item(input("foo -> i;")).test()?;
+        top_level_item(input("foo i ->;")).test()?;
+        top_level_item(input("foo i -> i;")).test()
+    }
+
+    #[test]
+    fn test_import() {
+        let import_path = |source| match top_level_item(input(source)) {
+            Ok((_, TopLevelItem::Import(import))) => import.path,
+            other => panic!("expected import, got {:?}", other),
+        };
+        assert_eq!(import_path("import \"std.f\";"), "std.f");
+        assert_eq!(import_path("import\"lib/a b.f\" ;"), "lib/a b.f");
+        assert_eq!(import_path("use io;"), "io.f");
+
+        // Functions may still start with the keywords
+        assert!(matches!(
+            top_level_item(input("importer;")),
+            Ok((_, TopLevelItem::Decl(_)))
+        ));
+        assert!(matches!(
+            top_level_item(input("user : ;")),
+            Ok((_, TopLevelItem::Impl(_)))
+        ));
+
+        let error = parse_module("import \"std.f;", FileId::default()).unwrap_err();
+        assert_eq!(error.message, "unterminated import, expected `\"`");
+    }
+
+    #[test]
+    fn test_visibility() {
+        match top_level_item(input("pub extern foo i -> ;")) {
+            Ok((_, TopLevelItem::Decl(decl))) => assert!(decl.is_pub && decl.is_extern),
+            other => panic!("expected decl, got {:?}", other),
+        }
+        match top_level_item(input("pub foo : io::print ;")) {
+            Ok((_, TopLevelItem::Impl(function))) => {
+                assert!(function.is_pub);
+                assert!(matches!(
+                    &function.body.words[..],
+                    [Word::FunctionCall(FunctionCall { name, .. })] if name == "io::print"
+                ));
+            }
+            other => panic!("expected impl, got {:?}", other),
+        }
+        match top_level_item(input("public : ;")) {
+            Ok((_, TopLevelItem::Impl(function))) => {
+                assert!(!function.is_pub);
+                assert_eq!(function.head.name, "public");
+            }
+            other => panic!("expected impl, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let literal = |source| match word(input(source)) {
+            Ok((rest, word)) if rest.is_empty() => word,
+            other => panic!("expected literal, got {:?}", other),
+        };
+        let int = |source| match literal(source) {
+            Word::IntLiteral(literal) => (literal.value, literal.suffix),
+            other => panic!("expected integer literal, got {:?}", other),
+        };
+        assert_eq!(int("-12"), (-12, None));
+        assert_eq!(int("1_000_000"), (1_000_000, None));
+        assert_eq!(int("0xFF"), (255, None));
+        assert_eq!(int("0b1010"), (10, None));
+        assert_eq!(int("-0o17"), (-15, None));
+        assert_eq!(int("0x8000_0000"), (0x8000_0000, None));
+        assert_eq!(int("10q"), (10, Some(ConcreteType::I64)));
+        assert_eq!(int("255uc"), (255, Some(ConcreteType::U8)));
+        assert_eq!(int("0xFFuq"), (255, Some(ConcreteType::U64)));
+        assert_eq!(int("0xFFFF_FFFF_FFFF_FFFFuq"), (u64::MAX.into(), Some(ConcreteType::U64)));
+        assert_eq!(int("0xFFc"), (0xFFC, None));
+        assert_eq!(int("-3c"), (-3, Some(ConcreteType::I8)));
+        assert_eq!(int("7ui"), (7, Some(ConcreteType::U32)));
+        // Literals too large for their type are still literals, so the typechecker can report them
+        assert_eq!(int("99999999999999999999"), (99999999999999999999, None));
+        assert_eq!(int("-0x1_0000_0000_0000_0000q"), (-(1 << 64), Some(ConcreteType::I64)));
+        assert!(matches!(literal("1.5"), Word::FloatLiteral(n, ConcreteType::F32, _) if n == 1.5));
+        assert!(matches!(literal("1.5d"), Word::FloatLiteral(n, ConcreteType::F64, _) if n == 1.5));
+        assert!(matches!(
+            literal("-2_000.25f"),
+            Word::FloatLiteral(n, ConcreteType::F32, _) if n == -2000.25
+        ));
+
+        assert_eq!(int("'a'"), (97, Some(ConcreteType::I8)));
+        assert_eq!(int("'\\n'"), (10, Some(ConcreteType::I8)));
+        assert_eq!(int("'\\''"), (39, Some(ConcreteType::I8)));
+        assert_eq!(int("'\\x7f'"), (127, Some(ConcreteType::I8)));
+        assert_eq!(int("'\\xff'"), (-1, Some(ConcreteType::I8)));
+
+        // Words which only start like a literal are function calls
+        for name in ["2dup", "10qq", "0x", "1.5x", "_1", "-"] {
+            match literal(name) {
+                Word::FunctionCall(call) => assert_eq!(call.name, name),
+                other => panic!("expected `{}` to be a function call, got {:?}", name, other),
+            }
+        }
+
+        let error = |source| parse_module(source, FileId::default()).unwrap_err().message;
+        assert_eq!(
+            error("a : 'ab' ;"),
+            "expected `'` in character literal, found `b'`"
+        );
+        assert_eq!(
+            error("a : 'é' ;"),
+            "expected escape sequence or ASCII character in character literal, found `é'`"
+        );
+        assert_eq!(
+            error("a : 1000000000000000000000000000000000000000 ;"),
+            "expected integer literal that fits in 128 bits in function body, \
+             found `1000000000000000000000000000000000000000`"
+        );
+    }
+
+    #[test]
+    fn test_cast() {
+        let cast = |source| match word(input(source)) {
+            Ok((remaining, Word::Cast(to, span))) if remaining.is_empty() => {
+                assert_eq!(span.len, source.len());
+                to
+            }
+            other => panic!("expected cast, got {:?}", other),
+        };
+        assert_eq!(cast("(i)"), Type::Concrete(ConcreteType::I32));
+        assert_eq!(
+            cast("(**uc)"),
+            Type::Pointer(Box::new(Type::Pointer(Box::new(Type::Concrete(
+                ConcreteType::U8
+            )))))
+        );
+        assert_eq!(
+            cast("(*'T)"),
+            Type::Pointer(Box::new(Type::Generic("T".into(), None)))
+        );
+        assert_eq!(cast("(my-struct)"), Type::Record("my-struct".into()));
+        assert_eq!(
+            cast("(shapes::point)"),
+            Type::Record("shapes::point".into())
+        );
+        for name in ["(-x)", "(i)x", "()"] {
+            assert!(matches!(word(input(name)), Ok((_, Word::FunctionCall(_)))));
+        }
+    }
+
+    #[test]
+    fn test_string_literal() {
+        let string = |source| match word(input(source)) {
+            Ok((_, Word::StringLiteral(s, _))) => s,
+            other => panic!("expected string literal, got {:?}", other),
+        };
+        assert_eq!(string("\"hello world\""), b"hello world");
+        assert_eq!(string("\"\""), b"");
+        assert_eq!(string("\"a\\nb\\tc\\\"d\\\\\""), b"a\nb\tc\"d\\");
+        assert_eq!(string("\"\\x00\\x7F\\xff\""), b"\x00\x7f\xff");
+        assert_eq!(string("\"é\""), "é".as_bytes());
+
+        let (_, block) = code_block(input("\"a b\" puts")).unwrap();
+        assert_eq!(block.words.len(), 2);
+
+        let error = |source| parse_module(source, FileId::default()).unwrap_err().message;
+        assert_eq!(
+            error("a : \"abc ;"),
+            "unterminated string literal, expected `\"`"
+        );
+        assert_eq!(
+            error("a : \"a\\q\" ;"),
+            "expected escape sequence in string literal, found `q\"`"
+        );
+    }
+
+    #[test]
+    fn test_type_args() {
+        let call = |source| match word(input(source)) {
+            Ok((remaining, Word::FunctionCall(call))) if remaining.is_empty() => call,
+            other => panic!("expected function call, got {:?}", other),
+        };
+        let alloca = call("alloca<i>");
+        assert_eq!(alloca.name, "alloca");
+        assert_eq!(alloca.type_args, vec![Type::Concrete(ConcreteType::I32)]);
+        assert_eq!(alloca.span.len, "alloca<i>".len());
+
+        let pointer = Type::Pointer(Box::new(Type::Concrete(ConcreteType::U8)));
+        let qualified = call("mem::pair<*uc,q>");
+        assert_eq!(qualified.name, "mem::pair");
+        assert_eq!(
+            qualified.type_args,
+            vec![pointer, Type::Concrete(ConcreteType::I64)]
+        );
+
+        let construct = call("construct<item>");
+        assert_eq!(construct.type_args, vec![Type::Record("item".into())]);
+
+        // Words which only look like they have type arguments are called as they are
+        for name in ["<", "<=", "<<", "ptr<", "a<1>", "a<i>b"] {
+            let plain = call(name);
+            assert_eq!(plain.name, name);
+            assert!(plain.type_args.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_generic_type_args() {
+        let error = parse_error("a : alloca<'T> drop ;");
+        assert_eq!(
+            error.message,
+            "expected type argument in function body, found `'T`"
+        );
+        assert_eq!(error.span.unwrap(), span(11, 2, 1, 12));
+        assert_eq!(
+            error.help,
+            vec!["type arguments to `alloca` cannot be generic".to_string()]
+        );
+
+        let error = parse_error("a : mem::pair<i,*'U> ;");
+        assert_eq!(
+            error.message,
+            "expected type argument in function body, found `'U`"
+        );
+        assert_eq!(
+            error.help,
+            vec!["type arguments to `mem::pair` cannot be generic".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        let function = |source| match top_level_item(input(source)) {
+            Ok((_, TopLevelItem::Impl(function))) => function,
+            other => panic!("expected impl, got {:?}", other),
+        };
+        let inc = function("pub inline inc i -> i : 1 + ;");
+        assert!(inc.is_pub && inc.is_inline);
+        assert_eq!(inc.head.name, "inc");
+        assert!(!function("inc i -> i : 1 + ;").is_inline);
+        assert_eq!(function("inliner : ;").head.name, "inliner");
+
+        // Only implementations can be inline
+        assert!(parse_module("inline inc i -> i;", FileId::default()).is_err());
+        // Functions may still be named `extern`
+        assert_eq!(function("extern : ;").head.name, "extern");
+    }
+
+    #[test]
+    fn test_const() {
+        let constant = |source| match top_level_item(input(source)) {
+            Ok((_, TopLevelItem::Const(constant))) => constant,
+            other => panic!("expected constant, got {:?}", other),
+        };
+        assert_eq!(constant("let a 5;").body.words.len(), 1);
+        assert_eq!(constant("let e : a 1 + ;").body.words.len(), 3);
+        let e = constant("pub let e:a 1 +;");
+        assert!(e.is_pub);
+        assert_eq!(e.name, "e");
+
+        // Functions may still be named `let`
+        assert!(matches!(
+            top_level_item(input("let : ;")),
+            Ok((_, TopLevelItem::Impl(_)))
+        ));
+
+        let error = parse_module("let a 5", FileId::default()).unwrap_err();
+        assert_eq!(error.message, "unterminated constant, expected `;`");
+    }
+
+    #[test]
+    fn test_macro() {
+        let macro_def = |source| match top_level_item(input(source)) {
+            Ok((_, TopLevelItem::Macro(macro_def))) => macro_def,
+            other => panic!("expected macro, got {:?}", other),
+        };
+        assert_eq!(macro_def("macro nip : swap drop ;").body.words.len(), 2);
+        assert_eq!(macro_def("macro nothing:;").body.words.len(), 0);
+        let tuck = macro_def("pub macro tuck : swap over ;");
+        assert!(tuck.is_pub);
+        assert_eq!(tuck.name, "tuck");
+
+        // Functions may still be named `macro`
+        assert!(matches!(
+            top_level_item(input("macro : ;")),
+            Ok((_, TopLevelItem::Impl(_)))
+        ));
+
+        let error = parse_module("macro nip swap drop ;", FileId::default()).unwrap_err();
+        assert_eq!(error.message, "expected `:` in macro, found `swap`");
+    }
+
+    #[test]
+    fn test_record() {
+        let record = |source| match top_level_item(input(source)) {
+            Ok((remaining, TopLevelItem::Record(record))) if remaining.is_empty() => record,
+            other => panic!("expected record, got {:?}", other),
+        };
+        let my_struct = record("record my-struct : foo i , bar *item, other b , ;");
+        assert!(!my_struct.is_pub);
+        assert_eq!(my_struct.name, "my-struct");
+        let fields = my_struct
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.typ.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                ("foo", Type::Concrete(ConcreteType::I32)),
+                (
+                    "bar",
+                    Type::Pointer(Box::new(Type::Record("item".into())))
+                ),
+                ("other", Type::Concrete(ConcreteType::Bool)),
+            ]
+        );
+        assert_eq!(my_struct.fields[1].span.len, "bar *item".len());
+        assert_eq!(
+            my_struct.getter_name(&my_struct.fields[0]),
+            ".my-struct.foo"
+        );
+        assert_eq!(
+            my_struct.setter_name(&my_struct.fields[0]),
+            ">my-struct.foo"
+        );
+
+        let empty = record("pub record empty:;");
+        assert!(empty.is_pub);
+        assert!(empty.fields.is_empty());
+
+        // Record types are any other name starting with a letter, which is not mistaken for a
+        // concrete type it starts with
+        let (_, typ) = defined_function_type(input("item my_struct2->i")).unwrap();
+        assert_eq!(
+            typ.inputs,
+            vec![
+                Type::Record("item".into()),
+                Type::Record("my_struct2".into())
+            ]
+        );
+        assert_eq!(typ.outputs, vec![Type::Concrete(ConcreteType::I32)]);
+
+        let error = |source| parse_module(source, FileId::default()).unwrap_err().message;
+        assert_eq!(
+            error("record point : x i y i ;"),
+            "expected `,` in record, found `y`"
+        );
+        assert_eq!(error("record i : ;"), "expected name, found `i`");
+    }
+
+    #[test]
+    fn test_bool_literals() {
+        let (_, block) = code_block(input("true false t f")).unwrap();
+        assert!(matches!(block.words[0], Word::BoolLiteral(true, _)));
+        assert!(matches!(block.words[1], Word::BoolLiteral(false, _)));
+        // `t` and `f` are ordinary words
+        assert!(matches!(&block.words[2], Word::FunctionCall(call) if call.name == "t"));
+        assert!(matches!(&block.words[3], Word::FunctionCall(call) if call.name == "f"));
+        assert!(matches!(
+            top_level_item(input("t : ;")),
+            Ok((_, TopLevelItem::Impl(_)))
+        ));
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        let error = parse_error("true : ;");
+        assert_eq!(error.message, "expected name, found `true`");
+        assert_eq!(error.span.unwrap(), span(0, 4, 1, 1));
+        assert_eq!(
+            error.help,
+            vec!["`true` is a bool literal, so it cannot be the name of a definition".to_string()]
+        );
+
+        let error = parse_error("a;\nextern f i -> i;");
+        assert_eq!(error.message, "expected name, found `f`");
+        assert_eq!(
+            error.help,
+            vec!["`f` is the name of a type, so it cannot be the name of a definition".to_string()]
+        );
+        assert_eq!(error.notes, vec!["in top level item `f`".to_string()]);
+
+        let error = parse_error("num 'T:num -> 'T;");
+        assert_eq!(
+            error.help,
+            vec!["`num` is the name of a type class, so it cannot be the name of a definition"
+                .to_string()]
+        );
+
+        for source in ["let false 0;", "pub macro uq : ;", "inline b -> : ;", "q;", "int;"] {
+            assert_eq!(parse_error(source).message.split(',').next(), Some("expected name"));
+        }
+    }
+
+    #[test]
+    fn test_module() -> TestResult {
+        module(input("a; e;")).test()?;
+        module(input("a;e;")).test()?;
+        module(input("a;")).test()?;
+        module(input(" a; e; ")).test()?;
+        module(input("")).test()?;
+        module(input("[comment]")).test()
+    }
+
+    #[test]
+    fn test_whitespace() -> TestResult {
+        whitespace(input(" ")).test()?;
+        whitespace(input("[comment]")).test()?;
+        whitespace(input(" \t\n[co\nmment] ")).test()?;
+        whitespace(input("[comment][com \r\nm\rent]")).test()?;
+        maybe_whitespace(input(" ")).test()?;
+        maybe_whitespace(input(" \t\n[comment] ")).test()?;
+        maybe_whitespace(input("[comment][comment]")).test()?;
+        maybe_whitespace(input("")).test()
+    }
+
+    #[test]
+    fn test_if() -> TestResult {
+        if_statement(input("? : ;")).test()?;
+        if_statement(input("? dup 3 + : dup 4 + ;")).test()?;
+        if_statement(input("? dup 3 + print : ;")).test()?;
+        function_impl(input("a b->i : ? 1 : 2 ;;")).test()?;
+        function_impl(input("a -> : 3 4 = ? 1 : 2 ; drop ;")).test()
+    }
+
+    #[test]
+    fn testwhile() -> TestResult {
+        while_statement(input("@ true : ;")).test()?;
+        while_statement(input("@ 3 4 = : dup print ;")).test()?;
+        function_impl(input("a b->i : drop 0 @ dup 10 < : 1 + ; ;")).test()
+    }
+
+    fn span(offset: usize, len: usize, line: u32, column: usize) -> Span {
+        Span {
+            file: FileId::default(),
+            offset,
+            len,
+            line,
+            column,
+            expansion: None,
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let (_, items) = module(input("a;\nfoo i -> i :\n  1 + ? true : 2.0 ;\n;")).unwrap();
+        match &items[0] {
+            TopLevelItem::Decl(decl) => {
+                assert_eq!(decl.span, span(0, 2, 1, 1));
+                assert_eq!(decl.head.name_span, span(0, 1, 1, 1));
+            }
+            _ => panic!("expected decl"),
+        }
+        let function = match &items[1] {
+            TopLevelItem::Impl(function) => function,
+            _ => panic!("expected impl"),
+        };
+        assert_eq!(function.span, span(3, 35, 2, 1));
+        assert_eq!(function.head.name_span, span(3, 3, 2, 1));
+        assert_eq!(function.head.span, span(3, 10, 2, 1));
+        assert_eq!(function.body.span, span(18, 18, 3, 3));
+
+        let words = &function.body.words;
+        assert!(matches!(&words[0], Word::IntLiteral(n) if n.span == span(18, 1, 3, 3)));
+        assert!(matches!(&words[1], Word::FunctionCall(call) if call.span == span(20, 1, 3, 5)));
+        match &words[2] {
+            Word::IfStatement(statement) => {
+                assert_eq!(statement.span, span(22, 14, 3, 7));
+                let true_word = &statement.true_branch.words[0];
+                assert!(matches!(true_word, Word::BoolLiteral(true, s) if *s == span(24, 4, 3, 9)));
+                let false_word = &statement.false_branch.words[0];
+                assert!(matches!(
+                    false_word,
+                    Word::FloatLiteral(_, _, s) if *s == span(31, 3, 3, 16)
+                ));
+            }
+            _ => panic!("expected if statement"),
+        }
+    }
+
+    #[test]
+    fn test_span_file() {
+        let (_, items) = module(Input::new_extra("a;", FileId(3))).unwrap();
+        match &items[0] {
+            TopLevelItem::Decl(decl) => assert_eq!(decl.span.file, FileId(3)),
+            _ => panic!("expected decl"),
+        }
+    }
+
+    fn parse_error(source: &str) -> Diagnostic {
+        parse_module(source, FileId::default()).unwrap_err()
+    }
+
+    #[test]
+    fn test_repl_line() {
+        let line = |source| parse_repl_line(source, FileId::default());
+        assert!(matches!(line("sq i -> i : dup * ;"), Ok(ReplLine::Items(items)) if items.len() == 1));
+        assert!(matches!(line("a; e;"), Ok(ReplLine::Items(items)) if items.len() == 2));
+        assert!(matches!(line(" 3 sq ? 1 : 2 ; "), Ok(ReplLine::Expression(block)) if block.words.len() == 3));
+        assert!(matches!(line(""), Ok(ReplLine::Expression(block)) if block.words.is_empty()));
+
+        let error = line("1 ? 2").err().unwrap();
+        assert_eq!(error.message, "unterminated `?` if-statement, expected `:`");
+        let error = line("sq i -> i : dup *").err().unwrap();
+        assert_eq!(error.message, "unterminated function body, expected `;`");
+    }
+
+    #[test]
+    fn test_unterminated_errors() {
+        let error = parse_error("a : ? 1 : 2");
+        assert_eq!(error.message, "unterminated `?` if-statement, expected `;`");
+        assert_eq!(error.span.unwrap(), span(11, 0, 1, 12));
+        assert_eq!(
+            error.notes,
+            vec![
+                "the `?` if-statement starts at 1:5".to_string(),
+                "in top level item `a`".to_string()
+            ]
+        );
+
+        let error = parse_error("a : 1 [comment");
+        assert_eq!(error.message, "unterminated comment, expected `]`");
+
+        let error = parse_error("a;\ne : @ true : 1 drop");
+        assert_eq!(error.message, "unterminated `@` while loop, expected `;`");
+        assert_eq!(error.notes[1], "in top level item `e`");
+
+        let error = parse_error("a : 1 2 +");
+        assert_eq!(error.message, "unterminated function body, expected `;`");
+    }
+
+    #[test]
+    fn test_expected_errors() {
+        let error = parse_error("a;\nextern e i -> i 1 ;");
+        assert_eq!(error.message, "expected `;`, found `1`");
+        assert_eq!(error.span.unwrap(), span(19, 1, 2, 17));
+        assert_eq!(error.label.unwrap(), "expected `;`");
+        assert_eq!(error.notes, vec!["in top level item `e`".to_string()]);
+
+        let error = parse_error("a : 1 ] ;");
+        assert_eq!(error.message, "expected `;` in function body, found `]`");
+
+        let error = parse_error("a : ?1 : ; ;");
+        assert_eq!(
+            error.message,
+            "expected whitespace in `?` if-statement, found `1`"
+        );
+    }
+}
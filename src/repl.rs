@@ -0,0 +1,416 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::ast::{
+    visitor::{ModuleVisitor, ResultModuleVisitor},
+    CodeBlock, ConcreteType, FunctionHeader, FunctionImpl, FunctionType, ProgramTypes,
+    TopLevelItem, Type,
+};
+use crate::cli::Options;
+use crate::codegen::{module::ModuleCodeGen, CodeGenError};
+use crate::diagnostic::{Diagnostic, SourceMap};
+use crate::driver::{load_sources, prelude, render_diagnostics};
+use crate::expand::{ExpandError, MacroExpander};
+use crate::loader::{module_name, Loader};
+use crate::parser::{parse_repl_line, ReplLine};
+use crate::resolve::{NameResolver, ResolveError, ROOT_MODULE};
+use crate::typecheck::{
+    CodeBlockTypeChecker, FunctionMapBuilder, ModuleTypeChecker, TypeCheckError,
+};
+
+/// Name of the function each evaluated line is compiled into. Words cannot contain `[`, so this
+/// never clashes with a user defined function.
+const LINE_FUNCTION: &str = "[repl]";
+
+/// Runs an interactive session on stdin. The standard library and input files from `options` are
+/// defined before the first line is read, and the public functions of each are available without
+/// importing them.
+pub fn run(options: &Options) -> Result<(), String> {
+    let mut prelude = prelude(options);
+    prelude.extend(
+        options
+            .inputs
+            .iter()
+            .map(|path| module_name(&path.display().to_string())),
+    );
+    let mut repl = Repl::new(
+        Loader::new(options.include_dirs.clone()),
+        NameResolver::new(prelude),
+    );
+    let prelude = load_sources(options, &mut repl.loader, &mut repl.sources)
+        .map_err(|diagnostics| render_diagnostics(&diagnostics, &repl.sources))?;
+    repl.define(prelude)
+        .map_err(|diagnostics| render_diagnostics(&diagnostics, &repl.sources))?;
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        print!("> ");
+        stdout.flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            // End of input
+            println!();
+            return Ok(());
+        }
+        match repl.eval_line(line) {
+            Ok(()) => println!("{}", repl.format_stack()),
+            Err(diagnostics) => eprint!("{}", render_diagnostics(&diagnostics, &repl.sources)),
+        }
+    }
+}
+
+struct Repl {
+    // Every line entered is kept, so diagnostics can refer back to earlier definitions
+    sources: SourceMap,
+    line_count: usize,
+    // Kept between lines so that files are only imported once
+    loader: Loader,
+    resolver: NameResolver,
+    expander: MacroExpander,
+    // Every item defined so far. This is compiled again along with each evaluated line.
+    items: Vec<TopLevelItem>,
+    types: ProgramTypes,
+    // The types and values currently on the stack. Each value is held in a 64 bit slot, as
+    // expected by the stack entry function (see `ModuleCodeGen::with_stack_entry`).
+    stack: Vec<Type>,
+    values: Vec<u64>,
+}
+
+impl Repl {
+    fn new(loader: Loader, resolver: NameResolver) -> Self {
+        Self {
+            sources: SourceMap::new(),
+            line_count: 0,
+            loader,
+            resolver,
+            expander: MacroExpander::new(),
+            items: Vec::new(),
+            types: ProgramTypes::default(),
+            stack: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn eval_line(&mut self, line: String) -> Result<(), Vec<Diagnostic>> {
+        self.line_count += 1;
+        let file = self.sources.add(
+            format!("<repl:{}>", self.line_count),
+            ROOT_MODULE.to_string(),
+            line,
+        );
+        match parse_repl_line(&self.sources.get(file).source, file).map_err(|e| vec![e])? {
+            ReplLine::Items(items) => {
+                // Imports in the REPL are relative to the working directory
+                let items =
+                    self.loader
+                        .resolve_imports(items, Some(Path::new(".")), &mut self.sources)?;
+                self.define(items)
+            }
+            ReplLine::Expression(block) => self.evaluate(block),
+        }
+    }
+
+    /// Adds `items` to the session, as long as they typecheck against everything already defined
+    fn define(&mut self, items: Vec<TopLevelItem>) -> Result<(), Vec<Diagnostic>> {
+        let defined_count = self.items.len();
+        self.items.extend(items);
+
+        // The resolver and expander are restored if the new items are rejected, so nothing they
+        // defined is kept
+        let resolver = self.resolver.clone();
+        let expander = self.expander.clone();
+        let resolved = self
+            .resolver
+            .resolve(&mut self.items[defined_count..], &self.sources)
+            .map_err(|errors| errors.iter().map(ResolveError::to_diagnostic).collect());
+        let expanded = resolved.and_then(|_| {
+            self.expander
+                .expand(&mut self.items[defined_count..], &mut self.sources)
+                .map_err(|errors| errors.iter().map(ExpandError::to_diagnostic).collect())
+        });
+        let types = expanded.and_then(|_| {
+            FunctionMapBuilder::new()
+                .walk_result(&mut self.items)
+                .map_err(|e| vec![e.to_diagnostic()])
+        });
+        // Everything before the new items has already been checked
+        let checked = types.and_then(|types| {
+            ModuleTypeChecker::new(&types)
+                .walk(&mut self.items[defined_count..])
+                .map(|_| types)
+                .map_err(|errors| errors.iter().map(TypeCheckError::to_diagnostic).collect())
+        });
+
+        match checked {
+            Ok(types) => {
+                self.types = types;
+                Ok(())
+            }
+            Err(diagnostics) => {
+                self.items.truncate(defined_count);
+                self.resolver = resolver;
+                self.expander = expander;
+                Err(diagnostics)
+            }
+        }
+    }
+
+    /// Runs `block` on the current stack. The block is compiled into a function which takes the
+    /// current stack as its inputs, and returns the new stack.
+    fn evaluate(&mut self, mut block: CodeBlock) -> Result<(), Vec<Diagnostic>> {
+        self.resolver
+            .resolve_block(&mut block, ROOT_MODULE)
+            .map_err(|errors| {
+                errors
+                    .iter()
+                    .map(ResolveError::to_diagnostic)
+                    .collect::<Vec<_>>()
+            })?;
+        self.expander
+            .expand_block(&mut block, &mut self.sources)
+            .map_err(|errors| {
+                errors
+                    .iter()
+                    .map(ExpandError::to_diagnostic)
+                    .collect::<Vec<_>>()
+            })?;
+        let stack = CodeBlockTypeChecker::new(self.stack.clone(), &self.types)
+            .check(&mut block, None)
+            .map_err(|e| vec![e.to_diagnostic()])?;
+        // Each value on the stack is held in a 64 bit slot, which a record may not fit in
+        if let Some(record) = stack.iter().find(|typ| matches!(typ, Type::Record(_))) {
+            return Err(vec![Diagnostic::error(format!(
+                "a `{}` cannot be left on the stack in the REPL",
+                record
+            ))
+            .with_span(block.span)
+            .with_help("use its fields' getters, then drop it")]);
+        }
+        // The storage a pointer refers to, such as a string literal's global or a stack slot from
+        // `alloca` or `ref`, belongs to the module compiled for this line, which is freed once the
+        // line has run
+        if let Some(pointer) = stack.iter().find(|typ| matches!(typ, Type::Pointer(_))) {
+            return Err(vec![Diagnostic::error(format!(
+                "a `{}` cannot be left on the stack in the REPL",
+                pointer
+            ))
+            .with_span(block.span)
+            .with_help(
+                "use the pointer on the line it was created on, then drop it",
+            )]);
+        }
+
+        let span = block.span;
+        let head = FunctionHeader {
+            name: LINE_FUNCTION.to_string(),
+            typ: FunctionType {
+                inputs: self.stack.clone(),
+                outputs: stack.clone(),
+            },
+            name_span: span,
+            span,
+        };
+        self.types
+            .functions
+            .insert(LINE_FUNCTION.to_string(), head.typ.clone());
+        self.items.push(TopLevelItem::Impl(FunctionImpl {
+            head,
+            is_pub: false,
+            is_inline: false,
+            body: block,
+            span,
+        }));
+
+        let module = ModuleCodeGen::new(&self.types)
+            .with_stack_entry(LINE_FUNCTION)
+            .walk(&mut self.items);
+        self.items.pop();
+        self.types.functions.remove(LINE_FUNCTION);
+
+        let module = module.map_err(|errors| {
+            errors
+                .iter()
+                .map(CodeGenError::to_diagnostic)
+                .collect::<Vec<_>>()
+        })?;
+        self.values = module
+            .run_stack_entry(&self.values, stack.len())
+            .map_err(|message| vec![Diagnostic::error(message)])?;
+        self.stack = stack;
+        Ok(())
+    }
+
+    /// Shows each value on the stack with its type, eg. `<2> 3:i 1.5:f`
+    fn format_stack(&self) -> String {
+        let mut formatted = format!("<{}>", self.stack.len());
+        for (typ, value) in self.stack.iter().zip(&self.values) {
+            formatted.push_str(&format!(" {}:{}", format_value(*value, typ), typ));
+        }
+        formatted
+    }
+}
+
+/// Interprets a stack slot as a value of type `typ`
+fn format_value(value: u64, typ: &Type) -> String {
+    match typ {
+        Type::Concrete(concrete) => match concrete {
+            ConcreteType::I8 => (value as i8).to_string(),
+            ConcreteType::U8 => (value as u8).to_string(),
+            ConcreteType::I32 => (value as i32).to_string(),
+            ConcreteType::U32 => (value as u32).to_string(),
+            ConcreteType::I64 => (value as i64).to_string(),
+            ConcreteType::U64 => value.to_string(),
+            ConcreteType::F32 => f32::from_bits(value as u32).to_string(),
+            ConcreteType::F64 => f64::from_bits(value).to_string(),
+            ConcreteType::Bool => (value & 1 == 1).to_string(),
+        },
+        Type::Generic(..) | Type::UntypedInt(_) => {
+            unreachable!("values on the stack always have a concrete type")
+        }
+        Type::Pointer(_) | Type::Record(_) => {
+            unreachable!("pointers and records cannot be left on the stack")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> Repl {
+        let mut repl = Repl::new(
+            Loader::new(Vec::new()),
+            NameResolver::new(prelude(&Options::default())),
+        );
+        let prelude =
+            load_sources(&Options::default(), &mut repl.loader, &mut repl.sources).unwrap();
+        repl.define(prelude).unwrap();
+        repl
+    }
+
+    fn eval(repl: &mut Repl, line: &str) -> Result<String, Vec<Diagnostic>> {
+        repl.eval_line(line.to_string())?;
+        Ok(repl.format_stack())
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut repl = repl();
+        assert_eq!(eval(&mut repl, "1 2").unwrap(), "<2> 1:i 2:i");
+        assert_eq!(eval(&mut repl, "+ -5").unwrap(), "<2> 3:i -5:i");
+        assert_eq!(
+            eval(&mut repl, "swap 2.5 true").unwrap(),
+            "<4> -5:i 3:i 2.5:f true:b"
+        );
+        assert_eq!(eval(&mut repl, "drop drop drop drop").unwrap(), "<0>");
+        assert_eq!(
+            eval(&mut repl, "0xFF 'a' -10q 255uc 1.5d").unwrap(),
+            "<5> 255:i 97:c -10:q 255:uc 1.5:d"
+        );
+    }
+
+    #[test]
+    fn test_define() {
+        let mut repl = repl();
+        assert_eq!(eval(&mut repl, "sq i -> i : dup * ;").unwrap(), "<0>");
+        assert_eq!(eval(&mut repl, "3 sq").unwrap(), "<1> 9:i");
+        assert_eq!(eval(&mut repl, "std::inc").unwrap(), "<1> 10:i");
+        assert_eq!(eval(&mut repl, "sq dec").unwrap(), "<1> 99:i");
+        assert_eq!(eval(&mut repl, "drop 9").unwrap(), "<1> 9:i");
+        assert_eq!(
+            eval(&mut repl, "dup 10 < ? sq : drop 0 ;").unwrap(),
+            "<1> 81:i"
+        );
+        assert_eq!(
+            eval(&mut repl, "@ dup 0 > : 1 - ; 2 1").unwrap(),
+            "<3> 0:i 2:i 1:i"
+        );
+        assert_eq!(
+            eval(&mut repl, "macro sum : + + ;").unwrap(),
+            "<3> 0:i 2:i 1:i"
+        );
+        assert_eq!(eval(&mut repl, "sum 5 6 7 sum").unwrap(), "<2> 3:i 18:i");
+    }
+
+    #[test]
+    fn test_records() {
+        let mut repl = repl();
+        eval(&mut repl, "record point : x i , y f , ;").unwrap();
+        assert_eq!(
+            eval(&mut repl, "construct<point> 2 >point.x .point.x nip").unwrap(),
+            "<1> 2:i"
+        );
+
+        // A record does not fit in a stack slot, so it cannot be left on the stack
+        let errors = eval(&mut repl, "construct<point>").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "a `point` cannot be left on the stack in the REPL"
+        );
+        assert_eq!(eval(&mut repl, "1").unwrap(), "<2> 2:i 1:i");
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut repl = repl();
+        assert_eq!(eval(&mut repl, "1 \"hi\" drop").unwrap(), "<1> 1:i");
+
+        // The string's global is freed along with the line's module, so it cannot be left on the
+        // stack for the next line
+        let errors = eval(&mut repl, "\"hello world\"").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "a `*c` cannot be left on the stack in the REPL"
+        );
+        assert_eq!(eval(&mut repl, "2").unwrap(), "<2> 1:i 2:i");
+    }
+
+    #[test]
+    fn test_pointers() {
+        let mut repl = repl();
+        assert_eq!(
+            eval(&mut repl, "alloca<q> dup 77q store deref").unwrap(),
+            "<1> 77:q"
+        );
+
+        // The slot is on the stack of the line's function, which has returned by the next line
+        let errors = eval(&mut repl, "alloca<q> dup 77q store").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "a `*q` cannot be left on the stack in the REPL"
+        );
+        let errors = eval(&mut repl, "ref").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "a `*q` cannot be left on the stack in the REPL"
+        );
+        assert_eq!(eval(&mut repl, "1q +").unwrap(), "<1> 78:q");
+    }
+
+    #[test]
+    fn test_errors_keep_state() {
+        let mut repl = repl();
+        eval(&mut repl, "1").unwrap();
+
+        let errors = eval(&mut repl, "2.0 +").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected a `f` on the stack for `+`, but got a `i`"
+        );
+        let errors = eval(&mut repl, "bad -> i : 1.0 ;").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected `bad` to leave `i` on the stack, instead it left `f`"
+        );
+        assert!(eval(&mut repl, "bad").is_err());
+
+        assert_eq!(eval(&mut repl, "1 +").unwrap(), "<1> 2:i");
+    }
+}
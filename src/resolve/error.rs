@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+    pub span: Span,
+}
+
+impl ResolveError {
+    pub fn new(kind: ResolveErrorKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveErrorKind {
+    // A word or record type which is exported by more than one imported module. `modules` is
+    // sorted.
+    Ambiguous { name: String, modules: Vec<String> },
+    // A word or record type which exists in an imported module, but is not marked `pub`
+    Private { name: String, module: String },
+    // A word or record type which is only defined in modules that are not imported
+    NotImported { name: String, module: String },
+    // A qualified name whose module is not imported
+    ModuleNotImported(String),
+    // A qualified name whose module does not define it
+    NotInModule { name: String, module: String },
+}
+
+impl fmt::Display for ResolveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveErrorKind::Ambiguous { name, modules } => {
+                let modules = modules
+                    .iter()
+                    .map(|module| format!("`{}`", module))
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "`{}` is ambiguous, it is exported by modules {}",
+                    name,
+                    modules.join(", ")
+                )
+            }
+            ResolveErrorKind::Private { name, module } => {
+                write!(f, "`{}` is private to module `{}`", name, module)
+            }
+            ResolveErrorKind::NotImported { name, module } => write!(
+                f,
+                "`{}` is defined in module `{}`, which is not imported",
+                name, module
+            ),
+            ResolveErrorKind::ModuleNotImported(module) => {
+                write!(f, "module `{}` is not imported", module)
+            }
+            ResolveErrorKind::NotInModule { name, module } => {
+                write!(f, "module `{}` does not define `{}`", module, name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl ResolveError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string()).with_span(self.span);
+        match &self.kind {
+            ResolveErrorKind::Ambiguous { name, modules } => {
+                diagnostic.with_label("used here").with_help(format!(
+                    "qualify the name with the module it is from, eg. `{}::{}`",
+                    modules[0], name
+                ))
+            }
+            ResolveErrorKind::Private { module, .. } => {
+                diagnostic.with_label("used here").with_help(format!(
+                    "mark it `pub` in module `{}` to use it from other modules",
+                    module
+                ))
+            }
+            ResolveErrorKind::NotImported { module, .. }
+            | ResolveErrorKind::ModuleNotImported(module) => diagnostic
+                .with_label("used here")
+                .with_help(format!("import the module with `use {};`", module)),
+            ResolveErrorKind::NotInModule { .. } => diagnostic.with_label("not found"),
+        }
+    }
+}
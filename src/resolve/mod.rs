@@ -0,0 +1,516 @@
+mod error;
+#[cfg(test)]
+mod resolve_tests;
+
+use std::collections::HashMap;
+
+use crate::ast::{
+    visitor::{CodeBlockVisitor, ModuleVisitor},
+    CodeBlock, ConcreteType, Const, FunctionCall, FunctionDecl, FunctionImpl, FunctionType,
+    IfStatement, Import, IntLiteral, Macro, Record, Span, TopLevelItem, Type, WhileStatement,
+};
+use crate::diagnostic::SourceMap;
+use crate::loader::module_name;
+
+pub use error::{ResolveError, ResolveErrorKind};
+
+/// Functions in the root module keep their own names rather than being qualified. The REPL
+/// defines its functions here, so they are shown in diagnostics as they were written.
+pub const ROOT_MODULE: &str = "";
+
+#[derive(Debug, Clone)]
+struct Definition {
+    is_pub: bool,
+    // Extern and intrinsic functions keep their own name, since it is what the linker and codegen
+    // know them by. Every other function is qualified by its module.
+    is_global: bool,
+}
+
+/// Records are named separately from functions, so a record and a function may share a name
+#[derive(Debug, Clone, Copy)]
+enum Namespace {
+    Functions,
+    Records,
+}
+
+/// The functions and records defined in a module, and the modules it imports
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    functions: HashMap<String, Definition>,
+    records: HashMap<String, Definition>,
+    imports: Vec<String>,
+}
+
+impl Scope {
+    fn names(&self, namespace: Namespace) -> &HashMap<String, Definition> {
+        match namespace {
+            Namespace::Functions => &self.functions,
+            Namespace::Records => &self.records,
+        }
+    }
+
+    fn names_mut(&mut self, namespace: Namespace) -> &mut HashMap<String, Definition> {
+        match namespace {
+            Namespace::Functions => &mut self.functions,
+            Namespace::Records => &mut self.records,
+        }
+    }
+}
+
+/// Resolves each word to the function it calls, renaming every function to a name which is
+/// unique across the program (eg. `io::print`). Record types are resolved and renamed the same
+/// way. After this, later passes can treat the program as a single namespace.
+///
+/// An unqualified word refers to a function in the current module if there is one, otherwise to
+/// a `pub` function in one of the modules it imports. Words and types which are not defined
+/// anywhere are left alone, to be reported by the typechecker.
+#[derive(Debug, Clone, Default)]
+pub struct NameResolver {
+    modules: HashMap<String, Scope>,
+    // Modules which every module imports implicitly, eg. `std`
+    prelude: Vec<String>,
+}
+
+impl NameResolver {
+    pub fn new(prelude: Vec<String>) -> Self {
+        Self {
+            modules: HashMap::new(),
+            prelude,
+        }
+    }
+
+    /// Adds the functions and imports of `items`, then resolves the words in them. Functions
+    /// added by previous calls can be used by later ones.
+    pub fn resolve(
+        &mut self,
+        items: &mut [TopLevelItem],
+        sources: &SourceMap,
+    ) -> Result<(), Vec<ResolveError>> {
+        DefinitionCollector {
+            resolver: self,
+            sources,
+        }
+        .walk(items);
+        ModuleResolver::new(self, sources).walk(items)
+    }
+
+    /// Resolves the words in `block` as if it were in `module`
+    pub fn resolve_block(
+        &self,
+        block: &mut CodeBlock,
+        module: &str,
+    ) -> Result<(), Vec<ResolveError>> {
+        let errors = CodeBlockResolver::new(self, module).walk(block);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn definition(&self, module: &str, namespace: Namespace, name: &str) -> Option<&Definition> {
+        self.modules
+            .get(module)
+            .and_then(|scope| scope.names(namespace).get(name))
+    }
+
+    /// The name `name` from `module` is known by in the rest of the compiler
+    fn global_name(&self, module: &str, namespace: Namespace, name: &str) -> String {
+        match self.definition(module, namespace, name) {
+            Some(definition) if !definition.is_global && module != ROOT_MODULE => {
+                format!("{}::{}", module, name)
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// Every module whose functions can be used from `module`, other than itself
+    fn imports(&self, module: &str) -> Vec<&str> {
+        let imports = self.modules.get(module).map(|scope| &scope.imports[..]);
+        let mut imports = self
+            .prelude
+            .iter()
+            .chain(imports.into_iter().flatten())
+            .map(String::as_str)
+            .filter(|import| *import != module)
+            .collect::<Vec<_>>();
+        imports.sort_unstable();
+        imports.dedup();
+        imports
+    }
+
+    /// Finds the global name of the function or record `word` refers to when used in `module`
+    fn resolve_name(
+        &self,
+        module: &str,
+        namespace: Namespace,
+        word: &str,
+    ) -> Result<Option<String>, ResolveErrorKind> {
+        if let Some((qualifier, name)) = word.rsplit_once("::") {
+            return self
+                .resolve_qualified(module, namespace, qualifier, name)
+                .map(Some);
+        }
+        if self.definition(module, namespace, word).is_some() {
+            return Ok(Some(self.global_name(module, namespace, word)));
+        }
+
+        let imports = self.imports(module);
+        let mut exporters = imports
+            .iter()
+            .filter(
+                |import| matches!(self.definition(import, namespace, word), Some(d) if d.is_pub),
+            )
+            .map(|import| (*import, self.global_name(import, namespace, word)))
+            .collect::<Vec<_>>();
+        // The same extern function may be declared by several modules
+        exporters.sort_by(|(_, a), (_, b)| a.cmp(b));
+        exporters.dedup_by(|(_, a), (_, b)| a == b);
+        match &exporters[..] {
+            [(_, global_name)] => return Ok(Some(global_name.clone())),
+            [_, ..] => {
+                let mut modules = exporters
+                    .iter()
+                    .map(|(module, _)| module.to_string())
+                    .collect::<Vec<_>>();
+                modules.sort();
+                return Err(ResolveErrorKind::Ambiguous {
+                    name: word.to_string(),
+                    modules,
+                });
+            }
+            [] => {}
+        }
+
+        if let Some(import) = imports
+            .iter()
+            .find(|import| self.definition(import, namespace, word).is_some())
+        {
+            return Err(ResolveErrorKind::Private {
+                name: word.to_string(),
+                module: import.to_string(),
+            });
+        }
+        let defined_in = self
+            .modules
+            .iter()
+            .filter(|(_, scope)| scope.names(namespace).contains_key(word))
+            .map(|(module, _)| module)
+            .min();
+        match defined_in {
+            Some(other) => Err(ResolveErrorKind::NotImported {
+                name: word.to_string(),
+                module: other.clone(),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn resolve_qualified(
+        &self,
+        module: &str,
+        namespace: Namespace,
+        qualifier: &str,
+        name: &str,
+    ) -> Result<String, ResolveErrorKind> {
+        if qualifier != module && !self.imports(module).contains(&qualifier) {
+            return Err(ResolveErrorKind::ModuleNotImported(qualifier.to_string()));
+        }
+        match self.definition(qualifier, namespace, name) {
+            None => Err(ResolveErrorKind::NotInModule {
+                name: name.to_string(),
+                module: qualifier.to_string(),
+            }),
+            Some(definition) if qualifier != module && !definition.is_pub => {
+                Err(ResolveErrorKind::Private {
+                    name: name.to_string(),
+                    module: qualifier.to_string(),
+                })
+            }
+            Some(_) => Ok(self.global_name(qualifier, namespace, name)),
+        }
+    }
+
+    /// Renames the records in `types` to their global names, as used in `module`
+    fn resolve_types<'t>(
+        &self,
+        module: &str,
+        types: impl IntoIterator<Item = &'t mut Type>,
+        span: Span,
+    ) -> Vec<ResolveError> {
+        types
+            .into_iter()
+            .filter_map(|typ| self.resolve_type(module, typ).err())
+            .map(|kind| ResolveError::new(kind, span))
+            .collect()
+    }
+
+    fn resolve_type(&self, module: &str, typ: &mut Type) -> Result<(), ResolveErrorKind> {
+        match typ {
+            Type::Record(name) => {
+                if let Some(global_name) = self.resolve_name(module, Namespace::Records, name)? {
+                    *name = global_name;
+                }
+                Ok(())
+            }
+            Type::Pointer(inner) => self.resolve_type(module, inner),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn signature_types(typ: &mut FunctionType) -> impl Iterator<Item = &mut Type> {
+    typ.inputs.iter_mut().chain(&mut typ.outputs)
+}
+
+/// Records which module each function and record is defined in, and what each module imports
+struct DefinitionCollector<'a> {
+    resolver: &'a mut NameResolver,
+    sources: &'a SourceMap,
+}
+
+impl DefinitionCollector<'_> {
+    fn scope(&mut self, span: Span) -> &mut Scope {
+        let module = &self.sources.get(span.file).module;
+        self.resolver.modules.entry(module.clone()).or_default()
+    }
+
+    fn define(
+        &mut self,
+        namespace: Namespace,
+        name: &str,
+        span: Span,
+        is_pub: bool,
+        is_global: bool,
+    ) {
+        // A function may be both declared and implemented, so its properties are merged
+        let definition = self
+            .scope(span)
+            .names_mut(namespace)
+            .entry(name.to_string())
+            .or_insert(Definition {
+                is_pub: false,
+                is_global: false,
+            });
+        definition.is_pub |= is_pub;
+        definition.is_global |= is_global;
+    }
+}
+
+impl ModuleVisitor for DefinitionCollector<'_> {
+    type ItemResult = ();
+    type FinalOutput = ();
+
+    fn visit_decl(&mut self, function: &mut FunctionDecl) {
+        self.define(
+            Namespace::Functions,
+            &function.head.name,
+            function.span,
+            function.is_pub,
+            function.is_extern || function.is_intrinsic,
+        );
+    }
+
+    fn visit_impl(&mut self, function: &mut FunctionImpl) {
+        let (name, is_pub) = (&function.head.name, function.is_pub);
+        self.define(Namespace::Functions, name, function.span, is_pub, false);
+    }
+
+    fn visit_const(&mut self, constant: &mut Const) {
+        let (name, is_pub) = (&constant.name, constant.is_pub);
+        self.define(Namespace::Functions, name, constant.span, is_pub, false);
+    }
+
+    fn visit_macro(&mut self, macro_: &mut Macro) {
+        let (name, is_pub) = (&macro_.name, macro_.is_pub);
+        self.define(Namespace::Functions, name, macro_.span, is_pub, false);
+    }
+
+    fn visit_record(&mut self, record: &mut Record) {
+        let (span, is_pub) = (record.span, record.is_pub);
+        self.define(Namespace::Records, &record.name, span, is_pub, false);
+        for field in &record.fields {
+            let (getter, setter) = (record.getter_name(field), record.setter_name(field));
+            self.define(Namespace::Functions, &getter, span, is_pub, false);
+            self.define(Namespace::Functions, &setter, span, is_pub, false);
+        }
+    }
+
+    fn visit_import(&mut self, import: &mut Import) {
+        let imported = module_name(&import.path);
+        let scope = self.scope(import.span);
+        if !scope.imports.contains(&imported) {
+            scope.imports.push(imported);
+        }
+    }
+
+    fn finalize(self) {}
+}
+
+/// Renames every function and record to its global name, and resolves the words in each function
+/// body and the records in each type
+struct ModuleResolver<'a> {
+    resolver: &'a NameResolver,
+    sources: &'a SourceMap,
+    errors: Vec<ResolveError>,
+}
+
+impl<'a> ModuleResolver<'a> {
+    fn new(resolver: &'a NameResolver, sources: &'a SourceMap) -> Self {
+        Self {
+            resolver,
+            sources,
+            errors: Vec::new(),
+        }
+    }
+
+    fn module(&self, span: Span) -> &'a str {
+        &self.sources.get(span.file).module
+    }
+}
+
+impl ModuleVisitor for ModuleResolver<'_> {
+    type ItemResult = ();
+    type FinalOutput = Result<(), Vec<ResolveError>>;
+
+    fn visit_decl(&mut self, function: &mut FunctionDecl) {
+        let module = self.module(function.span);
+        let head = &mut function.head;
+        head.name = self
+            .resolver
+            .global_name(module, Namespace::Functions, &head.name);
+        self.errors.extend(self.resolver.resolve_types(
+            module,
+            signature_types(&mut head.typ),
+            head.span,
+        ));
+    }
+
+    fn visit_impl(&mut self, function: &mut FunctionImpl) {
+        let module = self.module(function.span);
+        let head = &mut function.head;
+        head.name = self
+            .resolver
+            .global_name(module, Namespace::Functions, &head.name);
+        self.errors.extend(self.resolver.resolve_types(
+            module,
+            signature_types(&mut head.typ),
+            head.span,
+        ));
+        self.errors
+            .extend(CodeBlockResolver::new(self.resolver, module).walk(&mut function.body));
+    }
+
+    fn visit_const(&mut self, constant: &mut Const) {
+        let module = self.module(constant.span);
+        constant.name = self
+            .resolver
+            .global_name(module, Namespace::Functions, &constant.name);
+        self.errors
+            .extend(CodeBlockResolver::new(self.resolver, module).walk(&mut constant.body));
+    }
+
+    // Words in a macro refer to what they would in the macro's own module, not where it is used
+    fn visit_macro(&mut self, macro_: &mut Macro) {
+        let module = self.module(macro_.span);
+        macro_.name = self
+            .resolver
+            .global_name(module, Namespace::Functions, &macro_.name);
+        self.errors
+            .extend(CodeBlockResolver::new(self.resolver, module).walk(&mut macro_.body));
+    }
+
+    fn visit_record(&mut self, record: &mut Record) {
+        let module = self.module(record.span);
+        record.name = self
+            .resolver
+            .global_name(module, Namespace::Records, &record.name);
+        for field in &mut record.fields {
+            self.errors.extend(self.resolver.resolve_types(
+                module,
+                Some(&mut field.typ),
+                field.span,
+            ));
+        }
+    }
+
+    fn visit_import(&mut self, _: &mut Import) {}
+
+    fn finalize(self) -> Result<(), Vec<ResolveError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Resolves every word in a code block. Walking the block returns the errors found.
+struct CodeBlockResolver<'a> {
+    resolver: &'a NameResolver,
+    module: &'a str,
+    errors: Vec<ResolveError>,
+}
+
+impl<'a> CodeBlockResolver<'a> {
+    fn new(resolver: &'a NameResolver, module: &'a str) -> Self {
+        Self {
+            resolver,
+            module,
+            errors: Vec::new(),
+        }
+    }
+
+    fn walk_nested(&mut self, block: &mut CodeBlock) {
+        self.errors
+            .extend(CodeBlockResolver::new(self.resolver, self.module).walk(block));
+    }
+}
+
+impl CodeBlockVisitor for CodeBlockResolver<'_> {
+    type ItemResult = ();
+    type FinalOutput = Vec<ResolveError>;
+
+    fn visit_int_literal(&mut self, _: &mut IntLiteral) {}
+
+    fn visit_float_literal(&mut self, _: f64, _: ConcreteType, _: Span) {}
+
+    fn visit_bool_literal(&mut self, _: bool, _: Span) {}
+
+    fn visit_string_literal(&mut self, _: &[u8], _: Span) {}
+
+    fn visit_cast(&mut self, to: &mut Type, span: Span) {
+        self.errors
+            .extend(self.resolver.resolve_types(self.module, Some(to), span));
+    }
+
+    fn visit_function(&mut self, function: &mut FunctionCall) {
+        match self
+            .resolver
+            .resolve_name(self.module, Namespace::Functions, &function.name)
+        {
+            Ok(Some(global_name)) => function.name = global_name,
+            Ok(None) => {}
+            Err(kind) => self.errors.push(ResolveError::new(kind, function.span)),
+        }
+        self.errors.extend(self.resolver.resolve_types(
+            self.module,
+            &mut function.type_args,
+            function.span,
+        ));
+    }
+
+    fn visit_if_statement(&mut self, statement: &mut IfStatement) {
+        self.walk_nested(&mut statement.true_branch);
+        self.walk_nested(&mut statement.false_branch);
+    }
+
+    fn visit_while_statement(&mut self, statement: &mut WhileStatement) {
+        self.walk_nested(&mut statement.condition);
+        self.walk_nested(&mut statement.body);
+    }
+
+    fn finalize(self) -> Vec<ResolveError> {
+        self.errors
+    }
+}
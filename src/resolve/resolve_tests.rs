@@ -0,0 +1,324 @@
+use super::*;
+use crate::ast::Word;
+use crate::diagnostic::SourceMap;
+use crate::parser::parse_module;
+
+/// Parses each `(module, source)` pair as a separate file, then resolves them all together with
+/// `std` as the prelude
+fn resolve_files(files: &[(&str, &str)]) -> Result<Vec<TopLevelItem>, Vec<ResolveErrorKind>> {
+    let mut sources = SourceMap::new();
+    let mut items = Vec::new();
+    for (module, source) in files {
+        let file = sources.add(
+            format!("{}.f", module),
+            module.to_string(),
+            source.to_string(),
+        );
+        items.extend(parse_module(&sources.get(file).source, file).unwrap());
+    }
+    NameResolver::new(vec!["std".to_string()])
+        .resolve(&mut items, &sources)
+        .map_err(|errors| errors.into_iter().map(|e| e.kind).collect())
+        .map(|_| items)
+}
+
+/// The names of every function called in the last function of the files
+fn resolved_calls(files: &[(&str, &str)]) -> Vec<String> {
+    let items = resolve_files(files).unwrap();
+    match items.last() {
+        Some(TopLevelItem::Impl(function)) => function
+            .body
+            .words
+            .iter()
+            .map(|word| match word {
+                Word::FunctionCall(call) => call.name.clone(),
+                other => panic!("expected a function call, got {:?}", other),
+            })
+            .collect(),
+        other => panic!("expected a function implementation, got {:?}", other),
+    }
+}
+
+fn resolve_err(files: &[(&str, &str)]) -> ResolveErrorKind {
+    resolve_files(files).unwrap_err().remove(0)
+}
+
+const STD: (&str, &str) = (
+    "std",
+    "pub intrinsic dup 'T -> 'T 'T; pub extern putchar i -> ; pub nl : 10 putchar ; helper : ;",
+);
+
+#[test]
+fn test_function_names() {
+    let items = resolve_files(&[
+        STD,
+        (
+            "main",
+            "extern main; main : ; sq : ; let n 5; macro m : ; record r : x i , ;",
+        ),
+    ])
+    .unwrap();
+    let names = items
+        .iter()
+        .filter_map(|item| match item {
+            TopLevelItem::Decl(function) => Some(function.head.name.as_str()),
+            TopLevelItem::Impl(function) => Some(function.head.name.as_str()),
+            TopLevelItem::Const(constant) => Some(constant.name.as_str()),
+            TopLevelItem::Macro(macro_) => Some(macro_.name.as_str()),
+            TopLevelItem::Record(record) => Some(record.name.as_str()),
+            TopLevelItem::Import(_) => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            "dup",
+            "putchar",
+            "std::nl",
+            "std::helper",
+            "main",
+            "main",
+            "main::sq",
+            "main::n",
+            "main::m",
+            "main::r"
+        ]
+    );
+}
+
+#[test]
+fn test_resolve() {
+    assert_eq!(
+        resolved_calls(&[STD, ("main", "a : dup nl putchar std::nl ;")]),
+        vec!["dup", "std::nl", "putchar", "std::nl"]
+    );
+    // Functions in the current module shadow imported ones
+    assert_eq!(
+        resolved_calls(&[STD, ("main", "nl : ; a : nl std::nl main::nl ;")]),
+        vec!["main::nl", "std::nl", "main::nl"]
+    );
+    // Private functions can be used from within their own module
+    assert_eq!(
+        resolved_calls(&[STD, ("io", "helper : ; pub print : helper ;")]),
+        vec!["io::helper"]
+    );
+    assert_eq!(
+        resolved_calls(&[
+            STD,
+            ("io", "pub print : ;"),
+            ("main", "use io; a : print io::print ;")
+        ]),
+        vec!["io::print", "io::print"]
+    );
+    // The same extern function declared in two modules is not ambiguous
+    assert_eq!(
+        resolved_calls(&[
+            STD,
+            ("io", "pub extern putchar i -> ;"),
+            ("main", "use io; a : putchar ;")
+        ]),
+        vec!["putchar"]
+    );
+    // The getters and setters of a record are qualified by its module, like the record
+    assert_eq!(
+        resolved_calls(&[
+            STD,
+            ("shapes", "pub record point : x i , ;"),
+            (
+                "main",
+                "use shapes; a : .point.x >point.x shapes::.point.x ;"
+            )
+        ]),
+        vec!["shapes::.point.x", "shapes::>point.x", "shapes::.point.x"]
+    );
+    // Undefined words are left for the typechecker to report
+    assert_eq!(
+        resolved_calls(&[STD, ("main", "a : nothing ;")]),
+        vec!["nothing"]
+    );
+}
+
+#[test]
+fn test_resolve_errors() {
+    let io = ("io", "pub print : ; secret : ;");
+    let fmt = ("fmt", "pub print : ;");
+    assert_eq!(
+        resolve_err(&[STD, io, fmt, ("main", "use io; use fmt; a : print ;")]),
+        ResolveErrorKind::Ambiguous {
+            name: "print".to_string(),
+            modules: vec!["fmt".to_string(), "io".to_string()],
+        }
+    );
+    assert_eq!(
+        resolve_err(&[STD, io, ("main", "use io; a : secret ;")]),
+        ResolveErrorKind::Private {
+            name: "secret".to_string(),
+            module: "io".to_string(),
+        }
+    );
+    assert_eq!(
+        resolve_err(&[STD, ("main", "a : std::helper ;")]),
+        ResolveErrorKind::Private {
+            name: "helper".to_string(),
+            module: "std".to_string(),
+        }
+    );
+    assert_eq!(
+        resolve_err(&[
+            STD,
+            ("shapes", "record point : x i , ;"),
+            ("main", "use shapes; a : .point.x ;")
+        ]),
+        ResolveErrorKind::Private {
+            name: ".point.x".to_string(),
+            module: "shapes".to_string(),
+        }
+    );
+    // A private record cannot be used as a type by other modules
+    let shapes = ("shapes", "record point : x i , ;");
+    for source in [
+        "use shapes; a point -> : drop ;",
+        "use shapes; a -> *shapes::point : 0 (*point) ;",
+        "use shapes; a : construct<point> drop ;",
+        "use shapes; record line : start point , ;",
+    ] {
+        assert_eq!(
+            resolve_err(&[STD, shapes, ("main", source)]),
+            ResolveErrorKind::Private {
+                name: "point".to_string(),
+                module: "shapes".to_string(),
+            }
+        );
+    }
+    assert_eq!(
+        resolve_err(&[STD, io, ("main", "a : print ;")]),
+        ResolveErrorKind::NotImported {
+            name: "print".to_string(),
+            module: "io".to_string(),
+        }
+    );
+    assert_eq!(
+        resolve_err(&[STD, io, ("main", "a : io::print ;")]),
+        ResolveErrorKind::ModuleNotImported("io".to_string())
+    );
+    assert_eq!(
+        resolve_err(&[STD, io, ("main", "use io; a : io::missing ;")]),
+        ResolveErrorKind::NotInModule {
+            name: "missing".to_string(),
+            module: "io".to_string(),
+        }
+    );
+
+    // Every error is reported, including those in nested blocks
+    let errors = resolve_files(&[
+        STD,
+        io,
+        ("main", "a : true ? print : ; @ true : secret ; ;"),
+    ])
+    .unwrap_err();
+    assert_eq!(errors.len(), 2);
+}
+
+/// The record types used by the signatures, record fields and function bodies of `items`
+fn record_types(items: &[TopLevelItem]) -> Vec<String> {
+    fn records(typ: &Type, names: &mut Vec<String>) {
+        match typ {
+            Type::Record(name) => names.push(name.clone()),
+            Type::Pointer(inner) => records(inner, names),
+            _ => {}
+        }
+    }
+    let mut names = Vec::new();
+    for item in items {
+        match item {
+            TopLevelItem::Impl(function) => {
+                let typ = &function.head.typ;
+                for typ in typ.inputs.iter().chain(&typ.outputs) {
+                    records(typ, &mut names);
+                }
+                for word in &function.body.words {
+                    match word {
+                        Word::Cast(to, _) => records(to, &mut names),
+                        Word::FunctionCall(call) => call
+                            .type_args
+                            .iter()
+                            .for_each(|typ| records(typ, &mut names)),
+                        _ => {}
+                    }
+                }
+            }
+            TopLevelItem::Record(record) => {
+                names.push(record.name.clone());
+                for field in &record.fields {
+                    records(&field.typ, &mut names);
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+#[test]
+fn test_resolve_records() {
+    let shapes = ("shapes", "pub record point : x i , ;");
+    assert_eq!(
+        record_types(
+            &resolve_files(&[
+                STD,
+                shapes,
+                (
+                    "main",
+                    "use shapes; a point -> *point : ref (*shapes::point) construct<point> drop ;"
+                )
+            ])
+            .unwrap()
+        ),
+        vec![
+            "shapes::point",
+            "shapes::point",
+            "shapes::point",
+            "shapes::point",
+            "shapes::point"
+        ]
+    );
+
+    // Records with the same name in different modules are different records
+    let items = resolve_files(&[
+        STD,
+        shapes,
+        ("grid", "pub record point : row i , col i , ;"),
+        (
+            "main",
+            "use shapes; use grid; record line : start shapes::point , end grid::point , ;
+            a shapes::point -> grid::point : drop construct<grid::point> ;",
+        ),
+    ])
+    .unwrap();
+    assert_eq!(
+        record_types(&items),
+        vec![
+            "shapes::point",
+            "grid::point",
+            "main::line",
+            "shapes::point",
+            "grid::point",
+            "shapes::point",
+            "grid::point",
+            "grid::point"
+        ]
+    );
+    // An unqualified record exported by two imported modules is ambiguous
+    assert_eq!(
+        resolve_err(&[
+            STD,
+            shapes,
+            ("grid", "pub record point : row i , ;"),
+            ("main", "use shapes; use grid; a point -> : drop ;")
+        ]),
+        ResolveErrorKind::Ambiguous {
+            name: "point".to_string(),
+            modules: vec!["grid".to_string(), "shapes".to_string()],
+        }
+    );
+}
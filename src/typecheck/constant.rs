@@ -0,0 +1,307 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{
+    visitor::{CodeBlockVisitor, ResultCodeBlockvisitor},
+    CodeBlock, ConcreteType, ConstValue, FunctionCall, IfStatement, IntLiteral, Span, Type,
+    WhileStatement,
+};
+
+use super::{TypeCheckError, TypeCheckErrorKind, TypeCheckResult};
+
+/// Stops evaluation of constants which would never finish
+const MAX_LOOP_ITERATIONS: usize = 1 << 20;
+
+/// Evaluates the body of a constant, which must already have been typechecked. Walking the block
+/// returns the values it leaves on the stack.
+///
+/// Intrinsics are evaluated with the same semantics as the code generated for them, so a constant
+/// has the same value it would have if its words were run by the program.
+pub(super) struct ConstEvaluator<'a> {
+    intrinsics: &'a HashSet<String>,
+    constants: &'a HashMap<String, ConstValue>,
+    stack: Vec<ConstValue>,
+}
+
+impl<'a> ConstEvaluator<'a> {
+    pub(super) fn new(
+        intrinsics: &'a HashSet<String>,
+        constants: &'a HashMap<String, ConstValue>,
+    ) -> Self {
+        Self::with_stack(intrinsics, constants, Vec::new())
+    }
+
+    fn with_stack(
+        intrinsics: &'a HashSet<String>,
+        constants: &'a HashMap<String, ConstValue>,
+        stack: Vec<ConstValue>,
+    ) -> Self {
+        Self {
+            intrinsics,
+            constants,
+            stack,
+        }
+    }
+
+    /// Evaluates a nested block, starting from the current stack
+    fn evaluate(&mut self, block: &mut CodeBlock) -> TypeCheckResult {
+        let stack = std::mem::take(&mut self.stack);
+        self.stack = ConstEvaluator::with_stack(self.intrinsics, self.constants, stack)
+            .walk_result(block)?;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> ConstValue {
+        self.stack
+            .pop()
+            .expect("typechecking ensures the stack does not underflow")
+    }
+
+    fn pop_bool(&mut self) -> bool {
+        match self.pop() {
+            ConstValue::Bool(value) => value,
+            other => unreachable!("typechecking ensures this is a bool, got {:?}", other),
+        }
+    }
+
+    /// Applies the intrinsic `name`. Returns false if it cannot be evaluated at compile time.
+    fn apply_intrinsic(&mut self, name: &str) -> Result<bool, String> {
+        let n = self.stack.len();
+        match name {
+            "dup" => self.stack.push(self.stack[n - 1].clone()),
+            "dup2" => self.stack.extend_from_within(n - 2..),
+            "drop" => {
+                self.pop();
+            }
+            "over" => self.stack.push(self.stack[n - 2].clone()),
+            "swap" => self.stack.swap(n - 1, n - 2),
+            "rot" => {
+                let value = self.stack.remove(n - 3);
+                self.stack.push(value);
+            }
+            "+" | "-" | "*" | "/" | "%" | "<<" | ">>" => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                match arithmetic(name, lhs, rhs)? {
+                    Some(value) => self.stack.push(value),
+                    None => return Ok(false),
+                }
+            }
+            "<" | "<=" | ">" | ">=" | "=" | "!=" => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let ordering = match compare(&lhs, &rhs) {
+                    Some(ordering) => ordering,
+                    // Comparisons with NaN are always false, apart from `!=`
+                    None => {
+                        self.stack.push(ConstValue::Bool(name == "!="));
+                        return Ok(true);
+                    }
+                };
+                let result = match name {
+                    "<" => ordering == Ordering::Less,
+                    "<=" => ordering != Ordering::Greater,
+                    ">" => ordering == Ordering::Greater,
+                    ">=" => ordering != Ordering::Less,
+                    "=" => ordering == Ordering::Equal,
+                    _ => ordering != Ordering::Equal,
+                };
+                self.stack.push(ConstValue::Bool(result));
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl CodeBlockVisitor for ConstEvaluator<'_> {
+    type ItemResult = TypeCheckResult;
+    type FinalOutput = Vec<ConstValue>;
+
+    fn visit_int_literal(&mut self, literal: &mut IntLiteral) -> TypeCheckResult {
+        match &literal.typ {
+            Some(Type::Concrete(typ)) => self.stack.push(wrap(literal.value as i64, typ.clone())),
+            _ => unreachable!("integer literals are annotated by the typechecker"),
+        }
+        Ok(())
+    }
+
+    fn visit_bool_literal(&mut self, n: bool, _: Span) -> TypeCheckResult {
+        self.stack.push(ConstValue::Bool(n));
+        Ok(())
+    }
+
+    fn visit_float_literal(&mut self, n: f64, typ: ConcreteType, _: Span) -> TypeCheckResult {
+        self.stack.push(round(n, typ));
+        Ok(())
+    }
+
+    // Strings are stored in memory, so there is no value to give the constant
+    fn visit_string_literal(&mut self, s: &[u8], span: Span) -> TypeCheckResult {
+        Err(TypeCheckError::new(
+            TypeCheckErrorKind::NotConstant(format!("\"{}\"", s.escape_ascii())),
+            span,
+        ))
+    }
+
+    // Pointers have no value at compile time, so only casts to number types can be evaluated
+    fn visit_cast(&mut self, to: &mut Type, span: Span) -> TypeCheckResult {
+        match to {
+            Type::Concrete(to) => {
+                let value = self.pop();
+                self.stack.push(cast(value, to.clone()));
+                Ok(())
+            }
+            _ => Err(TypeCheckError::new(
+                TypeCheckErrorKind::NotConstant(format!("({})", to)),
+                span,
+            )),
+        }
+    }
+
+    fn visit_function(&mut self, function: &mut FunctionCall) -> TypeCheckResult {
+        if let Some(value) = self.constants.get(&function.name) {
+            self.stack.push(value.clone());
+            return Ok(());
+        }
+
+        let applied = if self.intrinsics.contains(&function.name) {
+            self.apply_intrinsic(&function.name).map_err(|message| {
+                TypeCheckError::new(TypeCheckErrorKind::ConstEvaluation(message), function.span)
+            })?
+        } else {
+            false
+        };
+        if !applied {
+            return Err(TypeCheckError::new(
+                TypeCheckErrorKind::NotConstant(function.name.clone()),
+                function.span,
+            ));
+        }
+        Ok(())
+    }
+
+    fn visit_if_statement(&mut self, statement: &mut IfStatement) -> TypeCheckResult {
+        if self.pop_bool() {
+            self.evaluate(&mut statement.true_branch)
+        } else {
+            self.evaluate(&mut statement.false_branch)
+        }
+    }
+
+    fn visit_while_statement(&mut self, statement: &mut WhileStatement) -> TypeCheckResult {
+        for _ in 0..MAX_LOOP_ITERATIONS {
+            self.evaluate(&mut statement.condition)?;
+            if !self.pop_bool() {
+                return Ok(());
+            }
+            self.evaluate(&mut statement.body)?;
+        }
+        Err(TypeCheckError::new(
+            TypeCheckErrorKind::ConstEvaluation(format!(
+                "while loop did not finish after {} iterations",
+                MAX_LOOP_ITERATIONS
+            )),
+            statement.span,
+        ))
+    }
+
+    fn finalize(self) -> Vec<ConstValue> {
+        self.stack
+    }
+}
+
+impl ResultCodeBlockvisitor for ConstEvaluator<'_> {
+    type ErrorType = TypeCheckError;
+}
+
+/// Truncates `value` to the width of `typ`, then sign or zero extends it back to 64 bits
+fn wrap(value: i64, typ: ConcreteType) -> ConstValue {
+    let shift = 64 - typ.width();
+    let value = if typ.is_signed() {
+        (value << shift) >> shift
+    } else {
+        (((value as u64) << shift) >> shift) as i64
+    };
+    ConstValue::Int(value, typ)
+}
+
+/// Rounds `value` to the precision of `typ`
+fn round(value: f64, typ: ConcreteType) -> ConstValue {
+    match typ {
+        ConcreteType::F32 => ConstValue::Float(value as f32 as f64, typ),
+        _ => ConstValue::Float(value, typ),
+    }
+}
+
+/// Evaluates a binary arithmetic intrinsic. Both operands have the same type.
+fn arithmetic(name: &str, lhs: ConstValue, rhs: ConstValue) -> Result<Option<ConstValue>, String> {
+    let value = match (lhs, rhs) {
+        (ConstValue::Int(a, typ), ConstValue::Int(b, _)) => {
+            if (name == "/" || name == "%") && b == 0 {
+                return Err("division by zero".to_string());
+            }
+            if (name == "<<" || name == ">>") && !(0..typ.width() as i64).contains(&b) {
+                return Err(format!("shift by {} is out of range for `{}`", b, typ));
+            }
+            // Unsigned values are zero extended, so only division and right shifts differ
+            let value = match (name, typ.is_signed()) {
+                ("+", _) => a.wrapping_add(b),
+                ("-", _) => a.wrapping_sub(b),
+                ("*", _) => a.wrapping_mul(b),
+                ("/", true) => a.wrapping_div(b),
+                ("/", false) => ((a as u64) / (b as u64)) as i64,
+                ("%", true) => a.wrapping_rem(b),
+                ("%", false) => ((a as u64) % (b as u64)) as i64,
+                ("<<", _) => a << b,
+                (">>", true) => a >> b,
+                (">>", false) => ((a as u64) >> b) as i64,
+                _ => unreachable!("not an arithmetic intrinsic: {}", name),
+            };
+            wrap(value, typ)
+        }
+        (ConstValue::Float(a, typ), ConstValue::Float(b, _)) => {
+            let value = match name {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                "%" => a % b,
+                _ => return Ok(None),
+            };
+            round(value, typ)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+fn compare(lhs: &ConstValue, rhs: &ConstValue) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (ConstValue::Int(a, typ), ConstValue::Int(b, _)) if typ.is_signed() => a.partial_cmp(b),
+        (ConstValue::Int(a, _), ConstValue::Int(b, _)) => (*a as u64).partial_cmp(&(*b as u64)),
+        (ConstValue::Float(a, _), ConstValue::Float(b, _)) => a.partial_cmp(b),
+        (ConstValue::Bool(a), ConstValue::Bool(b)) => a.partial_cmp(b),
+        _ => unreachable!("typechecking ensures both sides of a comparison have the same type"),
+    }
+}
+
+fn cast(value: ConstValue, to: ConcreteType) -> ConstValue {
+    // A bool can only be cast to a number, or to itself
+    if to == ConcreteType::Bool {
+        return value;
+    }
+    let value = match value {
+        ConstValue::Bool(value) => ConstValue::Int(value as i64, ConcreteType::Bool),
+        value => value,
+    };
+    match (value, to.is_integral()) {
+        (ConstValue::Int(value, _), true) => wrap(value, to),
+        (ConstValue::Int(value, from), false) if from.is_signed() => round(value as f64, to),
+        (ConstValue::Int(value, _), false) => round(value as u64 as f64, to),
+        (ConstValue::Float(value, _), true) if to.is_signed() => wrap(value as i64, to),
+        (ConstValue::Float(value, _), true) => wrap(value as u64 as i64, to),
+        (ConstValue::Float(value, _), false) => round(value, to),
+        (ConstValue::Bool(_), _) => unreachable!("bools are converted to integers above"),
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt;
+
+use crate::ast::{ConcreteType, Span, Type, TypeClass};
+use crate::diagnostic::Diagnostic;
+
+use super::literal::int_range;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeCheckError {
+    pub kind: TypeCheckErrorKind,
+    pub span: Span,
+    // The type stack at the point the error occured. This is None for errors which are not
+    // found while checking a code block (eg. redeclarations).
+    pub stack: Option<Vec<Type>>,
+}
+
+impl TypeCheckError {
+    pub fn new(kind: TypeCheckErrorKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            stack: None,
+        }
+    }
+
+    pub fn with_stack(kind: TypeCheckErrorKind, span: Span, stack: Vec<Type>) -> Self {
+        Self {
+            kind,
+            span,
+            stack: Some(stack),
+        }
+    }
+}
+
+/// Something which takes values off the stack, and so can fail if the stack does not hold the
+/// right types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackConsumer {
+    Function(String),
+    IfStatement,
+    Cast(Type),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopPart {
+    Condition,
+    Body,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeCheckErrorKind {
+    // The stack did not have enough values to pass to the consumer. `inputs` holds every type
+    // the consumer takes, `expected` is the one that was missing.
+    StackUnderflow {
+        consumer: StackConsumer,
+        inputs: Vec<Type>,
+        expected: Type,
+    },
+    TypeMismatch {
+        consumer: StackConsumer,
+        inputs: Vec<Type>,
+        expected: Type,
+        actual: Type,
+    },
+    UndefinedFunction(String),
+    // A record type which is not defined anywhere in the program
+    UndefinedType(String),
+    // A generic in a function's outputs which does not appear in its inputs
+    UndefinedGeneric(String),
+    // A generic used in the body of a function which does not appear in its signature
+    UnboundGeneric(String),
+    // A value whose type is a generic of the calling function was passed to a function which
+    // needs it to be in a class, but the generic is not constrained to that class
+    UnconstrainedGeneric {
+        function: String,
+        generic: String,
+        class: TypeClass,
+    },
+    // A cast between types which cannot be converted, eg. from a float to a pointer
+    BadCast {
+        from: Type,
+        to: Type,
+    },
+    // The type arguments of a call do not fit the generics of the function, eg. `alloca<i,f>`
+    BadTypeArguments {
+        function: String,
+        generics: Vec<Type>,
+        type_args: Vec<Type>,
+    },
+    // The two branches of an if statement leave different types on the stack
+    BranchMismatch {
+        true_branch: Vec<Type>,
+        false_branch: Vec<Type>,
+    },
+    // A while condition must only push a bool, and a while body must have no overall stack
+    // effect
+    LoopEffect {
+        part: LoopPart,
+        consumed: Vec<Type>,
+        produced: Vec<Type>,
+    },
+    WrongReturnStack {
+        function: String,
+        expected: Vec<Type>,
+        actual: Vec<Type>,
+    },
+    Redeclaration(String),
+    Reimplementation(String),
+    RecordRedeclaration(String),
+    DuplicateField {
+        record: String,
+        field: String,
+    },
+    // The layout of a record must be known, so its fields cannot be generic
+    GenericField {
+        record: String,
+        field: String,
+    },
+    // A record which holds a value of itself, directly or within its fields
+    RecursiveRecord(String),
+    // A constant must leave exactly one value on the stack
+    ConstValueCount {
+        name: String,
+        produced: Vec<Type>,
+    },
+    // A word used in a constant which cannot be evaluated at compile time
+    NotConstant(String),
+    // Evaluating a constant failed, eg. by dividing by zero
+    ConstEvaluation(String),
+    // An integer literal with a value its type cannot hold, eg. `300c`
+    LiteralOutOfRange {
+        value: i128,
+        typ: ConcreteType,
+    },
+}
+
+impl fmt::Display for StackConsumer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackConsumer::Function(name) => write!(f, "`{}`", name),
+            StackConsumer::IfStatement => write!(f, "if statement"),
+            StackConsumer::Cast(to) => write!(f, "`({})`", to),
+        }
+    }
+}
+
+impl fmt::Display for LoopPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopPart::Condition => write!(f, "while condition"),
+            LoopPart::Body => write!(f, "while body"),
+        }
+    }
+}
+
+/// Displays a stack of types as a space separated list, eg. `i f *b`
+pub struct TypeStack<'a>(pub &'a [Type]);
+
+impl fmt::Display for TypeStack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "nothing");
+        }
+        let types = self.0.iter().map(Type::to_string).collect::<Vec<_>>();
+        write!(f, "`{}`", types.join(" "))
+    }
+}
+
+impl fmt::Display for TypeCheckErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeCheckErrorKind::StackUnderflow {
+                consumer, expected, ..
+            } => write!(
+                f,
+                "expected a `{}` on the stack for {}, but the stack was empty",
+                expected, consumer
+            ),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "expected a `{}` on the stack for {}, but got a `{}`",
+                expected, consumer, actual
+            ),
+            TypeCheckErrorKind::UndefinedFunction(name) => {
+                write!(f, "undefined function `{}`", name)
+            }
+            TypeCheckErrorKind::UndefinedType(name) => write!(f, "undefined type `{}`", name),
+            TypeCheckErrorKind::UndefinedGeneric(name) => {
+                write!(f, "generic `'{}` is not defined by the function's inputs", name)
+            }
+            TypeCheckErrorKind::UnboundGeneric(name) => {
+                write!(f, "generic `'{}` does not appear in the function's signature", name)
+            }
+            TypeCheckErrorKind::UnconstrainedGeneric {
+                function,
+                generic,
+                class,
+            } => write!(
+                f,
+                "`{}` takes a `{}`, but `'{}` is not constrained to `{}`",
+                function, class, generic, class
+            ),
+            TypeCheckErrorKind::BadCast { from, to } => {
+                write!(f, "cannot cast a `{}` to `{}`", from, to)
+            }
+            TypeCheckErrorKind::BadTypeArguments {
+                function,
+                generics,
+                type_args,
+            } if generics.len() != type_args.len() => write!(
+                f,
+                "`{}` takes {} type argument(s), but {} were given",
+                function,
+                generics.len(),
+                type_args.len()
+            ),
+            TypeCheckErrorKind::BadTypeArguments {
+                function,
+                generics,
+                type_args,
+            } => write!(
+                f,
+                "type arguments {} do not fit the generics {} of `{}`",
+                TypeStack(type_args),
+                TypeStack(generics),
+                function
+            ),
+            TypeCheckErrorKind::BranchMismatch {
+                true_branch,
+                false_branch,
+            } => write!(
+                f,
+                "if branches have different stack effects: the true branch leaves {}, the false branch leaves {}",
+                TypeStack(true_branch),
+                TypeStack(false_branch)
+            ),
+            TypeCheckErrorKind::LoopEffect {
+                part: LoopPart::Condition,
+                consumed,
+                produced,
+            } => write!(
+                f,
+                "while condition should only produce a `b`, but it consumes {} and produces {}",
+                TypeStack(consumed),
+                TypeStack(produced)
+            ),
+            TypeCheckErrorKind::LoopEffect {
+                part: LoopPart::Body,
+                consumed,
+                produced,
+            } => write!(
+                f,
+                "while body should have no stack effect, but it consumes {} and produces {}",
+                TypeStack(consumed),
+                TypeStack(produced)
+            ),
+            TypeCheckErrorKind::WrongReturnStack {
+                function,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "expected `{}` to leave {} on the stack, instead it left {}",
+                function,
+                TypeStack(expected),
+                TypeStack(actual)
+            ),
+            TypeCheckErrorKind::Redeclaration(name) => {
+                write!(f, "attempting to redeclare function `{}`", name)
+            }
+            TypeCheckErrorKind::Reimplementation(name) => {
+                write!(f, "attempting to re-implement function `{}`", name)
+            }
+            TypeCheckErrorKind::RecordRedeclaration(name) => {
+                write!(f, "attempting to redeclare record `{}`", name)
+            }
+            TypeCheckErrorKind::DuplicateField { record, field } => write!(
+                f,
+                "record `{}` has more than one field named `{}`",
+                record, field
+            ),
+            TypeCheckErrorKind::GenericField { record, field } => write!(
+                f,
+                "field `{}` of record `{}` cannot have a generic type",
+                field, record
+            ),
+            TypeCheckErrorKind::RecursiveRecord(name) => {
+                write!(f, "record `{}` contains itself", name)
+            }
+            TypeCheckErrorKind::ConstValueCount { name, produced } => write!(
+                f,
+                "constant `{}` must produce exactly one value, but it produces {}",
+                name,
+                TypeStack(produced)
+            ),
+            TypeCheckErrorKind::NotConstant(name) => {
+                write!(f, "`{}` cannot be evaluated at compile time", name)
+            }
+            TypeCheckErrorKind::ConstEvaluation(message) => {
+                write!(f, "could not evaluate constant: {}", message)
+            }
+            TypeCheckErrorKind::LiteralOutOfRange { value, typ } => {
+                write!(f, "integer literal `{}` does not fit in `{}`", value, typ)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)?;
+        if let Some(stack) = &self.stack {
+            write!(f, " (stack: {})", TypeStack(stack))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TypeCheckError {}
+
+impl TypeCheckError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.kind.to_string()).with_span(self.span);
+        let stack = self.stack.as_deref().unwrap_or(&[]);
+
+        match &self.kind {
+            TypeCheckErrorKind::StackUnderflow { inputs, .. }
+            | TypeCheckErrorKind::TypeMismatch { inputs, .. } => {
+                // Only show as much of the stack as the consumer would have taken
+                let stack_top = &stack[stack.len().saturating_sub(inputs.len())..];
+                diagnostic.with_label(format!(
+                    "expected {}, stack has {}",
+                    TypeStack(inputs),
+                    TypeStack(stack_top)
+                ))
+            }
+            TypeCheckErrorKind::UndefinedFunction(_) => diagnostic
+                .with_label("not found in this module")
+                .with_help("functions must be declared or implemented somewhere in the module"),
+            TypeCheckErrorKind::UndefinedType(_) => diagnostic
+                .with_label("used here")
+                .with_help("a type is either a builtin type such as `i`, a generic such as `'T`, or the name of a record"),
+            TypeCheckErrorKind::UndefinedGeneric(_) => diagnostic
+                .with_label("called here")
+                .with_help("every generic in a function's outputs must also appear in its inputs, or be given as a type argument, eg. `alloca<i>`"),
+            TypeCheckErrorKind::UnboundGeneric(_) => diagnostic
+                .with_label("used here")
+                .with_help("a function's body can only use the generics in its signature"),
+            TypeCheckErrorKind::UnconstrainedGeneric { generic, class, .. } => diagnostic
+                .with_label("called here")
+                .with_help(format!(
+                    "constrain the generic in the function's signature, eg. `'{}:{}`",
+                    generic, class
+                )),
+            TypeCheckErrorKind::BadCast { .. } => diagnostic
+                .with_label("cast here")
+                .with_help("numbers, including generics constrained to `num` or `int`, can be cast to any number type, integers to pointers, and pointers to integers or other pointers"),
+            TypeCheckErrorKind::BadTypeArguments { .. } => diagnostic
+                .with_label("called here")
+                .with_help("type arguments are given in the order each generic first appears in the function's signature"),
+            TypeCheckErrorKind::BranchMismatch { .. } => diagnostic
+                .with_note(format!("stack before the branches: {}", TypeStack(stack)))
+                .with_help("both branches of an if statement must leave the same types on the stack"),
+            TypeCheckErrorKind::LoopEffect { part, .. } => {
+                let diagnostic =
+                    diagnostic.with_note(format!("stack before the loop: {}", TypeStack(stack)));
+                match part {
+                    LoopPart::Condition => diagnostic.with_help(
+                        "the condition may read the stack, but must leave it unchanged apart from pushing a single `b`",
+                    ),
+                    LoopPart::Body => diagnostic
+                        .with_help("the body must leave the same types on the stack as it started with"),
+                }
+            }
+            TypeCheckErrorKind::WrongReturnStack {
+                expected, actual, ..
+            } => diagnostic.with_label(format!(
+                "expected {}, stack has {}",
+                TypeStack(expected),
+                TypeStack(actual)
+            )),
+            TypeCheckErrorKind::Redeclaration(_)
+            | TypeCheckErrorKind::Reimplementation(_)
+            | TypeCheckErrorKind::RecordRedeclaration(_)
+            | TypeCheckErrorKind::DuplicateField { .. } => {
+                diagnostic.with_label("defined again here")
+            }
+            TypeCheckErrorKind::GenericField { .. } => diagnostic
+                .with_label("field defined here")
+                .with_help("each field must have a builtin type such as `i`, a pointer type or a record type"),
+            TypeCheckErrorKind::RecursiveRecord(name) => diagnostic
+                .with_label("defined here")
+                .with_help(format!(
+                    "a record can hold a pointer to itself, eg. `*{}`, but not a value of itself",
+                    name
+                )),
+            TypeCheckErrorKind::ConstValueCount { produced, .. } => diagnostic.with_label(format!(
+                "expected one value, stack has {}",
+                TypeStack(produced)
+            )),
+            TypeCheckErrorKind::NotConstant(_) => diagnostic
+                .with_label("used in a constant here")
+                .with_help("constants may only use literals, other constants, if statements, while loops and stack, arithmetic or comparison intrinsics and casts"),
+            TypeCheckErrorKind::ConstEvaluation(_) => diagnostic.with_label("evaluated here"),
+            TypeCheckErrorKind::LiteralOutOfRange { typ, .. } => {
+                let range = int_range(typ);
+                diagnostic.with_label(format!(
+                    "`{}` holds values from {} to {}",
+                    typ,
+                    range.start(),
+                    range.end()
+                ))
+            }
+        }
+    }
+}
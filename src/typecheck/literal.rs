@@ -0,0 +1,189 @@
+use std::ops::RangeInclusive;
+
+use crate::ast::{
+    visitor::{CodeBlockVisitor, ResultCodeBlockvisitor},
+    CodeBlock, ConcreteType, FunctionCall, IfStatement, IntLiteral, Span, Type, TypeClass,
+    WhileStatement,
+};
+
+use super::{TypeCheckError, TypeCheckErrorKind, TypeCheckResult};
+
+/// The types of the integer literals without a suffix in a code block. Each literal starts with
+/// its own `Type::UntypedInt`, which is bound to a concrete type, or to another literal's type,
+/// when the typechecker finds how it is used.
+#[derive(Debug, Default)]
+pub(super) struct LiteralTypes {
+    // What each literal's type is bound to, indexed by the id in its `Type::UntypedInt`
+    bindings: Vec<Option<Type>>,
+}
+
+impl LiteralTypes {
+    /// Creates the type of a new literal, which is not yet bound to anything
+    pub fn new_literal(&mut self) -> Type {
+        self.bindings.push(None);
+        Type::UntypedInt(self.bindings.len() - 1)
+    }
+
+    /// Replaces each literal type in `typ` with what it is bound to, as far as is known
+    pub fn resolve(&self, typ: &Type) -> Type {
+        match typ {
+            Type::UntypedInt(id) => match &self.bindings[*id] {
+                Some(bound) => self.resolve(bound),
+                None => typ.clone(),
+            },
+            Type::Pointer(inner) => Type::Pointer(Box::new(self.resolve(inner))),
+            _ => typ.clone(),
+        }
+    }
+
+    /// Checks whether `a` and `b` can be the same type, binding literal types so that they are.
+    /// A literal can become any integral type except bool.
+    pub fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        match (&a, &b) {
+            (Type::UntypedInt(x), Type::UntypedInt(y)) => {
+                if x != y {
+                    self.bindings[*x] = Some(b.clone());
+                }
+                true
+            }
+            (Type::UntypedInt(id), Type::Concrete(concrete))
+            | (Type::Concrete(concrete), Type::UntypedInt(id)) => {
+                let integral = concrete.is_integral() && *concrete != ConcreteType::Bool;
+                if integral {
+                    self.bindings[*id] = Some(Type::Concrete(concrete.clone()));
+                }
+                integral
+            }
+            (Type::Pointer(a), Type::Pointer(b)) => self.unify(a, b),
+            _ => a == b,
+        }
+    }
+
+    /// Checks whether `typ` is a member of `class`. Every type a literal can become is an integer
+    /// type, so literals are in every class of number types.
+    pub fn in_class(&self, typ: &Type, class: TypeClass) -> bool {
+        match self.resolve(typ) {
+            Type::Concrete(concrete) => class.members().contains(&concrete),
+            Type::UntypedInt(_) => class != TypeClass::Record,
+            Type::Record(_) => class == TypeClass::Record,
+            // A generic of the function being checked is in every class containing the one it is
+            // constrained to, eg. a `'T:int` is a `num`
+            Type::Generic(_, Some(constraint)) => {
+                constraint == class
+                    || constraint != TypeClass::Record
+                        && constraint
+                            .members()
+                            .iter()
+                            .all(|typ| class.members().contains(typ))
+            }
+            _ => false,
+        }
+    }
+
+    /// Binds every literal whose type was not decided by its use to `i`
+    pub fn default(&mut self) {
+        for id in 0..self.bindings.len() {
+            if let Type::UntypedInt(id) = self.resolve(&Type::UntypedInt(id)) {
+                self.bindings[id] = Some(Type::Concrete(ConcreteType::I32));
+            }
+        }
+    }
+}
+
+/// The values an integer type can hold
+pub(super) fn int_range(typ: &ConcreteType) -> RangeInclusive<i128> {
+    let width = typ.width();
+    if typ.is_signed() {
+        -(1 << (width - 1))..=(1 << (width - 1)) - 1
+    } else {
+        0..=(1 << width) - 1
+    }
+}
+
+/// Replaces the literal types in a typechecked block with the concrete types they resolved to,
+/// so that later passes only see concrete types. Each literal is checked to fit in its type.
+pub(super) struct LiteralAnnotator<'a> {
+    literals: &'a LiteralTypes,
+}
+
+impl<'a> LiteralAnnotator<'a> {
+    pub fn new(literals: &'a LiteralTypes) -> Self {
+        Self { literals }
+    }
+
+    fn walk_nested(&self, block: &mut CodeBlock) -> TypeCheckResult {
+        LiteralAnnotator::new(self.literals).walk_result(block)
+    }
+
+    fn resolve_all(&self, types: &mut [Type]) {
+        for typ in types {
+            *typ = self.literals.resolve(typ);
+        }
+    }
+}
+
+impl CodeBlockVisitor for LiteralAnnotator<'_> {
+    type ItemResult = TypeCheckResult;
+    type FinalOutput = ();
+
+    fn visit_int_literal(&mut self, literal: &mut IntLiteral) -> TypeCheckResult {
+        let typ = match &literal.typ {
+            Some(typ) => self.literals.resolve(typ),
+            None => return Ok(()),
+        };
+        if let Type::Concrete(concrete) = &typ {
+            if !int_range(concrete).contains(&literal.value) {
+                return Err(TypeCheckError::new(
+                    TypeCheckErrorKind::LiteralOutOfRange {
+                        value: literal.value,
+                        typ: concrete.clone(),
+                    },
+                    literal.span,
+                ));
+            }
+        }
+        literal.typ = Some(typ);
+        Ok(())
+    }
+
+    fn visit_float_literal(&mut self, _: f64, _: ConcreteType, _: Span) -> TypeCheckResult {
+        Ok(())
+    }
+
+    fn visit_bool_literal(&mut self, _: bool, _: Span) -> TypeCheckResult {
+        Ok(())
+    }
+
+    fn visit_string_literal(&mut self, _: &[u8], _: Span) -> TypeCheckResult {
+        Ok(())
+    }
+
+    fn visit_cast(&mut self, _: &mut Type, _: Span) -> TypeCheckResult {
+        Ok(())
+    }
+
+    fn visit_function(&mut self, function: &mut FunctionCall) -> TypeCheckResult {
+        if let Some(typ) = &mut function.reified_type {
+            self.resolve_all(&mut typ.inputs);
+            self.resolve_all(&mut typ.outputs);
+        }
+        Ok(())
+    }
+
+    fn visit_if_statement(&mut self, statement: &mut IfStatement) -> TypeCheckResult {
+        self.walk_nested(&mut statement.true_branch)?;
+        self.walk_nested(&mut statement.false_branch)
+    }
+
+    fn visit_while_statement(&mut self, statement: &mut WhileStatement) -> TypeCheckResult {
+        self.walk_nested(&mut statement.condition)?;
+        self.walk_nested(&mut statement.body)
+    }
+
+    fn finalize(self) {}
+}
+
+impl ResultCodeBlockvisitor for LiteralAnnotator<'_> {
+    type ErrorType = TypeCheckError;
+}
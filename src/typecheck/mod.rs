@@ -1,89 +1,216 @@
+mod constant;
+mod error;
+mod literal;
 #[cfg(test)]
 mod typecheck_tests;
 
-use core::panic;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use crate::ast::{
-    visitor::{CodeBlockVisitor, ModuleVisitor},
-    ConcreteType, FunctionCall, FunctionDecl, FunctionImpl, FunctionType, IfStatement, Type,
-    WhileStatement,
+    visitor::{CodeBlockVisitor, ModuleVisitor, ResultCodeBlockvisitor, ResultModuleVisitor},
+    CodeBlock, ConcreteType, Const, ConstValue, FunctionCall, FunctionDecl, FunctionImpl,
+    FunctionType, IfStatement, Import, IntLiteral, Macro, ProgramTypes, Record, RecordField, Span,
+    Type, TypeClass, WhileStatement,
 };
 
+use constant::ConstEvaluator;
+use literal::{LiteralAnnotator, LiteralTypes};
+pub use error::{LoopPart, StackConsumer, TypeCheckError, TypeCheckErrorKind};
+
+type TypeCheckResult = Result<(), TypeCheckError>;
+
+/// Collects the type of every function, constant and record in a module, including the getters
+/// and setters of each record's fields. Constants are evaluated as they are found, so they may
+/// only use constants defined before them.
 pub struct FunctionMapBuilder {
-    // Maps name -> (type, is_implemented)
-    functions: HashMap<String, (FunctionType, bool)>,
+    types: ProgramTypes,
+    implemented: HashSet<String>,
+    intrinsics: HashSet<String>,
+    constants: HashMap<String, ConstValue>,
 }
 
 impl FunctionMapBuilder {
     pub fn new() -> Self {
         Self {
-            functions: HashMap::new(),
+            types: ProgramTypes::default(),
+            implemented: HashSet::new(),
+            intrinsics: HashSet::new(),
+            constants: HashMap::new(),
         }
     }
 }
 
 // TODO make sure implementation type matches declaration type
 impl ModuleVisitor for FunctionMapBuilder {
-    type ItemResult = ();
-    type FinalOutput = HashMap<String, FunctionType>;
+    type ItemResult = TypeCheckResult;
+    type FinalOutput = ProgramTypes;
 
-    fn visit_decl(&mut self, function: &mut FunctionDecl) {
-        if self.functions.contains_key(&function.head.name) {
+    fn visit_decl(&mut self, function: &mut FunctionDecl) -> TypeCheckResult {
+        if self.types.functions.contains_key(&function.head.name) {
             // TODO "previous declaration at X:X:X"
-            panic!("Attempting to redeclare function {}", &function.head.name);
+            return Err(TypeCheckError::new(
+                TypeCheckErrorKind::Redeclaration(function.head.name.clone()),
+                function.span,
+            ));
         }
-        self.functions.insert(
-            function.head.name.clone(),
-            (function.head.typ.clone(), false),
-        );
+        self.types.functions
+            .insert(function.head.name.clone(), function.head.typ.clone());
+        if function.is_intrinsic {
+            self.intrinsics.insert(function.head.name.clone());
+        }
+        Ok(())
     }
 
-    fn visit_impl(&mut self, function: &mut FunctionImpl) {
-        if self.functions.contains_key(&function.head.name) && self.functions[&function.head.name].1
+    fn visit_impl(&mut self, function: &mut FunctionImpl) -> TypeCheckResult {
+        if self.implemented.contains(&function.head.name)
+            || self.constants.contains_key(&function.head.name)
         {
             // TODO "previous implementation at X:X:X"
-            panic!(
-                "Attempting to re-implement function {}",
-                &function.head.name
-            );
+            return Err(TypeCheckError::new(
+                TypeCheckErrorKind::Reimplementation(function.head.name.clone()),
+                function.span,
+            ));
         }
 
-        self.functions.insert(
-            function.head.name.clone(),
-            (function.head.typ.clone(), true),
+        self.types.functions
+            .insert(function.head.name.clone(), function.head.typ.clone());
+        self.implemented.insert(function.head.name.clone());
+        Ok(())
+    }
+
+    fn visit_const(&mut self, constant: &mut Const) -> TypeCheckResult {
+        if self.types.functions.contains_key(&constant.name) {
+            return Err(TypeCheckError::new(
+                TypeCheckErrorKind::Redeclaration(constant.name.clone()),
+                constant.span,
+            ));
+        }
+
+        let stack = CodeBlockTypeChecker::new(Vec::new(), &self.types)
+            .check(&mut constant.body, None)?;
+        if stack.len() != 1 {
+            return Err(TypeCheckError::with_stack(
+                TypeCheckErrorKind::ConstValueCount {
+                    name: constant.name.clone(),
+                    produced: stack.clone(),
+                },
+                constant.name_span,
+                stack,
+            ));
+        }
+
+        let value = ConstEvaluator::new(&self.intrinsics, &self.constants)
+            .walk_result(&mut constant.body)?
+            .remove(0);
+        self.types.functions.insert(
+            constant.name.clone(),
+            FunctionType {
+                inputs: Vec::new(),
+                outputs: stack,
+            },
         );
+        self.constants.insert(constant.name.clone(), value.clone());
+        constant.value = Some(value);
+        Ok(())
     }
 
-    fn finalize(self) -> HashMap<String, FunctionType> {
-        self.functions
-            .into_iter()
-            // remove bool in tuple
-            .map(|(name, (typ, _))| (name, typ))
-            .collect()
+    // Macros have already been expanded into the bodies which use them
+    fn visit_macro(&mut self, _: &mut Macro) -> TypeCheckResult {
+        Ok(())
     }
+
+    // The types of the fields are checked by ModuleTypeChecker, once every record is known
+    fn visit_record(&mut self, record: &mut Record) -> TypeCheckResult {
+        if self.types.records.contains_key(&record.name) {
+            return Err(TypeCheckError::new(
+                TypeCheckErrorKind::RecordRedeclaration(record.name.clone()),
+                record.name_span,
+            ));
+        }
+
+        let record_type = Type::Record(record.name.clone());
+        let mut field_names = HashSet::new();
+        for field in &record.fields {
+            if !field_names.insert(&field.name) {
+                return Err(TypeCheckError::new(
+                    TypeCheckErrorKind::DuplicateField {
+                        record: record.name.clone(),
+                        field: field.name.clone(),
+                    },
+                    field.span,
+                ));
+            }
+
+            let getter = FunctionType {
+                inputs: vec![record_type.clone()],
+                outputs: vec![record_type.clone(), field.typ.clone()],
+            };
+            let setter = FunctionType {
+                inputs: vec![record_type.clone(), field.typ.clone()],
+                outputs: vec![record_type.clone()],
+            };
+            for (name, typ) in [
+                (record.getter_name(field), getter),
+                (record.setter_name(field), setter),
+            ] {
+                if self.types.functions.contains_key(&name) {
+                    return Err(TypeCheckError::new(
+                        TypeCheckErrorKind::Redeclaration(name),
+                        field.span,
+                    ));
+                }
+                // The record implements its getters and setters, so they cannot be implemented
+                // again by a function
+                self.implemented.insert(name.clone());
+                self.types.functions.insert(name, typ);
+            }
+        }
+        self.types
+            .records
+            .insert(record.name.clone(), record.fields.clone());
+        Ok(())
+    }
+
+    // Imported items have already been added to the module by the loader
+    fn visit_import(&mut self, _: &mut Import) -> TypeCheckResult {
+        Ok(())
+    }
+
+    fn finalize(self) -> ProgramTypes {
+        self.types
+    }
+}
+
+impl ResultModuleVisitor for FunctionMapBuilder {
+    type ErrorType = TypeCheckError;
 }
 
 impl Type {
     /// Checks whether self matches other. If self is a generic, the match always returns true and sets the generic's name to point to it's new reified type in generics_map
-    pub(super) fn matches(&self, other: &Type, generics_map: &mut HashMap<String, Type>) -> bool {
-        assert!(
-            !matches!(other, &Type::Generic(_)),
-            "Generics should only appear on LHS of type matcher"
-        );
+    /// Integer literals match any integral type, and are bound to the type they matched.
+    /// `other` may be a generic of the calling function, which only matches a constrained generic
+    /// if its own constraint is at least as narrow.
+    fn matches(
+        &self,
+        other: &Type,
+        generics_map: &mut HashMap<String, Type>,
+        literals: &mut LiteralTypes,
+    ) -> bool {
         match self {
-            Type::Concrete(concrete) => {
-                if let Type::Concrete(other_concrete) = other {
-                    concrete == other_concrete
-                } else {
-                    false
-                }
+            Type::Concrete(_) | Type::UntypedInt(_) | Type::Record(_) => {
+                literals.unify(self, other)
             }
-            Type::Generic(name) => {
+            Type::Generic(name, class) => {
+                if let Some(class) = class {
+                    if !literals.in_class(other, *class) {
+                        return false;
+                    }
+                }
                 // There is already a generic defined with a reified type
-                if generics_map.contains_key(name) {
-                    let previously_matched_generic = generics_map[name].clone();
-                    previously_matched_generic.matches(other, generics_map)
+                if let Some(previously_matched) = generics_map.get(name) {
+                    literals.unify(previously_matched, other)
                 } else {
                     generics_map.insert(name.clone(), other.clone());
                     true
@@ -91,7 +218,7 @@ impl Type {
             }
             Type::Pointer(inner) => {
                 if let Type::Pointer(other_inner) = other {
-                    inner.matches(other_inner, generics_map)
+                    inner.matches(other_inner, generics_map, literals)
                 } else {
                     false
                 }
@@ -101,42 +228,214 @@ impl Type {
 
     // Given a map of generics, reify the type of self (ie replace it with a concrete type if it
     // was a generic
-    pub(super) fn reify(&self, generics_map: &mut HashMap<String, Type>) -> Type {
+    pub(super) fn reify(
+        &self,
+        generics_map: &mut HashMap<String, Type>,
+    ) -> Result<Type, TypeCheckErrorKind> {
         match self {
-            Type::Concrete(_) => self.clone(),
-            Type::Generic(name) => generics_map
+            Type::Concrete(_) | Type::UntypedInt(_) | Type::Record(_) => Ok(self.clone()),
+            Type::Generic(name, _) => generics_map
                 .get(name)
-                .expect("Undefined generic {} on RHS of type declaration")
-                .clone(),
-            Type::Pointer(inner) => Type::Pointer(Box::new(inner.reify(generics_map))),
+                .cloned()
+                .ok_or_else(|| TypeCheckErrorKind::UndefinedGeneric(name.clone())),
+            Type::Pointer(inner) => Ok(Type::Pointer(Box::new(inner.reify(generics_map)?))),
+        }
+    }
+
+    // Whether a value of this type can be cast to `to`. Numbers and bools can be cast to any
+    // number type, integers and pointers to any pointer, and pointers to any integer type. A
+    // generic constrained to `num` or `int` is cast as the numbers it stands for, and any other
+    // generic can only be cast to itself.
+    fn can_cast_to(&self, to: &Type) -> bool {
+        let is_int = |typ: &Type| match typ {
+            Type::Concrete(concrete) => concrete.is_integral() && *concrete != ConcreteType::Bool,
+            Type::UntypedInt(_) | Type::Generic(_, Some(TypeClass::Int)) => true,
+            _ => false,
+        };
+        let is_number = |typ: &Type| match typ {
+            Type::Concrete(concrete) => *concrete != ConcreteType::Bool,
+            Type::UntypedInt(_) | Type::Generic(_, Some(TypeClass::Int | TypeClass::Num)) => true,
+            _ => false,
+        };
+        match (self, to) {
+            _ if self == to => true,
+            (Type::Concrete(ConcreteType::Bool), _) => is_number(to),
+            (_, Type::Pointer(_)) => is_int(self) || matches!(self, Type::Pointer(_)),
+            (Type::Pointer(_), _) => is_int(to),
+            _ => is_number(self) && is_number(to),
+        }
+    }
+
+    // Checks that every record named in the type is defined
+    fn check_defined(
+        &self,
+        records: &HashMap<String, Vec<RecordField>>,
+    ) -> Result<(), TypeCheckErrorKind> {
+        match self {
+            Type::Record(name) if !records.contains_key(name) => {
+                Err(TypeCheckErrorKind::UndefinedType(name.clone()))
+            }
+            Type::Pointer(inner) => inner.check_defined(records),
+            _ => Ok(()),
+        }
+    }
+
+    // Replace the generics which have already been matched, leaving the others as they are. Used
+    // to report what a partly matched function expects.
+    fn substitute(&self, generics_map: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Generic(name, _) => {
+                generics_map.get(name).cloned().unwrap_or_else(|| self.clone())
+            }
+            Type::Pointer(inner) => Type::Pointer(Box::new(inner.substitute(generics_map))),
+            _ => self.clone(),
         }
     }
 }
 
-// enum TypeCheckError<'a> {
-//     UndefinedGeneric(String),
-//     EmptyStack{expected: }
-// }
+impl FunctionType {
+    // The generics in the type, in the order they first appear. This is the order type arguments
+    // are given in.
+    fn generics(&self) -> Vec<Type> {
+        let mut generics: Vec<Type> = Vec::new();
+        for mut typ in self.inputs.iter().chain(&self.outputs) {
+            while let Type::Pointer(inner) = typ {
+                typ = inner;
+            }
+            if let Type::Generic(name, _) = typ {
+                let seen = generics
+                    .iter()
+                    .any(|generic| matches!(generic, Type::Generic(seen, _) if seen == name));
+                if !seen {
+                    generics.push(typ.clone());
+                }
+            }
+        }
+        generics
+    }
 
-struct CodeBlockTypeChecker<'a> {
-    function_map: &'a HashMap<String, FunctionType>,
+    // The type with each generic constrained to the class given to it anywhere in the type, so
+    // that the body of a function can rely on a constraint which is only written once, eg. in
+    // `'T:num 'T -> 'T`
+    fn constrained(&self) -> FunctionType {
+        let mut classes = HashMap::new();
+        for mut typ in self.inputs.iter().chain(&self.outputs) {
+            while let Type::Pointer(inner) = typ {
+                typ = inner;
+            }
+            if let Type::Generic(name, Some(class)) = typ {
+                classes.insert(name.clone(), *class);
+            }
+        }
+        let constrain = |typ: &Type| {
+            let generics = classes
+                .iter()
+                .map(|(name, class)| (name.clone(), Type::Generic(name.clone(), Some(*class))))
+                .collect();
+            typ.substitute(&generics)
+        };
+        FunctionType {
+            inputs: self.inputs.iter().map(constrain).collect(),
+            outputs: self.outputs.iter().map(constrain).collect(),
+        }
+    }
+}
+
+/// Typechecks a code block, starting from a given stack of types. Walking the block returns the
+/// types left on the stack after it, which may still contain the types of integer literals; use
+/// `check` to resolve them.
+pub struct CodeBlockTypeChecker<'a> {
+    types: &'a ProgramTypes,
     type_stack: Vec<Type>,
+    // Shared with the checkers of nested blocks, since a literal's type can be decided by a use
+    // outside the block it is in
+    literals: Rc<RefCell<LiteralTypes>>,
+    // The generics in the signature of the function the block is the body of, by name
+    generics: HashMap<String, Type>,
 }
 
 impl<'a> CodeBlockTypeChecker<'a> {
-    fn new(stack_before: Vec<Type>, function_map: &'a HashMap<String, FunctionType>) -> Self {
+    pub fn new(stack_before: Vec<Type>, types: &'a ProgramTypes) -> Self {
+        Self {
+            types,
+            type_stack: stack_before,
+            literals: Rc::default(),
+            generics: HashMap::new(),
+        }
+    }
+
+    /// Lets the block use the generics in `signature`, the type of the function it is the body of
+    pub fn in_function(mut self, signature: &FunctionType) -> Self {
+        for generic in signature.generics() {
+            if let Type::Generic(name, _) = &generic {
+                self.generics.insert(name.clone(), generic);
+            }
+        }
+        self
+    }
+
+    /// Typechecks `block`, then resolves the types of its integer literals. If `expected` is
+    /// given, literals left on the stack take the types expected of them. Literals whose type is
+    /// still undecided become `i`, and each literal is checked to fit in its type. Each literal and
+    /// function call in the block is annotated with its concrete types, and the resolved stack is
+    /// returned.
+    pub fn check(
+        self,
+        block: &mut CodeBlock,
+        expected: Option<&[Type]>,
+    ) -> Result<Vec<Type>, TypeCheckError> {
+        let literals = Rc::clone(&self.literals);
+        let stack = self.walk_result(block)?;
+
+        let mut literals = literals.borrow_mut();
+        if let Some(expected) = expected {
+            // A mismatch is left for the caller to report
+            if stack.len() == expected.len() {
+                for (actual, expected) in stack.iter().zip(expected) {
+                    literals.unify(actual, expected);
+                }
+            }
+        }
+        literals.default();
+        LiteralAnnotator::new(&literals).walk_result(block)?;
+        Ok(stack.iter().map(|typ| literals.resolve(typ)).collect())
+    }
+
+    /// Creates a checker for a block nested in this one, starting from `stack_before`
+    fn nested(&self, stack_before: Vec<Type>) -> CodeBlockTypeChecker<'a> {
         Self {
-            function_map,
+            types: self.types,
             type_stack: stack_before,
+            literals: Rc::clone(&self.literals),
+            generics: self.generics.clone(),
+        }
+    }
+
+    /// Gives the generics in `typ` the constraints they have in the function's signature. The
+    /// body of a function can only name the generics in its signature.
+    fn signature_generics(&self, typ: &Type) -> Result<Type, TypeCheckErrorKind> {
+        match typ {
+            Type::Generic(name, _) => self
+                .generics
+                .get(name)
+                .cloned()
+                .ok_or_else(|| TypeCheckErrorKind::UnboundGeneric(name.clone())),
+            Type::Pointer(inner) => Ok(Type::Pointer(Box::new(self.signature_generics(inner)?))),
+            _ => Ok(typ.clone()),
         }
     }
 
     /// Returns the overall effect on the stack of a given operation.  For example (i32) -> (i32)
     /// has the overall effect of () -> (), since the function will effectively edit the i32 in-place.
-    fn get_stack_effect<'i, 'o>(input: &'i [Type], output: &'o [Type]) -> (&'i [Type], &'o [Type]) {
+    fn get_stack_effect<'i, 'o>(
+        &self,
+        input: &'i [Type],
+        output: &'o [Type],
+    ) -> (&'i [Type], &'o [Type]) {
+        let mut literals = self.literals.borrow_mut();
         let mut compare_index = 0usize;
         for (i, o) in input.iter().zip(output.iter()) {
-            if i == o {
+            if literals.unify(i, o) {
                 compare_index += 1;
             } else {
                 break;
@@ -145,138 +444,290 @@ impl<'a> CodeBlockTypeChecker<'a> {
 
         (&input[compare_index..], &output[compare_index..])
     }
+
+    /// Resolves `types` to be reported in an error. Checking stops at the first error, so
+    /// literals whose type is still undecided are reported as `i`, which they default to.
+    fn reported(&self, types: &[Type]) -> Vec<Type> {
+        let mut literals = self.literals.borrow_mut();
+        literals.default();
+        types.iter().map(|typ| literals.resolve(typ)).collect()
+    }
+
+    /// Creates an error which records the current state of the stack
+    fn error(&self, kind: TypeCheckErrorKind, span: Span) -> TypeCheckError {
+        TypeCheckError::with_stack(kind, span, self.reported(&self.type_stack))
+    }
 }
 
 impl CodeBlockVisitor for CodeBlockTypeChecker<'_> {
-    type ItemResult = ();
+    type ItemResult = TypeCheckResult;
     type FinalOutput = Vec<Type>;
 
-    fn visit_i32_literal(&mut self, _: i32) {
-        self.type_stack.push(Type::Concrete(ConcreteType::I32))
+    fn visit_int_literal(&mut self, literal: &mut IntLiteral) -> TypeCheckResult {
+        let typ = match &literal.suffix {
+            Some(suffix) => Type::Concrete(suffix.clone()),
+            None => self.literals.borrow_mut().new_literal(),
+        };
+        literal.typ = Some(typ.clone());
+        self.type_stack.push(typ);
+        Ok(())
     }
 
-    fn visit_f32_literal(&mut self, _: f32) {
-        self.type_stack.push(Type::Concrete(ConcreteType::F32))
+    fn visit_bool_literal(&mut self, _: bool, _: Span) -> TypeCheckResult {
+        self.type_stack.push(Type::Concrete(ConcreteType::Bool));
+        Ok(())
     }
 
-    fn visit_bool_literal(&mut self, _: bool) {
-        self.type_stack.push(Type::Concrete(ConcreteType::Bool))
+    fn visit_float_literal(&mut self, _: f64, typ: ConcreteType, _: Span) -> TypeCheckResult {
+        self.type_stack.push(Type::Concrete(typ));
+        Ok(())
     }
 
-    fn visit_function(&mut self, function: &mut FunctionCall) {
+    fn visit_string_literal(&mut self, _: &[u8], _: Span) -> TypeCheckResult {
+        self.type_stack
+            .push(Type::Pointer(Box::new(Type::Concrete(ConcreteType::I8))));
+        Ok(())
+    }
+
+    fn visit_cast(&mut self, to: &mut Type, span: Span) -> TypeCheckResult {
+        to.check_defined(&self.types.records)
+            .map_err(|kind| self.error(kind, span))?;
+        let to = &self
+            .signature_generics(to)
+            .map_err(|kind| self.error(kind, span))?;
+        let from = match self.type_stack.last() {
+            Some(from) => self.literals.borrow().resolve(from),
+            None => {
+                // A cast takes any type
+                let any = Type::Generic("T".into(), None);
+                return Err(self.error(
+                    TypeCheckErrorKind::StackUnderflow {
+                        consumer: StackConsumer::Cast(to.clone()),
+                        inputs: vec![any.clone()],
+                        expected: any,
+                    },
+                    span,
+                ));
+            }
+        };
+        if !from.can_cast_to(to) {
+            return Err(self.error(
+                TypeCheckErrorKind::BadCast {
+                    from: self.reported(&[from]).remove(0),
+                    to: to.clone(),
+                },
+                span,
+            ));
+        }
+        self.type_stack.pop();
+        self.type_stack.push(to.clone());
+        Ok(())
+    }
+
+    fn visit_function(&mut self, function: &mut FunctionCall) -> TypeCheckResult {
         // instantiate reified input/output vectors
         function.reified_type = Some(FunctionType {
             inputs: Vec::new(),
             outputs: Vec::new(),
         });
 
-        match self.function_map.get(&function.name) {
-            Some(typ) => {
-                let mut generics_map = HashMap::new();
-                // Validate that the inputs to the function are on the stack
-
-                // TODO maybe should not reverse the iteration here, and instead check types from
-                // left to right in order to provide better error messages. eg. matching ('T 'T)
-                // with (i32 f32) will fail on the i32 (because of reverse) instead of failing on
-                // the f32.
-                for input_type in typ.inputs.iter().rev() {
-                    let top_type = self.type_stack
-                        .pop()
-                        .unwrap_or_else(||
-                            panic!(
-                                "Expected a {:?} on the stack to pass to {}, but there was nothing on the stack", 
-                                input_type,
-                                function.name
-                            )
-                        );
-                    assert!(
-                        input_type.matches(&top_type, &mut generics_map),
-                        "Expected an {:?} on the stack to pass to {}, but got a {:?}",
-                        input_type,
-                        function.name,
-                        top_type
-                    );
-
-                    // Annotate function with it's reified inputs.
-                    // Can safely unwrap because it has just been set above.
-                    // Insert at start of vec because we are iterating on the stack from right to
-                    // left (popping off the end of stack), but funtion IOs go from left to right
-                    // on the stack
-                    function
-                        .reified_type
-                        .as_mut()
-                        .unwrap()
-                        .inputs
-                        .insert(0, top_type.clone());
-                }
-                let reified_outputs = typ
-                    .outputs
-                    .iter()
-                    .map(|output_typ| output_typ.reify(&mut generics_map))
-                    .collect::<Vec<_>>();
+        let typ = self.types.functions.get(&function.name).ok_or_else(|| {
+            self.error(
+                TypeCheckErrorKind::UndefinedFunction(function.name.clone()),
+                function.span,
+            )
+        })?;
+
+        let mut generics_map = HashMap::new();
+        for type_arg in &function.type_args {
+            type_arg
+                .check_defined(&self.types.records)
+                .map_err(|kind| self.error(kind, function.span))?;
+        }
+        if !function.type_args.is_empty() {
+            let generics = typ.generics();
+            let fits = generics.len() == function.type_args.len()
+                && generics.iter().zip(&function.type_args).all(|(generic, arg)| {
+                    generic.matches(arg, &mut generics_map, &mut self.literals.borrow_mut())
+                });
+            if !fits {
+                return Err(self.error(
+                    TypeCheckErrorKind::BadTypeArguments {
+                        function: function.name.clone(),
+                        generics,
+                        type_args: function.type_args.clone(),
+                    },
+                    function.span,
+                ));
+            }
+        }
 
-                // Push the function's output to the stack
-                self.type_stack.extend(reified_outputs.to_vec());
-                // annotate function with it's reified outputs
-                function.reified_type.as_mut().unwrap().outputs = reified_outputs;
+        // Validate that the inputs to the function are on the stack. Nothing is popped until all
+        // the inputs have matched, so that errors report the stack as it was before the call.
+
+        // TODO maybe should not reverse the iteration here, and instead check types from
+        // left to right in order to provide better error messages. eg. matching ('T 'T)
+        // with (i32 f32) will fail on the i32 (because of reverse) instead of failing on
+        // the f32.
+        for (depth, input_type) in typ.inputs.iter().rev().enumerate() {
+            let consumer = || StackConsumer::Function(function.name.clone());
+            let top_type = match self.type_stack.len().checked_sub(depth + 1) {
+                Some(index) => &self.type_stack[index],
+                None => {
+                    return Err(self.error(
+                        TypeCheckErrorKind::StackUnderflow {
+                            consumer: consumer(),
+                            inputs: typ.inputs.clone(),
+                            expected: input_type.clone(),
+                        },
+                        function.span,
+                    ))
+                }
+            };
+            let matched =
+                input_type.matches(top_type, &mut generics_map, &mut self.literals.borrow_mut());
+            if !matched {
+                if let (Type::Generic(_, Some(class)), Type::Generic(generic, _)) =
+                    (input_type, top_type)
+                {
+                    if !self.literals.borrow().in_class(top_type, *class) {
+                        return Err(self.error(
+                            TypeCheckErrorKind::UnconstrainedGeneric {
+                                function: function.name.clone(),
+                                generic: generic.clone(),
+                                class: *class,
+                            },
+                            function.span,
+                        ));
+                    }
+                }
+                let inputs: Vec<Type> =
+                    typ.inputs.iter().map(|input| input.substitute(&generics_map)).collect();
+                let expected = input_type.substitute(&generics_map);
+                return Err(self.error(
+                    TypeCheckErrorKind::TypeMismatch {
+                        consumer: consumer(),
+                        inputs: self.reported(&inputs),
+                        expected: self.reported(std::slice::from_ref(&expected)).remove(0),
+                        actual: self.reported(std::slice::from_ref(top_type)).remove(0),
+                    },
+                    function.span,
+                ));
             }
-            None => panic!("undefined function {}", function.name),
         }
+
+        // Annotate function with it's reified inputs.
+        // Can safely unwrap because it has just been set above.
+        let inputs_start = self.type_stack.len() - typ.inputs.len();
+        function.reified_type.as_mut().unwrap().inputs = self.type_stack.split_off(inputs_start);
+
+        let reified_outputs = typ
+            .outputs
+            .iter()
+            .map(|output_typ| output_typ.reify(&mut generics_map))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|kind| self.error(kind, function.span))?;
+
+        // Push the function's output to the stack
+        self.type_stack.extend(reified_outputs.to_vec());
+        // annotate function with it's reified outputs
+        function.reified_type.as_mut().unwrap().outputs = reified_outputs;
+        Ok(())
     }
 
-    fn visit_if_statement(&mut self, statement: &mut IfStatement) {
-        match self.type_stack.pop() {
-            None => panic!("Expected a bool value for if statment, but stack was empty"),
-            Some(Type::Concrete(ConcreteType::Bool)) => {}
-            Some(typ) => panic!("Expected a bool value for if statement, got {:?}", typ),
+    fn visit_if_statement(&mut self, statement: &mut IfStatement) -> TypeCheckResult {
+        let expected = Type::Concrete(ConcreteType::Bool);
+        match self.type_stack.last() {
+            None => {
+                return Err(self.error(
+                    TypeCheckErrorKind::StackUnderflow {
+                        consumer: StackConsumer::IfStatement,
+                        inputs: vec![expected.clone()],
+                        expected,
+                    },
+                    statement.span,
+                ))
+            }
+            Some(typ) if self.literals.borrow_mut().unify(typ, &expected) => {}
+            Some(typ) => {
+                return Err(self.error(
+                    TypeCheckErrorKind::TypeMismatch {
+                        consumer: StackConsumer::IfStatement,
+                        inputs: vec![expected.clone()],
+                        expected,
+                        actual: self.reported(std::slice::from_ref(typ)).remove(0),
+                    },
+                    statement.span,
+                ))
+            }
+        }
+        self.type_stack.pop();
+
+        let true_branch = self
+            .nested(self.type_stack.to_vec())
+            .walk_result(&mut statement.true_branch)?;
+        let false_branch = self
+            .nested(self.type_stack.to_vec())
+            .walk_result(&mut statement.false_branch)?;
+
+        let branches_match = true_branch.len() == false_branch.len()
+            && true_branch
+                .iter()
+                .zip(&false_branch)
+                .all(|(t, f)| self.literals.borrow_mut().unify(t, f));
+        if !branches_match {
+            return Err(self.error(
+                TypeCheckErrorKind::BranchMismatch {
+                    true_branch: self.reported(&true_branch),
+                    false_branch: self.reported(&false_branch),
+                },
+                statement.span,
+            ));
         }
-        let true_branch = CodeBlockTypeChecker::new(self.type_stack.to_vec(), self.function_map)
-            .walk(&mut statement.true_branch);
-        let false_branch = CodeBlockTypeChecker::new(self.type_stack.to_vec(), self.function_map)
-            .walk(&mut statement.false_branch);
-
-        assert!(
-            true_branch == false_branch,
-            "If branches should have identical stack effects"
-        );
 
-        // We only need to append the true branch's stack output, as both branches are asserted to
+        // We only need to append the true branch's stack output, as both branches are checked to
         // have identical stack effects above
         self.type_stack = true_branch;
+        Ok(())
     }
 
-    fn visit_while_statement(&mut self, statement: &mut WhileStatement) {
-        let condition_block_result =
-            CodeBlockTypeChecker::new(self.type_stack.to_vec(), self.function_map)
-                .walk(&mut statement.condition);
+    fn visit_while_statement(&mut self, statement: &mut WhileStatement) -> TypeCheckResult {
+        let condition_block_result = self
+            .nested(self.type_stack.to_vec())
+            .walk_result(&mut statement.condition)?;
         let (effect_in, effect_out) =
-            Self::get_stack_effect(&self.type_stack, &condition_block_result);
-
-        assert!(
-            effect_in.is_empty(),
-            "expected while condition to not consume anything on the stack, instead it consumed {:?}",
-            effect_in
-        );
-        assert!(
-            effect_out == [Type::Concrete(ConcreteType::Bool)],
-            "expected while condition to produce a bool, instead it produced {:?}",
-            effect_out
-        );
-
-        let body_result = CodeBlockTypeChecker::new(self.type_stack.to_vec(), self.function_map)
-            .walk(&mut statement.body);
-        let (effect_in, effect_out) = Self::get_stack_effect(&self.type_stack, &body_result);
+            self.get_stack_effect(&self.type_stack, &condition_block_result);
+
+        let bool_type = Type::Concrete(ConcreteType::Bool);
+        let produces_bool =
+            matches!(effect_out, [typ] if self.literals.borrow_mut().unify(typ, &bool_type));
+        if !effect_in.is_empty() || !produces_bool {
+            return Err(self.error(
+                TypeCheckErrorKind::LoopEffect {
+                    part: LoopPart::Condition,
+                    consumed: self.reported(effect_in),
+                    produced: self.reported(effect_out),
+                },
+                statement.span,
+            ));
+        }
 
-        assert!(
-            effect_in.is_empty(),
-            "While body consumes {:?}, it should not consume anything",
-            effect_in
-        );
-        assert!(
-            effect_out.is_empty(),
-            "While body produces {:?}, it should not produce anything",
-            effect_out
-        );
+        let body_result = self
+            .nested(self.type_stack.to_vec())
+            .walk_result(&mut statement.body)?;
+        let (effect_in, effect_out) = self.get_stack_effect(&self.type_stack, &body_result);
+
+        if !effect_in.is_empty() || !effect_out.is_empty() {
+            return Err(self.error(
+                TypeCheckErrorKind::LoopEffect {
+                    part: LoopPart::Body,
+                    consumed: self.reported(effect_in),
+                    produced: self.reported(effect_out),
+                },
+                statement.body.span,
+            ));
+        }
+        Ok(())
     }
 
     fn finalize(self) -> Self::FinalOutput {
@@ -284,38 +735,138 @@ impl CodeBlockVisitor for CodeBlockTypeChecker<'_> {
     }
 }
 
+impl ResultCodeBlockvisitor for CodeBlockTypeChecker<'_> {
+    type ErrorType = TypeCheckError;
+}
+
+/// Typechecks every function implementation and record in a module. Each item is checked
+/// independently, so all items with errors are reported rather than just the first.
 pub struct ModuleTypeChecker<'a> {
-    functions: &'a HashMap<String, FunctionType>,
+    types: &'a ProgramTypes,
+    errors: Vec<TypeCheckError>,
 }
 
 impl<'a> ModuleTypeChecker<'a> {
-    pub fn new(functions: &'a HashMap<String, FunctionType>) -> Self {
-        Self { functions }
+    pub fn new(types: &'a ProgramTypes) -> Self {
+        Self {
+            types,
+            errors: Vec::new(),
+        }
+    }
+
+    fn check_signature(&self, function: &FunctionType, span: Span) -> TypeCheckResult {
+        for typ in function.inputs.iter().chain(&function.outputs) {
+            typ.check_defined(&self.types.records)
+                .map_err(|kind| TypeCheckError::new(kind, span))?;
+        }
+        Ok(())
+    }
+
+    fn check_impl(&self, function: &mut FunctionImpl) -> TypeCheckResult {
+        self.check_signature(&function.head.typ, function.head.span)?;
+        let typ = function.head.typ.constrained();
+        let return_stack = CodeBlockTypeChecker::new(typ.inputs.to_vec(), self.types)
+            .in_function(&typ)
+            .check(&mut function.body, Some(&typ.outputs))?;
+
+        if return_stack != typ.outputs {
+            return Err(TypeCheckError::with_stack(
+                TypeCheckErrorKind::WrongReturnStack {
+                    function: function.head.name.clone(),
+                    expected: typ.outputs,
+                    actual: return_stack.clone(),
+                },
+                function.head.span,
+                return_stack,
+            ));
+        }
+        Ok(())
+    }
+
+    // Fields cannot be generic, since a record's layout must be known. A record also cannot hold
+    // a value of itself, which would make it infinitely large, but it can hold a pointer to one.
+    fn check_record(&self, record: &Record) -> TypeCheckResult {
+        for field in &record.fields {
+            if field.typ.has_generic() {
+                return Err(TypeCheckError::new(
+                    TypeCheckErrorKind::GenericField {
+                        record: record.name.clone(),
+                        field: field.name.clone(),
+                    },
+                    field.span,
+                ));
+            }
+            field
+                .typ
+                .check_defined(&self.types.records)
+                .map_err(|kind| TypeCheckError::new(kind, field.span))?;
+        }
+        if self.contains_record(&record.name, &record.name, &mut HashSet::new()) {
+            return Err(TypeCheckError::new(
+                TypeCheckErrorKind::RecursiveRecord(record.name.clone()),
+                record.name_span,
+            ));
+        }
+        Ok(())
+    }
+
+    // Whether a value of the record `outer` holds a value of the record `inner`, either as one of
+    // its fields or within one of them
+    fn contains_record<'r>(
+        &'r self,
+        outer: &'r str,
+        inner: &str,
+        visited: &mut HashSet<&'r str>,
+    ) -> bool {
+        if !visited.insert(outer) {
+            return false;
+        }
+        let fields = self.types.records.get(outer).into_iter().flatten();
+        for field in fields {
+            if let Type::Record(name) = &field.typ {
+                if name == inner || self.contains_record(name, inner, visited) {
+                    return true;
+                }
+            }
+        }
+        false
     }
 }
 
 impl ModuleVisitor for ModuleTypeChecker<'_> {
     type ItemResult = ();
-    type FinalOutput = ();
+    type FinalOutput = Result<(), Vec<TypeCheckError>>;
 
-    fn visit_decl(&mut self, _: &mut FunctionDecl) {}
+    fn visit_decl(&mut self, function: &mut FunctionDecl) {
+        if let Err(error) = self.check_signature(&function.head.typ, function.head.span) {
+            self.errors.push(error);
+        }
+    }
 
     fn visit_impl(&mut self, function: &mut FunctionImpl) {
-        let return_stack =
-            CodeBlockTypeChecker::new(function.head.typ.inputs.to_vec(), self.functions)
-                .walk(&mut function.body);
-
-        assert!(
-            return_stack == function.head.typ.outputs,
-            "Expected function to leave {:?} on the stack, instead it left {:?}",
-            function.head.typ.outputs,
-            return_stack
-        );
+        if let Err(error) = self.check_impl(function) {
+            self.errors.push(error);
+        }
     }
 
-    fn finalize(self) {}
-}
+    // Constants are checked as they are evaluated, by FunctionMapBuilder
+    fn visit_const(&mut self, _: &mut Const) {}
+
+    fn visit_macro(&mut self, _: &mut Macro) {}
+
+    fn visit_record(&mut self, record: &mut Record) {
+        if let Err(error) = self.check_record(record) {
+            self.errors.push(error);
+        }
+    }
 
-// impl ResultModuleVisitor for ModuleTypeChecker<'_> {
-//     type ErrorType = ();
-// }
+    fn visit_import(&mut self, _: &mut Import) {}
+
+    fn finalize(self) -> Result<(), Vec<TypeCheckError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
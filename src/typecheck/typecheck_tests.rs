@@ -1,17 +1,55 @@
-    use crate::parser::module;
+    use crate::ast::{FileId, TopLevelItem, TypeClass, Word};
+    use crate::parser::{module, Input};
 
     use super::*;
+    use crate::ast::visitor::{ModuleVisitor, ResultModuleVisitor};
 
-    // TODO typechecker should return result with error types, so we can make sure #[should_panic]
-    // panics with the right error message
-
-    fn typecheck(input: &str) {
+    /// Typechecks `input` after the standard library, returning the checked module
+    fn check_module(input: &str) -> (Result<(), Vec<TypeCheckError>>, Vec<TopLevelItem>) {
         let mut program = String::from(include_str!("../../std.f"));
         program.push_str(input);
 
-        let mut module = module(&program).unwrap().1;
-        let functions = FunctionMapBuilder::new().walk(&mut module);
-        ModuleTypeChecker::new(&functions).walk(&mut module);
+        let mut module = module(Input::new_extra(&program, FileId::default()))
+            .unwrap()
+            .1;
+        let result = match FunctionMapBuilder::new().walk_result(&mut module) {
+            Ok(types) => ModuleTypeChecker::new(&types).walk(&mut module),
+            Err(e) => Err(vec![e]),
+        };
+        (result, module)
+    }
+
+    fn check_all(input: &str) -> Result<(), Vec<TypeCheckError>> {
+        check_module(input).0
+    }
+
+    fn check(input: &str) -> Result<(), TypeCheckError> {
+        check_all(input).map_err(|mut errors| errors.remove(0))
+    }
+
+    fn typecheck(input: &str) {
+        if let Err(e) = check(input) {
+            panic!("{}", e);
+        }
+    }
+
+    fn typecheck_err(input: &str) -> TypeCheckErrorKind {
+        match check(input) {
+            Ok(()) => panic!("expected a type error"),
+            Err(e) => e.kind,
+        }
+    }
+
+    fn i() -> Type {
+        Type::Concrete(ConcreteType::I32)
+    }
+
+    fn f() -> Type {
+        Type::Concrete(ConcreteType::F32)
+    }
+
+    fn b() -> Type {
+        Type::Concrete(ConcreteType::Bool)
     }
 
     #[test]
@@ -22,26 +60,40 @@
     #[test]
     fn test_return() {
         typecheck("a -> i : 1 ;");
-        typecheck("b -> i i i : 1 2 3 ;");
-        typecheck("c -> i f : 1 1.0 ;");
+        typecheck("g -> i i i : 1 2 3 ;");
+        typecheck("h -> i f : 1 1.0 ;");
     }
 
     #[test]
-    #[should_panic]
     fn test_bad_return() {
-        typecheck("a -> i : 1.0 ;");
+        assert_eq!(
+            typecheck_err("a -> i : 1.0 ;"),
+            TypeCheckErrorKind::WrongReturnStack {
+                function: "a".into(),
+                expected: vec![i()],
+                actual: vec![f()],
+            }
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_extra_return() {
-        typecheck("a -> i : 1 1 ;");
+        assert!(matches!(
+            typecheck_err("a -> i : 1 1 ;"),
+            TypeCheckErrorKind::WrongReturnStack { .. }
+        ));
     }
 
     #[test]
-    #[should_panic]
     fn test_not_enough_return() {
-        typecheck("a -> f i : i ;");
+        assert_eq!(
+            typecheck_err("a -> f i : i ;"),
+            TypeCheckErrorKind::UndefinedFunction("i".into())
+        );
+        assert!(matches!(
+            typecheck_err("a -> f i : 1 ;"),
+            TypeCheckErrorKind::WrongReturnStack { .. }
+        ));
     }
 
     #[test]
@@ -52,16 +104,16 @@
     #[test]
     fn test_input_output() {
         typecheck("a i -> i : 1 + ;");
-        typecheck("b f -> f : ;");
-        typecheck("c i f b -> i f b : ;");
+        typecheck("g f -> f : ;");
+        typecheck("h i f b -> i f b : ;");
     }
 
     #[test]
     fn test_drop() {
         typecheck("a : 3 drop ;");
-        typecheck("c : 1.0 drop ;");
-        typecheck("b : t drop ;");
-        typecheck("d : 3 1.0 drop drop ;");
+        typecheck("h : 1.0 drop ;");
+        typecheck("g : true drop ;");
+        typecheck("j : 3 1.0 drop drop ;");
         typecheck("e i i -> i : drop ;");
     }
 
@@ -81,10 +133,8 @@
             "
         nop 'T -> 'T;
         foo 'T 'U 'V -> 'V 'T 'U;
-        deref *'T -> 'T;
-        ref 'T -> *'T;
         
-        test1 -> i *f b : 1 nop 1.0 ref t ref deref ;
+        test1 -> i *f b : 1 nop 1.0 ref true ref deref ;
         test2 f *i -> *f i : deref swap ref swap ;
         test3 i -> **i : ref deref ref ref ;
         test4 ***b -> b : deref deref deref ;
@@ -96,23 +146,377 @@
     }
 
     #[test]
-    #[should_panic]
+    fn test_type_classes() {
+        let add = "add 'T:num 'T:num -> 'T; shl 'T:int 'T:int -> 'T;";
+        typecheck(&format!("{} a -> f : 1.0 2.0 add ;", add));
+        typecheck(&format!("{} a -> uq : 1uq 2uq add ;", add));
+        typecheck(&format!("{} a -> c : 1 2c add 1 shl ;", add));
+        assert_eq!(
+            typecheck_err(&format!("{} a : true false add drop ;", add)),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::Function("add".into()),
+                inputs: vec![
+                    Type::Generic("T".into(), Some(TypeClass::Num)),
+                    Type::Generic("T".into(), Some(TypeClass::Num)),
+                ],
+                expected: Type::Generic("T".into(), Some(TypeClass::Num)),
+                actual: b(),
+            }
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} a : 1.0 1.0 shl drop ;", add)),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::Function("shl".into()),
+                inputs: vec![
+                    Type::Generic("T".into(), Some(TypeClass::Int)),
+                    Type::Generic("T".into(), Some(TypeClass::Int)),
+                ],
+                expected: Type::Generic("T".into(), Some(TypeClass::Int)),
+                actual: f(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_type_args() {
+        let decls = "pair 'T:num 'U -> 'U 'T;";
+        typecheck(&format!("{} a -> *i : alloca<i> dup 1 store ;", decls));
+        typecheck(&format!("{} a -> **q : alloca<**q> deref ;", decls));
+        typecheck(&format!("{} a -> b f : 1.0 true pair<f,b> ;", decls));
+        typecheck(&format!("{} a -> b c : 1 true pair<c,b> ;", decls));
+
+        let generics = vec![
+            Type::Generic("T".into(), Some(TypeClass::Num)),
+            Type::Generic("U".into(), None),
+        ];
+        assert_eq!(
+            typecheck_err(&format!("{} a : true 1.0 pair<b,f> drop drop ;", decls)),
+            TypeCheckErrorKind::BadTypeArguments {
+                function: "pair".into(),
+                generics: generics.clone(),
+                type_args: vec![b(), f()],
+            }
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} a : 1 1 pair<i> drop drop ;", decls)),
+            TypeCheckErrorKind::BadTypeArguments {
+                function: "pair".into(),
+                generics,
+                type_args: vec![i()],
+            }
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} a : 1.0 true pair<i,b> drop drop ;", decls)),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::Function("pair".into()),
+                inputs: vec![i(), b()],
+                expected: i(),
+                actual: f(),
+            }
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} a : alloca drop ;", decls)),
+            TypeCheckErrorKind::UndefinedGeneric("T".into())
+        );
+    }
+
+    #[test]
     fn test_undef_generic() {
         // NOTE: undefined generics ('Q) only get caught when the generic is reified/monomorphized
-        typecheck("a i 'T 'U -> 'U 'Q 'T b; test : 1 t f drop drop drop drop ;");
+        assert_eq!(
+            typecheck_err("a i 'T 'U -> 'U 'Q 'T b; test : 1 true false a drop drop drop drop ;"),
+            TypeCheckErrorKind::UndefinedGeneric("Q".into())
+        );
+    }
+
+    #[test]
+    fn test_generic_calls() {
+        typecheck("add 'T:num 'T:num -> 'T:num : + ;");
+        typecheck("id 'T -> 'T : dup drop ;");
+        typecheck("get *'T -> 'T : deref ;");
+        typecheck("sum3 'T:num 'T 'T -> 'T : + + ;");
+        typecheck("shift 'T:int 'T:int -> 'T : + dup shl ; shl 'U:int 'U:int -> 'U;");
+        assert_eq!(
+            typecheck_err("add 'T 'T -> 'T : + ;"),
+            TypeCheckErrorKind::UnconstrainedGeneric {
+                function: "+".into(),
+                generic: "T".into(),
+                class: TypeClass::Num,
+            }
+        );
+        assert_eq!(
+            typecheck_err("shift 'T:num 'T:num -> 'T : >> ;"),
+            TypeCheckErrorKind::UnconstrainedGeneric {
+                function: ">>".into(),
+                generic: "T".into(),
+                class: TypeClass::Int,
+            }
+        );
+        assert!(matches!(
+            typecheck_err("mix 'T:num 'U:num -> 'T : + ;"),
+            TypeCheckErrorKind::TypeMismatch { .. }
+        ));
+        assert!(matches!(
+            typecheck_err("one 'T:num -> 'T : 1 + ;"),
+            TypeCheckErrorKind::TypeMismatch { .. }
+        ));
+        assert!(matches!(
+            typecheck_err("sq 'T:num -> i : dup * ;"),
+            TypeCheckErrorKind::WrongReturnStack { .. }
+        ));
+    }
+
+    #[test]
+    fn test_casts() {
+        typecheck("a -> i f uc : 1.5 (i) true (f) 300 (uc) ;");
+        typecheck("a -> *i q *c : 0x1000 (*i) \"s\" (q) \"s\" (*i) (*c) ;");
+
+        let pointer = Type::Pointer(Box::new(i()));
+        for (source, from, to) in [
+            ("1.0 (*i)", f(), pointer.clone()),
+            ("1 (*i) (d)", pointer.clone(), Type::Concrete(ConcreteType::F64)),
+            ("1 (b)", i(), b()),
+            ("true (*i)", b(), pointer),
+        ] {
+            assert_eq!(
+                typecheck_err(&format!("a : {} drop ;", source)),
+                TypeCheckErrorKind::BadCast { from, to },
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_generic_casts() {
+        typecheck("cst *i -> *'T : (*'T) ;");
+        typecheck("addr *'T -> q : (q) ;");
+        typecheck("to-int 'T:num -> i : (i) ;");
+        typecheck("offset 'T:num i -> 'T : ('T) + ;");
+        typecheck("same 'T -> 'T : ('T) ;");
+        assert_eq!(
+            typecheck_err("a : 1 (*i) (*'T) drop ;"),
+            TypeCheckErrorKind::UnboundGeneric("T".into())
+        );
+        assert_eq!(
+            typecheck_err("to-int 'T -> i : (i) ;"),
+            TypeCheckErrorKind::BadCast {
+                from: Type::Generic("T".into(), None),
+                to: i(),
+            }
+        );
+        assert!(matches!(
+            typecheck_err("to-int 'T:num -> *i : (*i) ;"),
+            TypeCheckErrorKind::BadCast { .. }
+        ));
+    }
+
+    #[test]
+    fn test_records() {
+        let point = "record point : x i , y f , ;";
+        typecheck(&format!(
+            "{} a -> point i : construct<point> 1 >point.x .point.x ;",
+            point
+        ));
+        typecheck(&format!(
+            "{} a point -> f point : .point.y swap 2.0 >point.y (point) ;",
+            point
+        ));
+        // Records can be used before they are defined, and may hold a pointer to themselves
+        typecheck(&format!(
+            "a -> *i : alloca<line> deref .line.next swap drop (*i) ;
+            record line : start point , next *line , ; {}",
+            point
+        ));
+
+        let point_type = Type::Record("point".into());
+        assert_eq!(
+            typecheck_err(&format!("{} a : construct<point> 1 >point.y drop ;", point)),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::Function(">point.y".into()),
+                inputs: vec![point_type.clone(), f()],
+                expected: f(),
+                actual: i(),
+            }
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} a : 1 (point) drop ;", point)),
+            TypeCheckErrorKind::BadCast {
+                from: i(),
+                to: point_type,
+            }
+        );
+        assert_eq!(
+            typecheck_err("a : construct<i> drop ;"),
+            TypeCheckErrorKind::BadTypeArguments {
+                function: "construct".into(),
+                generics: vec![Type::Generic("T".into(), Some(TypeClass::Record))],
+                type_args: vec![i()],
+            }
+        );
+        for source in ["a shape -> : drop ;", "a : (*shape) drop ;", "a : alloca<shape> drop ;"] {
+            assert_eq!(
+                typecheck_err(source),
+                TypeCheckErrorKind::UndefinedType("shape".into()),
+                "{}",
+                source
+            );
+        }
+
+        assert_eq!(
+            typecheck_err("record pair : a i , a i , ;"),
+            TypeCheckErrorKind::DuplicateField {
+                record: "pair".into(),
+                field: "a".into(),
+            }
+        );
+        assert_eq!(
+            typecheck_err("record pair : a *'T , ;"),
+            TypeCheckErrorKind::GenericField {
+                record: "pair".into(),
+                field: "a".into(),
+            }
+        );
+        assert_eq!(
+            typecheck_err("record node : next node , ;"),
+            TypeCheckErrorKind::RecursiveRecord("node".into())
+        );
+        assert_eq!(
+            typecheck_err("record outer : x inner , ; record inner : y outer , ;"),
+            TypeCheckErrorKind::RecursiveRecord("outer".into())
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} {}", point, point)),
+            TypeCheckErrorKind::RecordRedeclaration("point".into())
+        );
+        assert_eq!(
+            typecheck_err(&format!("{} .point.x point -> point i : 1 ;", point)),
+            TypeCheckErrorKind::Reimplementation(".point.x".into())
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_bad_deref() {
-        typecheck("deref *'T -> 'T; test : 1 deref ;");
+        assert_eq!(
+            typecheck_err("test : 1 deref ;"),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::Function("deref".into()),
+                inputs: vec![Type::Pointer(Box::new(Type::Generic("T".into(), None)))],
+                expected: Type::Pointer(Box::new(Type::Generic("T".into(), None))),
+                actual: i(),
+            }
+        );
     }
 
     #[test]
     fn test_literals() {
         typecheck("a -> i : 1 ;");
-        typecheck("b -> f : 1.0 ;");
-        typecheck("c -> b : t ;");
+        typecheck("g -> f : 1.0 ;");
+        typecheck("h -> b : true ;");
+        typecheck("j -> *c : \"str\\n\" ;");
+        typecheck("e -> c q uc d : 'a' 10q 0xFFuc 1.5d ;");
+    }
+
+    #[test]
+    fn test_literal_inference() {
+        typecheck("a -> q : 1 ;");
+        typecheck("addq q q -> q; g -> q : 1 2 addq ;");
+        typecheck("h -> uc : true ? 1 : 2 ; ;");
+        typecheck("j q -> q : true ? drop 1 : ; ;");
+        typecheck("e q -> q : @ true : drop 1 ; ;");
+        typecheck("k -> c : 1 dup drop ;");
+
+        // A literal whose use does not decide its type is an `i`
+        typecheck("g -> i : 1 ;");
+        typecheck("let n 5; h -> i : n ;");
+        assert_eq!(
+            typecheck_err("let n 5; h -> q : n ;"),
+            TypeCheckErrorKind::WrongReturnStack {
+                function: "h".into(),
+                expected: vec![Type::Concrete(ConcreteType::I64)],
+                actual: vec![i()],
+            }
+        );
+
+        // Literals are never bools or floats
+        assert_eq!(
+            typecheck_err("a -> b : 1 ;"),
+            TypeCheckErrorKind::WrongReturnStack {
+                function: "a".into(),
+                expected: vec![b()],
+                actual: vec![i()],
+            }
+        );
+        assert!(matches!(
+            typecheck_err("a f -> ; g : 1 a ;"),
+            TypeCheckErrorKind::TypeMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_literal_range() {
+        typecheck("a -> c c uc : -128 127 255 ;");
+        typecheck("g -> q q uq : -9223372036854775808 9223372036854775807 18446744073709551615 ;");
+        typecheck("h -> i : -2147483648 ;");
+        let out_of_range = |value, typ| TypeCheckErrorKind::LiteralOutOfRange { value, typ };
+        assert_eq!(
+            typecheck_err("a -> c : 128 ;"),
+            out_of_range(128, ConcreteType::I8)
+        );
+        assert_eq!(
+            typecheck_err("a : -1uc drop ;"),
+            out_of_range(-1, ConcreteType::U8)
+        );
+        assert_eq!(
+            typecheck_err("a : 2147483648 drop ;"),
+            out_of_range(2147483648, ConcreteType::I32)
+        );
+        assert_eq!(
+            typecheck_err("a -> uq : -1 ;"),
+            out_of_range(-1, ConcreteType::U64)
+        );
+        assert_eq!(
+            typecheck_err("let n 256uc;"),
+            out_of_range(256, ConcreteType::U8)
+        );
+
+        let diagnostic = check("a : 300c drop ;").unwrap_err().to_diagnostic();
+        assert_eq!(diagnostic.message, "integer literal `300` does not fit in `c`");
+        assert_eq!(diagnostic.label.unwrap(), "`c` holds values from -128 to 127");
+        assert_eq!(diagnostic.span.unwrap().len, "300c".len());
+
+        // Literals too large for a 64 bit type are still checked against their type
+        assert_eq!(
+            typecheck_err("pub main -> i : 99999999999999999999 ;"),
+            out_of_range(99999999999999999999, ConcreteType::I32)
+        );
+        assert_eq!(
+            typecheck_err("a : -18446744073709551616uq drop ;"),
+            out_of_range(-18446744073709551616, ConcreteType::U64)
+        );
+    }
+
+    #[test]
+    fn test_literal_annotation() {
+        let (result, module) = check_module("a -> q : 1 dup drop ;");
+        result.unwrap();
+        let q = Type::Concrete(ConcreteType::I64);
+        let words = match module.last() {
+            Some(TopLevelItem::Impl(function)) => &function.body.words,
+            other => panic!("expected a function implementation, got {:?}", other),
+        };
+        match &words[0] {
+            Word::IntLiteral(literal) => assert_eq!(literal.typ, Some(q.clone())),
+            other => panic!("expected an integer literal, got {:?}", other),
+        }
+        match &words[1] {
+            Word::FunctionCall(call) => {
+                let typ = call.reified_type.as_ref().unwrap();
+                assert_eq!(typ.inputs, vec![q.clone()]);
+                assert_eq!(typ.outputs, vec![q.clone(), q]);
+            }
+            other => panic!("expected a function call, got {:?}", other),
+        }
     }
 
     #[test]
@@ -123,15 +527,36 @@
     }
 
     #[test]
-    #[should_panic]
     fn test_bad_call_args() {
-        typecheck("a f -> ; b : 1 a ;");
+        assert_eq!(
+            typecheck_err("a f -> ; g : 1 a ;"),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::Function("a".into()),
+                inputs: vec![f()],
+                expected: f(),
+                actual: i(),
+            }
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_no_call_args() {
-        typecheck("a f -> ; b : a ;");
+        assert_eq!(
+            typecheck_err("a f -> ; g : a ;"),
+            TypeCheckErrorKind::StackUnderflow {
+                consumer: StackConsumer::Function("a".into()),
+                inputs: vec![f()],
+                expected: f(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_undefined_function() {
+        assert_eq!(
+            typecheck_err("a : 1 foo ;"),
+            TypeCheckErrorKind::UndefinedFunction("foo".into())
+        );
     }
 
     #[test]
@@ -140,24 +565,110 @@
     }
 
     #[test]
-    #[should_panic]
     fn test_redecl() {
-        typecheck("a; a;");
+        assert_eq!(
+            typecheck_err("a; a;"),
+            TypeCheckErrorKind::Redeclaration("a".into())
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_reimpl() {
-        typecheck("a : ; a : ;");
+        assert_eq!(
+            typecheck_err("a : ; a : ;"),
+            TypeCheckErrorKind::Reimplementation("a".into())
+        );
+    }
+
+    /// The value of the last constant in `input`
+    fn evaluate(input: &str) -> Result<ConstValue, TypeCheckErrorKind> {
+        let mut program = String::from(include_str!("../../std.f"));
+        program.push_str(input);
+
+        let mut module = module(Input::new_extra(&program, FileId::default()))
+            .unwrap()
+            .1;
+        FunctionMapBuilder::new()
+            .walk_result(&mut module)
+            .map_err(|e| e.kind)?;
+        match module.pop() {
+            Some(TopLevelItem::Const(constant)) => Ok(constant.value.unwrap()),
+            other => panic!("expected a constant, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_const() {
+        typecheck("let a 5; g -> i : a 1 + ;");
+        typecheck("let a : 1.5; let e : a (i) 2 *; h -> i : e ;");
+        // Constants are typechecked before functions, so they can be used earlier in the module
+        typecheck("g -> i : a ; let a 5;");
+
+        assert_eq!(
+            typecheck_err("let a : 1 2;"),
+            TypeCheckErrorKind::ConstValueCount {
+                name: "a".into(),
+                produced: vec![i(), i()],
+            }
+        );
+        assert_eq!(
+            typecheck_err("let a : ;"),
+            TypeCheckErrorKind::ConstValueCount {
+                name: "a".into(),
+                produced: vec![],
+            }
+        );
+        assert_eq!(
+            typecheck_err("let a 5; let a 6;"),
+            TypeCheckErrorKind::Redeclaration("a".into())
+        );
+    }
+
+    #[test]
+    fn test_const_evaluation() {
+        use crate::ast::ConcreteType::*;
+        assert_eq!(evaluate("let a 5;"), Ok(ConstValue::Int(5, I32)));
+        assert_eq!(evaluate("let a 5; let e : a a * 1 -;"), Ok(ConstValue::Int(24, I32)));
+        assert_eq!(evaluate("let a : 7 2 / 7 2 % +;"), Ok(ConstValue::Int(4, I32)));
+        assert_eq!(evaluate("let a : 1 2 swap -;"), Ok(ConstValue::Int(1, I32)));
+        assert_eq!(evaluate("let a : 2147483647 1 +;"), Ok(ConstValue::Int(-2147483648, I32)));
+        assert_eq!(evaluate("let a : -1 (uc);"), Ok(ConstValue::Int(255, U8)));
+        assert_eq!(evaluate("let a : 300 (c);"), Ok(ConstValue::Int(44, I8)));
+        assert_eq!(evaluate("let a : 2.75 (i) (d);"), Ok(ConstValue::Float(2.0, F64)));
+        assert_eq!(evaluate("let a : 3 4 <;"), Ok(ConstValue::Bool(true)));
+        assert_eq!(evaluate("let a : 3 true ? 1 + : 1 - ;;"), Ok(ConstValue::Int(4, I32)));
+        assert_eq!(
+            evaluate("let a : 1 0 @ dup 10 < : swap 2 * swap 1 + ; drop;"),
+            Ok(ConstValue::Int(1024, I32))
+        );
+
+        assert_eq!(
+            evaluate("let a : 1 0 /;"),
+            Err(TypeCheckErrorKind::ConstEvaluation("division by zero".into()))
+        );
+        assert_eq!(
+            evaluate("let a : 1 32 <<;"),
+            Err(TypeCheckErrorKind::ConstEvaluation(
+                "shift by 32 is out of range for `i`".into()
+            ))
+        );
+        assert_eq!(
+            evaluate("one -> i : 1 ; let a : one;"),
+            Err(TypeCheckErrorKind::NotConstant("one".into()))
+        );
+        assert_eq!(
+            evaluate("let s \"a\\tb\";"),
+            Err(TypeCheckErrorKind::NotConstant("\"a\\tb\"".into()))
+        );
     }
 
     #[test]
     fn test_if() {
-        typecheck("a -> : t ? : ; ;");
-        typecheck("b -> i : t ? 1 : 2 ; ;");
-        typecheck("c -> f i : t ? 1.0 1 : 2.0 2 ; ;");
-        typecheck("d -> f i : f ? 1.0 1 : 1 1.0 swap 1 + ; ;");
-        typecheck("e -> i : 1 f ? 1 + : drop 1 2 + ; ;");
+        typecheck("a -> : true ? : ; ;");
+        typecheck("g -> i : true ? 1 : 2 ; ;");
+        typecheck("h -> f i : true ? 1.0 1 : 2.0 2 ; ;");
+        typecheck("j -> f i : false ? 1.0 1 : 1 1.0 swap 1 + ; ;");
+        typecheck("e -> i : 1 false ? 1 + : drop 1 2 + ; ;");
     }
 
     #[test]
@@ -165,9 +676,9 @@
         typecheck(
             "
         a :
-            t ?
+            true ?
                 1
-                f ?
+                false ?
                     1 +
                 :
                     2 +
@@ -182,37 +693,144 @@
     }
 
     #[test]
-    #[should_panic]
     fn test_if_use_nonexistant_val() {
-        typecheck("a : t ? 1 + : drop ; ;")
+        assert!(matches!(
+            typecheck_err("a : true ? 1 + : drop ; ;"),
+            TypeCheckErrorKind::StackUnderflow { .. }
+        ));
     }
 
     #[test]
-    #[should_panic]
     fn test_if_nonequal_branches() {
-        typecheck("a : t ? 1.0 : 1 ; drop ;")
+        assert_eq!(
+            typecheck_err("a : true ? 1.0 : 1 ; drop ;"),
+            TypeCheckErrorKind::BranchMismatch {
+                true_branch: vec![f()],
+                false_branch: vec![i()],
+            }
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_if_no_bool_input() {
-        typecheck("a : 1 ? : ; ]");
+        assert_eq!(
+            typecheck_err("a : 1 ? : ; ;"),
+            TypeCheckErrorKind::TypeMismatch {
+                consumer: StackConsumer::IfStatement,
+                inputs: vec![b()],
+                expected: b(),
+                actual: i(),
+            }
+        );
+        assert_eq!(
+            typecheck_err("a : ? : ; ;"),
+            TypeCheckErrorKind::StackUnderflow {
+                consumer: StackConsumer::IfStatement,
+                inputs: vec![b()],
+                expected: b(),
+            }
+        );
     }
 
     #[test]
     fn test_while() {
-        typecheck("a : @ t : ; ;");
-        typecheck("b : 0 @ dup 10 < : 1 + ; drop ;");
+        typecheck("a : @ true : ; ;");
+        typecheck("g : 0 @ dup 10 < : 1 + ; drop ;");
     }
 
     #[test]
-    #[should_panic]
     fn test_while_not_bool() {
-        typecheck("a : @ 1 : ; ;");
+        assert_eq!(
+            typecheck_err("a : @ 1 : ; ;"),
+            TypeCheckErrorKind::LoopEffect {
+                part: LoopPart::Condition,
+                consumed: vec![],
+                produced: vec![i()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_while_condition_consumes() {
+        assert_eq!(
+            typecheck_err("a : 1 @ drop true : ; ;"),
+            TypeCheckErrorKind::LoopEffect {
+                part: LoopPart::Condition,
+                consumed: vec![i()],
+                produced: vec![b()],
+            }
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_while_body_has_stack_effect() {
-        typecheck("a : @ t : 1 ; ;");
-    }
\ No newline at end of file
+        assert_eq!(
+            typecheck_err("a : @ true : 1 ; ;"),
+            TypeCheckErrorKind::LoopEffect {
+                part: LoopPart::Body,
+                consumed: vec![],
+                produced: vec![i()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_error_span() {
+        let program_start = include_str!("../../std.f").len();
+        let error = check("a : 1.0 inc ;").unwrap_err();
+        assert_eq!(error.span.offset, program_start + 8);
+        assert_eq!(error.span.len, 3);
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let errors = check_all(
+            "
+        a -> i : 1.0 ;
+        g : 1 2 + ;
+        h : 1 foo ;
+        j : 1 drop ;
+        ",
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(
+            errors[0].kind,
+            TypeCheckErrorKind::WrongReturnStack { .. }
+        ));
+        assert!(matches!(
+            errors[1].kind,
+            TypeCheckErrorKind::WrongReturnStack { .. }
+        ));
+        assert_eq!(
+            errors[2].kind,
+            TypeCheckErrorKind::UndefinedFunction("foo".into())
+        );
+    }
+
+    #[test]
+    fn test_error_stack() {
+        let error = check("a f -> : 1 swap + drop ;").unwrap_err();
+        assert_eq!(error.stack, Some(vec![i(), f()]));
+
+        let error = check("a : 1 true ? 1.0 : 2 ; ;").unwrap_err();
+        assert_eq!(error.stack, Some(vec![i()]));
+
+        let error = check("a -> i : 1 2 ;").unwrap_err();
+        assert_eq!(error.stack, Some(vec![i(), i()]));
+
+        let error = check("a; a;").unwrap_err();
+        assert_eq!(error.stack, None);
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let diagnostic = check("a i -> : 1.0 swap + drop ;")
+            .unwrap_err()
+            .to_diagnostic();
+        assert_eq!(diagnostic.label.unwrap(), "expected `i i`, stack has `f i`");
+
+        let diagnostic = check("a -> i : 1 1.0 ;").unwrap_err().to_diagnostic();
+        assert_eq!(diagnostic.label.unwrap(), "expected `i`, stack has `i f`");
+        assert_eq!(diagnostic.span.unwrap().len, "a -> i".len());
+    }
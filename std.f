@@ -1,54 +1,100 @@
 [stack operations]
-intrinsic dup 'T -> 'T 'T;
-intrinsic dup2 'T 'U -> 'T 'U 'T 'U;
-intrinsic drop 'T -> ;
-intrinsic over 'T 'U -> 'T 'U 'T;
-intrinsic swap 'T 'U -> 'U 'T;
-intrinsic rot 'T 'U 'V -> 'U 'V 'T;
+pub intrinsic dup 'T -> 'T 'T;
+pub intrinsic dup2 'T 'U -> 'T 'U 'T 'U;
+pub intrinsic drop 'T -> ;
+pub intrinsic over 'T 'U -> 'T 'U 'T;
+pub intrinsic swap 'T 'U -> 'U 'T;
+pub intrinsic rot 'T 'U 'V -> 'U 'V 'T;
 
-[TODO fix]
-[nip 'T 'U -> 'U : swap drop ;]
-[tuck 'T 'U -> 'U 'T 'U : swap over ;]
+[macros have no signature, they work on whatever types are on the stack where they are used]
+pub macro nip : swap drop ;
+pub macro tuck : swap over ;
 
 [math]
-intrinsic + i i -> i;
-intrinsic - i i -> i;
-intrinsic * i i -> i;
-intrinsic / i i -> i;
-intrinsic % i i -> i;
-intrinsic >> i i -> i;
-intrinsic << i i -> i;
+[`'T:num` is any integer or float type, `'T:int` is any integer type]
+pub intrinsic + 'T:num 'T:num -> 'T;
+pub intrinsic - 'T:num 'T:num -> 'T;
+pub intrinsic * 'T:num 'T:num -> 'T;
+pub intrinsic / 'T:num 'T:num -> 'T;
+pub intrinsic % 'T:num 'T:num -> 'T;
+pub intrinsic >> 'T:int 'T:int -> 'T;
+pub intrinsic << 'T:int 'T:int -> 'T;
 
 [comparisons]
-intrinsic < i i -> b;
-intrinsic <= i i -> b;
-intrinsic > i i -> b;
-intrinsic >= i i -> b;
-intrinsic = i i -> b;
-
-[typecasts]
-intrinsic (i) 'T -> i;
-intrinsic (ui) 'T -> ui;
-intrinsic (q) 'T -> q;
-intrinsic (uq) 'T -> uq;
-intrinsic (c) 'T -> c;
-intrinsic (uc) 'T -> uc;
-intrinsic (f) 'T -> f;
-intrinsic (d) 'T -> d;
-
-inc i -> i : 1 + ;
-dec i -> i : 1 - ;
+pub intrinsic < 'T:num 'T:num -> b;
+pub intrinsic <= 'T:num 'T:num -> b;
+pub intrinsic > 'T:num 'T:num -> b;
+pub intrinsic >= 'T:num 'T:num -> b;
+pub intrinsic = 'T:num 'T:num -> b;
+pub intrinsic != 'T:num 'T:num -> b;
+
+[pointers]
+pub intrinsic deref *'T -> 'T;
+pub intrinsic store *'T 'T -> ;
+[copies a value to a new slot on the stack, and gives its address]
+pub intrinsic ref 'T -> *'T;
+[gives the address of a new slot on the stack for the type argument, eg. `alloca<i>` gives a `*i`]
+pub intrinsic alloca -> *'T;
+[offsets a pointer by a number of the values it points to]
+pub intrinsic ptr+ *'T i -> *'T;
+pub intrinsic ptr< *'T *'T -> b;
+pub intrinsic ptr<= *'T *'T -> b;
+pub intrinsic ptr> *'T *'T -> b;
+pub intrinsic ptr>= *'T *'T -> b;
+pub intrinsic ptr= *'T *'T -> b;
+pub intrinsic ptr!= *'T *'T -> b;
+
+[records]
+[gives a record of the type argument with every field zeroed, eg. `construct<my-struct>`]
+pub intrinsic construct -> 'T:record;
+
+pub inline inc i -> i : 1 + ;
+pub inline dec i -> i : 1 - ;
 
 [TODO this should only be linked with stdio.f]
-extern putchar i -> ;
+pub extern putchar i -> ;
 
-nl : 10 putchar ;
+pub nl : 10 putchar ;
 
-iprint i -> :
+[prints a number which is not negative]
+udigits q -> :
     dup 9 > ?
-        dup 10 / dup 10 * rot swap - swap iprint
+        dup 10 / dup 10 * rot swap - swap udigits
+    : ;
+    48 + (i) putchar
+;
+
+pub qprint q -> :
+    dup 0 < ?
+        [the last digit is split off first, since negating the smallest `q` overflows]
+        45 putchar
+        dup -10 / dup 0 > ? udigits : drop ;
+        -10 % 0 swap -
     : ;
-    48 + putchar
+    udigits
 ;
 
-iprintln i-> : iprint nl ;
+pub iprint i -> : (q) qprint ;
+
+[prints the first n digits after the point of a number from 0 to 1]
+fdigits d i -> :
+    @ dup 0 > :
+        1 - swap 10.0d * dup (q) dup 48 + (i) putchar (d) - swap
+    ;
+    drop drop
+;
+
+[prints a number with 6 digits after the point, rounded towards zero. The part before the point
+ must fit in a `q`]
+pub dprint d -> :
+    dup 0.0d < ? 45 putchar 0.0d swap - : ;
+    dup (q) dup qprint (d) -
+    46 putchar 6 fdigits
+;
+
+pub fprint f -> : (d) dprint ;
+
+pub iprintln i-> : iprint nl ;
+pub qprintln q -> : qprint nl ;
+pub fprintln f -> : fprint nl ;
+pub dprintln d -> : dprint nl ;